@@ -0,0 +1,62 @@
+/* Runs a ROM for a number of frames and prints the screen as text.
+ *
+ *   cargo build -p rip_8_ffi --release
+ *   cc -Iffi/include ffi/examples/run.c target/release/librip8.a -lpthread -ldl -lm -o run
+ *   ./run pong2.c8 120
+ */
+#include <stdio.h>
+#include <stdlib.h>
+
+#include "rip8.h"
+
+int main(int argc, char **argv) {
+    if (argc < 2) {
+        fprintf(stderr, "usage: %s ROM [FRAMES]\n", argv[0]);
+        return 1;
+    }
+    FILE *file = fopen(argv[1], "rb");
+    if (!file) {
+        perror(argv[1]);
+        return 1;
+    }
+    uint8_t rom[0xE00];
+    size_t len = fread(rom, 1, sizeof rom, file);
+    fclose(file);
+    int frames = argc > 2 ? atoi(argv[2]) : 60;
+
+    Rip8Machine *machine = rip8_create(1);
+    rip8_set_speed(machine, 0);
+    if (!rip8_load_rom(machine, rom, len)) {
+        fprintf(stderr, "could not load %s\n", argv[1]);
+        return 1;
+    }
+
+    size_t size = rip8_snapshot(machine, NULL, 0);
+    uint8_t *snapshot = malloc(size);
+    if (!snapshot) {
+        perror("malloc");
+        return 1;
+    }
+    /* Taken up front as well, so there is always something to restore. */
+    rip8_snapshot(machine, snapshot, size);
+    for (int frame = 0; frame < frames; frame++) {
+        if (frame == frames / 2) {
+            rip8_snapshot(machine, snapshot, size);
+        }
+        rip8_run_cycles(machine, 10);
+        rip8_tick_timers(machine);
+    }
+    printf("snapshot of %zu bytes restored: %s\n", size, rip8_restore(machine, snapshot, size) ? "yes" : "no");
+    free(snapshot);
+    rip8_run_cycles(machine, 10 * (frames - frames / 2));
+
+    const uint8_t *screen = rip8_framebuffer(machine);
+    for (uint32_t y = 0; y < rip8_framebuffer_height(); y++) {
+        for (uint32_t x = 0; x < rip8_framebuffer_width(); x++) {
+            putchar(screen[y * rip8_framebuffer_width() + x] ? '#' : '.');
+        }
+        putchar('\n');
+    }
+    rip8_destroy(machine);
+    return 0;
+}
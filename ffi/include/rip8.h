@@ -0,0 +1,92 @@
+#ifndef RIP8_H
+#define RIP8_H
+
+/* Generated by cbindgen from ffi/src/lib.rs; do not edit. Regenerate with
+ * `cbindgen --config ffi/cbindgen.toml --output ffi/include/rip8.h ffi`. */
+
+#include <stdbool.h>
+#include <stddef.h>
+#include <stdint.h>
+
+#define RIP8_WIDTH 64
+
+#define RIP8_HEIGHT 32
+
+typedef struct Rip8Machine Rip8Machine;
+
+#ifdef __cplusplus
+extern "C" {
+#endif // __cplusplus
+
+/**
+ * Creates a machine with no program loaded. Release it with rip8_destroy.
+ */
+struct Rip8Machine *rip8_create(uint64_t seed);
+
+void rip8_destroy(struct Rip8Machine *handle);
+
+/**
+ * Resets the machine and loads a program at 0x200; quirks and speed are kept.
+ * Returns false if the program does not fit in memory.
+ */
+bool rip8_load_rom(struct Rip8Machine *handle, const uint8_t *data, size_t len);
+
+/**
+ * Selects a quirk profile: "default", "cosmac", "schip" or "xo-chip".
+ */
+bool rip8_set_quirks(struct Rip8Machine *handle, const char *profile);
+
+/**
+ * Instructions per timer tick. 0 stops rip8_run_cycles from ticking the timers,
+ * leaving them to rip8_tick_timers.
+ */
+void rip8_set_speed(struct Rip8Machine *handle, uint8_t cycles_per_frame);
+
+/**
+ * Runs up to `cycles` instructions. Returns true once the machine has halted.
+ */
+bool rip8_run_cycles(struct Rip8Machine *handle, uint32_t cycles);
+
+/**
+ * Decrements the delay and sound timers once, as a 60 Hz tick would.
+ */
+void rip8_tick_timers(struct Rip8Machine *handle);
+
+/**
+ * RIP8_WIDTH * RIP8_HEIGHT bytes, row-major, 1 for a lit pixel. The pointer stays
+ * valid until the next call that modifies the machine.
+ */
+const uint8_t *rip8_framebuffer(const struct Rip8Machine *handle);
+
+uint32_t rip8_framebuffer_width(void);
+
+uint32_t rip8_framebuffer_height(void);
+
+/**
+ * Whether the screen changed since the last call.
+ */
+bool rip8_take_redraw(struct Rip8Machine *handle);
+
+/**
+ * Presses or releases one of the 16 keys; other key numbers are ignored.
+ */
+void rip8_set_key(struct Rip8Machine *handle, uint8_t key, bool pressed);
+
+bool rip8_sound_playing(const struct Rip8Machine *handle);
+
+/**
+ * Writes a snapshot into `buffer` if `capacity` is large enough and returns its size
+ * either way, so a first call with a null buffer can size the allocation.
+ */
+size_t rip8_snapshot(const struct Rip8Machine *handle, uint8_t *buffer, size_t capacity);
+
+/**
+ * Restores a snapshot; the machine is left untouched and false returned if it is invalid.
+ */
+bool rip8_restore(struct Rip8Machine *handle, const uint8_t *data, size_t len);
+
+#ifdef __cplusplus
+} // extern "C"
+#endif // __cplusplus
+
+#endif /* RIP8_H */
@@ -0,0 +1,247 @@
+// C entry points around Machine. Every function accepts a null handle and then does nothing.
+#![allow(clippy::missing_safety_doc)]
+
+use std::ffi::CStr;
+use std::os::raw::c_char;
+use std::slice;
+
+use rip_8::quirks::Quirks;
+use rip_8::Machine;
+
+pub const RIP8_WIDTH: u32 = 64;
+pub const RIP8_HEIGHT: u32 = 32;
+
+// Opaque to C; only ever handled through a pointer from rip8_create.
+pub struct Rip8Machine {
+    machine: Machine,
+    seed: u64,
+}
+
+/// Creates a machine with no program loaded. Release it with rip8_destroy.
+#[no_mangle]
+pub extern "C" fn rip8_create(seed: u64) -> *mut Rip8Machine {
+    let mut machine = Machine::new();
+    machine.seed(seed);
+    Box::into_raw(Box::new(Rip8Machine { machine, seed }))
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn rip8_destroy(handle: *mut Rip8Machine) {
+    if !handle.is_null() {
+        drop(Box::from_raw(handle));
+    }
+}
+
+/// Resets the machine and loads a program at 0x200; quirks and speed are kept.
+/// Returns false if the program does not fit in memory.
+#[no_mangle]
+pub unsafe extern "C" fn rip8_load_rom(handle: *mut Rip8Machine, data: *const u8, len: usize) -> bool {
+    let handle = match handle.as_mut() {
+        Some(handle) => handle,
+        None => return false,
+    };
+    if data.is_null() || len > 0x1000 - 0x200 {
+        return false;
+    }
+    let mut machine = Machine::new();
+    machine.seed(handle.seed);
+    machine.set_quirks(handle.machine.quirks());
+    machine.set_speed(handle.machine.speed());
+    machine.load_program(slice::from_raw_parts(data, len));
+    handle.machine = machine;
+    true
+}
+
+/// Selects a quirk profile: "default", "cosmac", "schip" or "xo-chip".
+#[no_mangle]
+pub unsafe extern "C" fn rip8_set_quirks(handle: *mut Rip8Machine, profile: *const c_char) -> bool {
+    match (handle.as_mut(), profile.is_null()) {
+        (Some(handle), false) => match CStr::from_ptr(profile).to_str().ok().and_then(Quirks::profile) {
+            Some(quirks) => {
+                handle.machine.set_quirks(quirks);
+                true
+            }
+            None => false,
+        },
+        _ => false,
+    }
+}
+
+/// Instructions per timer tick. 0 stops rip8_run_cycles from ticking the timers,
+/// leaving them to rip8_tick_timers.
+#[no_mangle]
+pub unsafe extern "C" fn rip8_set_speed(handle: *mut Rip8Machine, cycles_per_frame: u8) {
+    if let Some(handle) = handle.as_mut() {
+        handle.machine.set_speed(cycles_per_frame);
+    }
+}
+
+/// Runs up to `cycles` instructions. Returns true once the machine has halted.
+#[no_mangle]
+pub unsafe extern "C" fn rip8_run_cycles(handle: *mut Rip8Machine, cycles: u32) -> bool {
+    let handle = match handle.as_mut() {
+        Some(handle) => handle,
+        None => return true,
+    };
+    for _ in 0..cycles {
+        if handle.machine.cycle() {
+            return true;
+        }
+    }
+    false
+}
+
+/// Decrements the delay and sound timers once, as a 60 Hz tick would.
+#[no_mangle]
+pub unsafe extern "C" fn rip8_tick_timers(handle: *mut Rip8Machine) {
+    if let Some(handle) = handle.as_mut() {
+        handle.machine.tick_timers();
+    }
+}
+
+/// RIP8_WIDTH * RIP8_HEIGHT bytes, row-major, 1 for a lit pixel. The pointer stays
+/// valid until the next call that modifies the machine.
+#[no_mangle]
+pub unsafe extern "C" fn rip8_framebuffer(handle: *const Rip8Machine) -> *const u8 {
+    match handle.as_ref() {
+        Some(handle) => handle.machine.screen().as_ptr() as *const u8,
+        None => std::ptr::null(),
+    }
+}
+
+#[no_mangle]
+pub extern "C" fn rip8_framebuffer_width() -> u32 {
+    RIP8_WIDTH
+}
+
+#[no_mangle]
+pub extern "C" fn rip8_framebuffer_height() -> u32 {
+    RIP8_HEIGHT
+}
+
+/// Whether the screen changed since the last call.
+#[no_mangle]
+pub unsafe extern "C" fn rip8_take_redraw(handle: *mut Rip8Machine) -> bool {
+    match handle.as_mut() {
+        Some(handle) => {
+            let redraw = handle.machine.needs_redraw();
+            handle.machine.draw_complete();
+            redraw
+        }
+        None => false,
+    }
+}
+
+/// Presses or releases one of the 16 keys; other key numbers are ignored.
+#[no_mangle]
+pub unsafe extern "C" fn rip8_set_key(handle: *mut Rip8Machine, key: u8, pressed: bool) {
+    if let (Some(handle), true) = (handle.as_mut(), key < 16) {
+        if pressed {
+            handle.machine.key_pressed(key as usize);
+        } else {
+            handle.machine.key_released(key as usize);
+        }
+    }
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn rip8_sound_playing(handle: *const Rip8Machine) -> bool {
+    handle.as_ref().is_some_and(|handle| handle.machine.sound_playing())
+}
+
+/// Writes a snapshot into `buffer` if `capacity` is large enough and returns its size
+/// either way, so a first call with a null buffer can size the allocation.
+#[no_mangle]
+pub unsafe extern "C" fn rip8_snapshot(handle: *const Rip8Machine, buffer: *mut u8, capacity: usize) -> usize {
+    let handle = match handle.as_ref() {
+        Some(handle) => handle,
+        None => return 0,
+    };
+    let state = handle.machine.save_state();
+    if !buffer.is_null() && capacity >= state.len() {
+        std::ptr::copy_nonoverlapping(state.as_ptr(), buffer, state.len());
+    }
+    state.len()
+}
+
+/// Restores a snapshot; the machine is left untouched and false returned if it is invalid.
+#[no_mangle]
+pub unsafe extern "C" fn rip8_restore(handle: *mut Rip8Machine, data: *const u8, len: usize) -> bool {
+    match (handle.as_mut(), data.is_null()) {
+        (Some(handle), false) => handle.machine.load_state(slice::from_raw_parts(data, len)),
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::ptr;
+
+    // i := hex v0 (0); sprite v1 v1 5; jump 0x204
+    const ZERO: [u8; 6] = [0xF0, 0x29, 0xD1, 0x15, 0x12, 0x04];
+
+    fn pixel(handle: *const Rip8Machine, x: usize, y: usize) -> u8 {
+        unsafe { *rip8_framebuffer(handle).add(y * RIP8_WIDTH as usize + x) }
+    }
+
+    #[test]
+    fn header_is_up_to_date() {
+        let crate_dir = env!("CARGO_MANIFEST_DIR");
+        let config = cbindgen::Config::from_file(format!("{}/cbindgen.toml", crate_dir)).unwrap();
+        let mut header = Vec::new();
+        cbindgen::generate_with_config(crate_dir, config).unwrap().write(&mut header);
+        let checked_in = std::fs::read_to_string(format!("{}/include/rip8.h", crate_dir)).unwrap();
+        assert!(String::from_utf8(header).unwrap() == checked_in, "include/rip8.h is stale; regenerate it with cbindgen");
+    }
+
+    #[test]
+    fn runs_a_program() {
+        unsafe {
+            let handle = rip8_create(1);
+            assert!(rip8_load_rom(handle, ZERO.as_ptr(), ZERO.len()));
+            assert!(!rip8_run_cycles(handle, 3));
+            assert!(rip8_take_redraw(handle));
+            assert!(!rip8_take_redraw(handle));
+            assert_eq!((0..4).map(|x| pixel(handle, x, 0)).collect::<Vec<_>>(), [1, 1, 1, 1]);
+            assert_eq!((0..4).map(|x| pixel(handle, x, 1)).collect::<Vec<_>>(), [1, 0, 0, 1]);
+            assert!(!rip8_load_rom(handle, ZERO.as_ptr(), 0x1000 - 0x200 + 1));
+            assert!(rip8_set_quirks(handle, c"cosmac".as_ptr()));
+            assert!(!rip8_set_quirks(handle, c"vip".as_ptr()));
+            rip8_set_key(handle, 16, true);
+            rip8_destroy(handle);
+        }
+    }
+
+    #[test]
+    fn snapshots_round_trip() {
+        unsafe {
+            let handle = rip8_create(1);
+            rip8_load_rom(handle, ZERO.as_ptr(), ZERO.len());
+            let size = rip8_snapshot(handle, ptr::null_mut(), 0);
+            let mut snapshot = vec![0; size];
+            assert_eq!(rip8_snapshot(handle, snapshot.as_mut_ptr(), size), size);
+            rip8_run_cycles(handle, 3);
+            assert_eq!(pixel(handle, 0, 0), 1);
+            assert!(!rip8_restore(handle, snapshot.as_ptr(), size - 1));
+            assert_eq!(pixel(handle, 0, 0), 1);
+            assert!(rip8_restore(handle, snapshot.as_ptr(), size));
+            assert_eq!(pixel(handle, 0, 0), 0);
+            rip8_destroy(handle);
+        }
+    }
+
+    #[test]
+    fn null_handles_are_ignored() {
+        unsafe {
+            let null = ptr::null_mut();
+            assert!(!rip8_load_rom(null, ZERO.as_ptr(), ZERO.len()));
+            assert!(rip8_run_cycles(null, 1));
+            assert!(rip8_framebuffer(null).is_null());
+            assert_eq!(rip8_snapshot(null, ptr::null_mut(), 0), 0);
+            assert!(!rip8_restore(null, ZERO.as_ptr(), ZERO.len()));
+            rip8_set_key(null, 1, true);
+            rip8_destroy(null);
+        }
+    }
+}
@@ -0,0 +1,8 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use rip_8::decompiler::Decompiler;
+
+fuzz_target!(|rom: &[u8]| {
+    let _ = Decompiler::new(rom).decompile();
+});
@@ -0,0 +1,33 @@
+#![no_main]
+
+use arbitrary::Arbitrary;
+use libfuzzer_sys::fuzz_target;
+use rip_8::Machine;
+
+const MAX_CYCLES: usize = 10_000;
+
+#[derive(Arbitrary, Debug)]
+struct Input {
+    keys: Vec<u16>,
+    rom: Vec<u8>,
+}
+
+fuzz_target!(|input: Input| {
+    let mut machine = Machine::new();
+    machine.load_program(&input.rom[..]);
+
+    for cycle in 0..MAX_CYCLES {
+        if let Some(keys) = input.keys.get(cycle % input.keys.len().max(1)) {
+            for key in 0..16 {
+                if keys & (1 << key) != 0 {
+                    machine.key_pressed(key);
+                } else {
+                    machine.key_released(key);
+                }
+            }
+        }
+        if machine.cycle() {
+            break;
+        }
+    }
+});
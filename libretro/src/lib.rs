@@ -0,0 +1,438 @@
+// The entry points are called by the frontend with pointers it owns, as described by libretro.h.
+#![allow(clippy::missing_safety_doc)]
+
+use std::ffi::{c_void, CStr};
+use std::os::raw::{c_char, c_uint};
+use std::sync::Mutex;
+
+use libretro_sys::*;
+use rip_8::audio::{ToneGenerator, ToneSettings, FRAME_RATE, SAMPLE_RATE};
+use rip_8::palette::Palette;
+use rip_8::quirks::Quirks;
+use rip_8::Machine;
+
+const WIDTH: usize = 64;
+const HEIGHT: usize = 32;
+
+// RetroPad button to keypad key: the d-pad is 2/4/6/8 and A fires 5, as most games expect.
+const BUTTONS: [(c_uint, usize, &CStr); 16] = [
+    (DEVICE_ID_JOYPAD_UP, 0x2, c"Key 2 (up)"),
+    (DEVICE_ID_JOYPAD_DOWN, 0x8, c"Key 8 (down)"),
+    (DEVICE_ID_JOYPAD_LEFT, 0x4, c"Key 4 (left)"),
+    (DEVICE_ID_JOYPAD_RIGHT, 0x6, c"Key 6 (right)"),
+    (DEVICE_ID_JOYPAD_A, 0x5, c"Key 5"),
+    (DEVICE_ID_JOYPAD_B, 0x0, c"Key 0"),
+    (DEVICE_ID_JOYPAD_X, 0x1, c"Key 1"),
+    (DEVICE_ID_JOYPAD_Y, 0x3, c"Key 3"),
+    (DEVICE_ID_JOYPAD_SELECT, 0x7, c"Key 7"),
+    (DEVICE_ID_JOYPAD_START, 0x9, c"Key 9"),
+    (DEVICE_ID_JOYPAD_L, 0xA, c"Key A"),
+    (DEVICE_ID_JOYPAD_R, 0xB, c"Key B"),
+    (DEVICE_ID_JOYPAD_L2, 0xC, c"Key C"),
+    (DEVICE_ID_JOYPAD_R2, 0xD, c"Key D"),
+    (DEVICE_ID_JOYPAD_L3, 0xE, c"Key E"),
+    (DEVICE_ID_JOYPAD_R3, 0xF, c"Key F"),
+];
+
+const QUIRKS_KEY: &CStr = c"rip8_quirks";
+const SPEED_KEY: &CStr = c"rip8_speed";
+
+struct Callbacks {
+    environment: Option<EnvironmentFn>,
+    video_refresh: Option<VideoRefreshFn>,
+    audio_batch: Option<AudioSampleBatchFn>,
+    input_poll: Option<InputPollFn>,
+    input_state: Option<InputStateFn>,
+}
+
+struct Core {
+    machine: Machine,
+    rom: Vec<u8>,
+    quirks: Quirks,
+    speed: u8,
+    tone: ToneGenerator,
+    palette: Palette,
+    video: Vec<u32>,
+    audio: Vec<i16>,
+}
+
+impl Core {
+    fn new(rom: Vec<u8>) -> Self {
+        let mut core = Core {
+            machine: Machine::new(),
+            rom,
+            quirks: Quirks::default(),
+            speed: Machine::new().speed(),
+            tone: ToneGenerator::new(ToneSettings::default(), SAMPLE_RATE),
+            palette: Palette::builtin().remove(0),
+            video: vec![0; WIDTH * HEIGHT],
+            audio: Vec::new(),
+        };
+        core.update_options();
+        core.reset();
+        core
+    }
+
+    fn reset(&mut self) {
+        self.machine = Machine::new();
+        self.machine.load_program(&self.rom[..]);
+        self.machine.set_quirks(self.quirks);
+        self.machine.set_speed(self.speed);
+    }
+
+    fn update_options(&mut self) {
+        if let Some(quirks) = variable(QUIRKS_KEY).and_then(|value| Quirks::profile(&value)) {
+            self.quirks = quirks;
+        }
+        if let Some(speed) = variable(SPEED_KEY).and_then(|value| value.parse().ok()) {
+            self.speed = speed;
+        }
+        self.machine.set_quirks(self.quirks);
+        self.machine.set_speed(self.speed);
+    }
+
+    fn run_frame(&mut self) {
+        for _ in 0..self.machine.speed() {
+            self.machine.cycle();
+        }
+        self.machine.draw_complete();
+
+        let color = |(r, g, b): (u8, u8, u8)| (r as u32) << 16 | (g as u32) << 8 | b as u32;
+        let (off, on) = (color(self.palette.colors[0]), color(self.palette.colors[1]));
+        for (pixel, lit) in self.video.iter_mut().zip(self.machine.screen().iter()) {
+            *pixel = if *lit { on } else { off };
+        }
+
+        self.audio.clear();
+        let gate = self.machine.sound_playing();
+        for _ in 0..SAMPLE_RATE / FRAME_RATE {
+            let sample = (self.tone.next_sample(gate) * i16::MAX as f32) as i16;
+            self.audio.extend_from_slice(&[sample, sample]);
+        }
+    }
+}
+
+static CALLBACKS: Mutex<Callbacks> = Mutex::new(Callbacks {
+    environment: None,
+    video_refresh: None,
+    audio_batch: None,
+    input_poll: None,
+    input_state: None,
+});
+static CORE: Mutex<Option<Core>> = Mutex::new(None);
+
+fn callbacks() -> std::sync::MutexGuard<'static, Callbacks> {
+    CALLBACKS.lock().expect("Callbacks poisoned")
+}
+
+fn environment(cmd: c_uint, data: *mut c_void) -> bool {
+    match callbacks().environment {
+        Some(environment) => unsafe { environment(cmd, data) },
+        None => false,
+    }
+}
+
+fn variable(key: &CStr) -> Option<String> {
+    let mut variable = Variable { key: key.as_ptr(), value: std::ptr::null() };
+    if !environment(ENVIRONMENT_GET_VARIABLE, &mut variable as *mut Variable as *mut c_void) || variable.value.is_null() {
+        return None;
+    }
+    Some(unsafe { CStr::from_ptr(variable.value) }.to_string_lossy().into_owned())
+}
+
+#[no_mangle]
+pub extern "C" fn retro_api_version() -> c_uint {
+    API_VERSION
+}
+
+#[no_mangle]
+pub extern "C" fn retro_init() {}
+
+#[no_mangle]
+pub extern "C" fn retro_deinit() {
+    *CORE.lock().expect("Core poisoned") = None;
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn retro_get_system_info(info: *mut SystemInfo) {
+    *info = SystemInfo {
+        library_name: c"RIP-8".as_ptr(),
+        library_version: c"0.1.0".as_ptr(),
+        valid_extensions: c"ch8|c8".as_ptr(),
+        need_fullpath: false,
+        block_extract: false,
+    };
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn retro_get_system_av_info(info: *mut SystemAvInfo) {
+    *info = SystemAvInfo {
+        geometry: GameGeometry {
+            base_width: WIDTH as c_uint,
+            base_height: HEIGHT as c_uint,
+            max_width: WIDTH as c_uint,
+            max_height: HEIGHT as c_uint,
+            aspect_ratio: 2.0,
+        },
+        timing: SystemTiming {
+            fps: FRAME_RATE as f64,
+            sample_rate: SAMPLE_RATE as f64,
+        },
+    };
+}
+
+#[no_mangle]
+pub extern "C" fn retro_set_environment(callback: EnvironmentFn) {
+    callbacks().environment = Some(callback);
+
+    let quirks = format!("Quirk profile; {}\0", Quirks::PROFILES.join("|"));
+    let variables = [
+        Variable { key: QUIRKS_KEY.as_ptr(), value: quirks.as_ptr() as *const c_char },
+        Variable { key: SPEED_KEY.as_ptr(), value: c"Instructions per frame; 4|8|10|15|20|30|50|100|200".as_ptr() },
+        Variable { key: std::ptr::null(), value: std::ptr::null() },
+    ];
+    environment(ENVIRONMENT_SET_VARIABLES, variables.as_ptr() as *mut c_void);
+
+    let mut no_game = false;
+    environment(ENVIRONMENT_SET_SUPPORT_NO_GAME, &mut no_game as *mut bool as *mut c_void);
+}
+
+#[no_mangle]
+pub extern "C" fn retro_set_video_refresh(callback: VideoRefreshFn) {
+    callbacks().video_refresh = Some(callback);
+}
+
+#[no_mangle]
+pub extern "C" fn retro_set_audio_sample(_callback: AudioSampleFn) {}
+
+#[no_mangle]
+pub extern "C" fn retro_set_audio_sample_batch(callback: AudioSampleBatchFn) {
+    callbacks().audio_batch = Some(callback);
+}
+
+#[no_mangle]
+pub extern "C" fn retro_set_input_poll(callback: InputPollFn) {
+    callbacks().input_poll = Some(callback);
+}
+
+#[no_mangle]
+pub extern "C" fn retro_set_input_state(callback: InputStateFn) {
+    callbacks().input_state = Some(callback);
+}
+
+#[no_mangle]
+pub extern "C" fn retro_set_controller_port_device(_port: c_uint, _device: c_uint) {}
+
+#[no_mangle]
+pub extern "C" fn retro_reset() {
+    if let Some(core) = CORE.lock().expect("Core poisoned").as_mut() {
+        core.reset();
+    }
+}
+
+#[no_mangle]
+pub extern "C" fn retro_run() {
+    let (input_poll, input_state, video_refresh, audio_batch) = {
+        let callbacks = callbacks();
+        (callbacks.input_poll, callbacks.input_state, callbacks.video_refresh, callbacks.audio_batch)
+    };
+    let mut core = CORE.lock().expect("Core poisoned");
+    let core = match core.as_mut() {
+        Some(core) => core,
+        None => return,
+    };
+
+    let mut updated = false;
+    if environment(ENVIRONMENT_GET_VARIABLE_UPDATE, &mut updated as *mut bool as *mut c_void) && updated {
+        core.update_options();
+    }
+
+    if let (Some(input_poll), Some(input_state)) = (input_poll, input_state) {
+        unsafe { input_poll() };
+        for (button, key, _) in BUTTONS {
+            if unsafe { input_state(0, DEVICE_JOYPAD, 0, button) } != 0 {
+                core.machine.key_pressed(key);
+            } else {
+                core.machine.key_released(key);
+            }
+        }
+    }
+
+    core.run_frame();
+
+    if let Some(video_refresh) = video_refresh {
+        unsafe { video_refresh(core.video.as_ptr() as *const c_void, WIDTH as c_uint, HEIGHT as c_uint, WIDTH * 4) };
+    }
+    if let Some(audio_batch) = audio_batch {
+        unsafe { audio_batch(core.audio.as_ptr(), core.audio.len() / 2) };
+    }
+}
+
+#[no_mangle]
+pub extern "C" fn retro_serialize_size() -> usize {
+    match CORE.lock().expect("Core poisoned").as_ref() {
+        Some(core) => core.machine.save_state().len(),
+        None => 0,
+    }
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn retro_serialize(data: *mut c_void, size: usize) -> bool {
+    let core = CORE.lock().expect("Core poisoned");
+    let state = match core.as_ref() {
+        Some(core) => core.machine.save_state(),
+        None => return false,
+    };
+    if state.len() > size {
+        return false;
+    }
+    std::ptr::copy_nonoverlapping(state.as_ptr(), data as *mut u8, state.len());
+    true
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn retro_unserialize(data: *const c_void, size: usize) -> bool {
+    let mut core = CORE.lock().expect("Core poisoned");
+    match core.as_mut() {
+        Some(core) => core.machine.load_state(std::slice::from_raw_parts(data as *const u8, size)),
+        None => false,
+    }
+}
+
+#[no_mangle]
+pub extern "C" fn retro_cheat_reset() {}
+
+#[no_mangle]
+pub extern "C" fn retro_cheat_set(_index: c_uint, _enabled: bool, _code: *const c_char) {}
+
+#[no_mangle]
+pub unsafe extern "C" fn retro_load_game(game: *const GameInfo) -> bool {
+    if game.is_null() || (*game).data.is_null() {
+        return false;
+    }
+    let mut format = PixelFormat::ARGB8888;
+    if !environment(ENVIRONMENT_SET_PIXEL_FORMAT, &mut format as *mut PixelFormat as *mut c_void) {
+        return false;
+    }
+    let descriptors: Vec<InputDescriptor> = BUTTONS.iter()
+        .map(|(button, _, description)| InputDescriptor { port: 0, device: DEVICE_JOYPAD, index: 0, id: *button, description: description.as_ptr() })
+        .chain(std::iter::once(InputDescriptor { port: 0, device: 0, index: 0, id: 0, description: std::ptr::null() }))
+        .collect();
+    environment(ENVIRONMENT_SET_INPUT_DESCRIPTORS, descriptors.as_ptr() as *mut c_void);
+
+    let rom = std::slice::from_raw_parts((*game).data as *const u8, (*game).size).to_vec();
+    *CORE.lock().expect("Core poisoned") = Some(Core::new(rom));
+    true
+}
+
+#[no_mangle]
+pub extern "C" fn retro_load_game_special(_game_type: c_uint, _info: *const GameInfo, _num_info: usize) -> bool {
+    false
+}
+
+#[no_mangle]
+pub extern "C" fn retro_unload_game() {
+    *CORE.lock().expect("Core poisoned") = None;
+}
+
+#[no_mangle]
+pub extern "C" fn retro_get_region() -> c_uint {
+    Region::NTSC as c_uint
+}
+
+#[no_mangle]
+pub extern "C" fn retro_get_memory_data(_id: c_uint) -> *mut c_void {
+    std::ptr::null_mut()
+}
+
+#[no_mangle]
+pub extern "C" fn retro_get_memory_size(_id: c_uint) -> usize {
+    0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicU32, Ordering};
+
+    // The core lives in statics, so the tests take turns.
+    static SERIAL: Mutex<()> = Mutex::new(());
+    static HELD: AtomicU32 = AtomicU32::new(u32::MAX);
+
+    unsafe extern "C" fn environment(cmd: c_uint, _data: *mut c_void) -> bool {
+        cmd == ENVIRONMENT_SET_PIXEL_FORMAT
+    }
+
+    unsafe extern "C" fn input_poll() {}
+
+    unsafe extern "C" fn input_state(_port: c_uint, _device: c_uint, _index: c_uint, id: c_uint) -> i16 {
+        (HELD.load(Ordering::SeqCst) == id) as i16
+    }
+
+    fn load(rom: &[u8]) {
+        retro_set_environment(environment);
+        retro_set_input_poll(input_poll);
+        retro_set_input_state(input_state);
+        let game = GameInfo { path: std::ptr::null(), data: rom.as_ptr() as *const c_void, size: rom.len(), meta: std::ptr::null() };
+        assert!(unsafe { retro_load_game(&game) });
+    }
+
+    fn core<T>(f: impl FnOnce(&Core) -> T) -> T {
+        f(CORE.lock().unwrap().as_ref().unwrap())
+    }
+
+    #[test]
+    fn states_round_trip() {
+        let _serial = SERIAL.lock().unwrap();
+        // v0 += 1; jump 0x200
+        load(&[0x70, 0x01, 0x12, 0x00]);
+        retro_run();
+        let size = retro_serialize_size();
+        let mut state = vec![0; size];
+        unsafe {
+            assert!(!retro_serialize(state.as_mut_ptr() as *mut c_void, size - 1));
+            assert!(retro_serialize(state.as_mut_ptr() as *mut c_void, size));
+        }
+        let saved = core(|core| core.machine.registers()[0]);
+        retro_run();
+        assert_ne!(core(|core| core.machine.registers()[0]), saved);
+        assert!(unsafe { retro_unserialize(state.as_ptr() as *const c_void, size) });
+        assert_eq!(core(|core| core.machine.registers()[0]), saved);
+        retro_unload_game();
+    }
+
+    #[test]
+    fn wrong_size_states_are_rejected() {
+        let _serial = SERIAL.lock().unwrap();
+        load(&[0x70, 0x01, 0x12, 0x00]);
+        let size = retro_serialize_size();
+        let mut state = vec![0; size + 1];
+        unsafe { retro_serialize(state.as_mut_ptr() as *mut c_void, size) };
+        retro_run();
+        let before = core(|core| core.machine.save_state());
+        unsafe {
+            assert!(!retro_unserialize(state.as_ptr() as *const c_void, size - 1));
+            assert!(!retro_unserialize(state.as_ptr() as *const c_void, size + 1));
+        }
+        assert_eq!(core(|core| core.machine.save_state()), before);
+        retro_unload_game();
+        assert!(!unsafe { retro_unserialize(state.as_ptr() as *const c_void, size) });
+    }
+
+    #[test]
+    fn joypad_buttons_press_keypad_keys() {
+        let _serial = SERIAL.lock().unwrap();
+        for (button, key, _) in BUTTONS {
+            // v0 := key, then skip past the loop at 0x204 into the one at 0x206 while it is held.
+            load(&[0x60, key as u8, 0xE0, 0x9E, 0x12, 0x04, 0x12, 0x06]);
+            HELD.store(button, Ordering::SeqCst);
+            retro_run();
+            assert_eq!(core(|core| core.machine.pc()), 0x206, "button {} should press key {:X}", button, key);
+            HELD.store(u32::MAX, Ordering::SeqCst);
+            retro_reset();
+            retro_run();
+            assert_eq!(core(|core| core.machine.pc()), 0x204);
+        }
+        retro_unload_game();
+    }
+}
@@ -0,0 +1,209 @@
+// clippy flags the conversions generated by the pyo3 macros.
+#![allow(clippy::useless_conversion)]
+
+use pyo3::exceptions::PyValueError;
+use pyo3::prelude::*;
+use pyo3::types::PyBytes;
+
+use rip_8::quirks::Quirks;
+
+const WIDTH: usize = 64;
+const HEIGHT: usize = 32;
+
+// A CHIP-8 machine; reset() reloads the last ROM with the same seed, quirks and speed.
+#[pyclass]
+struct Machine {
+    machine: rip_8::Machine,
+    rom: Vec<u8>,
+    seed: u64,
+}
+
+#[pymethods]
+impl Machine {
+    #[new]
+    #[pyo3(signature = (rom=None, seed=0, quirks="default", speed=None))]
+    fn new(rom: Option<&[u8]>, seed: u64, quirks: &str, speed: Option<u8>) -> PyResult<Self> {
+        let mut machine = Machine { machine: rip_8::Machine::new(), rom: Vec::new(), seed };
+        machine.set_quirks(quirks)?;
+        if let Some(speed) = speed {
+            machine.set_speed(speed);
+        }
+        if let Some(rom) = rom {
+            machine.load(rom)?;
+        } else {
+            machine.reset();
+        }
+        Ok(machine)
+    }
+
+    fn load(&mut self, rom: &[u8]) -> PyResult<()> {
+        if rom.len() > 0x1000 - 0x200 {
+            return Err(PyValueError::new_err(format!("ROM is {} bytes, at most {} fit", rom.len(), 0x1000 - 0x200)));
+        }
+        self.rom = rom.to_vec();
+        self.reset();
+        Ok(())
+    }
+
+    fn reset(&mut self) {
+        let mut machine = rip_8::Machine::new();
+        machine.seed(self.seed);
+        machine.set_quirks(self.machine.quirks());
+        machine.set_speed(self.machine.speed());
+        machine.load_program(&self.rom[..]);
+        self.machine = machine;
+    }
+
+    fn set_quirks(&mut self, profile: &str) -> PyResult<()> {
+        let quirks = Quirks::profile(profile)
+            .ok_or_else(|| PyValueError::new_err(format!("unknown quirk profile {}, expected one of {:?}", profile, Quirks::PROFILES)))?;
+        self.machine.set_quirks(quirks);
+        Ok(())
+    }
+
+    fn set_speed(&mut self, cycles_per_frame: u8) {
+        self.machine.set_speed(cycles_per_frame.max(1));
+    }
+
+    // Runs whole 60 Hz frames; returns whether the machine halted.
+    #[pyo3(signature = (frames=1))]
+    fn step_frame(&mut self, frames: u32) -> bool {
+        let speed = self.machine.speed() as u32;
+        (0..frames).any(|_| self.step(speed))
+    }
+
+    #[pyo3(signature = (cycles=1))]
+    fn step(&mut self, cycles: u32) -> bool {
+        for _ in 0..cycles {
+            if self.machine.cycle() {
+                return true;
+            }
+        }
+        self.machine.halted()
+    }
+
+    fn press(&mut self, key: usize) -> PyResult<()> {
+        self.machine.key_pressed(Self::key(key)?);
+        Ok(())
+    }
+
+    fn release(&mut self, key: usize) -> PyResult<()> {
+        self.machine.key_released(Self::key(key)?);
+        Ok(())
+    }
+
+    // Sets all 16 keys at once from a bit mask, bit n for key n.
+    fn set_keys(&mut self, mask: u16) {
+        for key in 0..16 {
+            if mask & (1 << key) != 0 {
+                self.machine.key_pressed(key);
+            } else {
+                self.machine.key_released(key);
+            }
+        }
+    }
+
+    // WIDTH * HEIGHT bytes, row-major, 1 for a lit pixel.
+    fn screen<'py>(&self, py: Python<'py>) -> Bound<'py, PyBytes> {
+        let pixels: Vec<u8> = self.machine.screen().iter().map(|pixel| *pixel as u8).collect();
+        PyBytes::new_bound(py, &pixels)
+    }
+
+    // The screen as a (32, 64) uint8 NumPy array; needs numpy installed.
+    fn screen_array<'py>(&self, py: Python<'py>) -> PyResult<Bound<'py, PyAny>> {
+        let numpy = py.import_bound("numpy")?;
+        let array = numpy.call_method1("frombuffer", (self.screen(py), numpy.getattr("uint8")?))?;
+        array.call_method1("reshape", (HEIGHT, WIDTH))
+    }
+
+    fn save_state<'py>(&self, py: Python<'py>) -> Bound<'py, PyBytes> {
+        PyBytes::new_bound(py, &self.machine.save_state())
+    }
+
+    fn load_state(&mut self, state: &[u8]) -> PyResult<()> {
+        if self.machine.load_state(state) {
+            Ok(())
+        } else {
+            Err(PyValueError::new_err("not a valid save state"))
+        }
+    }
+
+    #[pyo3(signature = (start=0, length=None))]
+    fn memory<'py>(&self, py: Python<'py>, start: usize, length: Option<usize>) -> PyResult<Bound<'py, PyBytes>> {
+        let memory = self.machine.memory();
+        let end = length.map_or(memory.len(), |length| start.saturating_add(length));
+        if start > end || end > memory.len() {
+            return Err(PyValueError::new_err(format!("range {:#05X}..{:#05X} is outside memory", start, end)));
+        }
+        Ok(PyBytes::new_bound(py, &memory[start..end]))
+    }
+
+    #[getter]
+    fn registers(&self) -> Vec<u8> {
+        self.machine.registers().to_vec()
+    }
+
+    #[getter]
+    fn pc(&self) -> usize {
+        self.machine.pc()
+    }
+
+    #[getter]
+    fn index(&self) -> u16 {
+        self.machine.index_register()
+    }
+
+    #[getter]
+    fn stack(&self) -> Vec<u16> {
+        self.machine.stack().to_vec()
+    }
+
+    #[getter]
+    fn delay_timer(&self) -> u8 {
+        self.machine.delay_timer()
+    }
+
+    #[getter]
+    fn sound_timer(&self) -> u8 {
+        self.machine.sound_timer()
+    }
+
+    #[getter]
+    fn sound_playing(&self) -> bool {
+        self.machine.sound_playing()
+    }
+
+    #[getter]
+    fn frames(&self) -> u64 {
+        self.machine.frames()
+    }
+
+    #[getter]
+    fn halted(&self) -> bool {
+        self.machine.halted()
+    }
+
+    #[getter]
+    fn waiting_for_key(&self) -> bool {
+        self.machine.waiting_for_key()
+    }
+}
+
+impl Machine {
+    fn key(key: usize) -> PyResult<usize> {
+        if key < 16 {
+            Ok(key)
+        } else {
+            Err(PyValueError::new_err(format!("key {} is not between 0 and 15", key)))
+        }
+    }
+}
+
+#[pymodule]
+#[pyo3(name = "rip8")]
+fn rip8(module: &Bound<'_, PyModule>) -> PyResult<()> {
+    module.add_class::<Machine>()?;
+    module.add("WIDTH", WIDTH)?;
+    module.add("HEIGHT", HEIGHT)?;
+    Ok(())
+}
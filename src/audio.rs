@@ -0,0 +1,191 @@
+use std::f32::consts::TAU;
+use std::io::{self, Write};
+
+#[cfg(feature = "sound")]
+mod device;
+
+#[cfg(feature = "sound")]
+pub use device::Audio;
+
+pub const SAMPLE_RATE: u32 = 44100;
+pub const FRAME_RATE: u32 = 60;
+// Attack and release time, long enough to avoid clicks but well under one 60 Hz timer tick.
+const ENVELOPE_SECONDS: f32 = 0.004;
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum Waveform {
+    Square,
+    Sine,
+    Triangle,
+}
+
+impl Waveform {
+    pub fn parse(text: &str) -> Option<Self> {
+        match text {
+            "square" => Some(Waveform::Square),
+            "sine" => Some(Waveform::Sine),
+            "triangle" => Some(Waveform::Triangle),
+            _ => None,
+        }
+    }
+
+    fn sample(self, phase: f32) -> f32 {
+        match self {
+            Waveform::Square => if phase < 0.5 { 1.0 } else { -1.0 },
+            Waveform::Sine => (phase * TAU).sin(),
+            Waveform::Triangle => 1.0 - 4.0 * (phase - 0.5).abs(),
+        }
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct ToneSettings {
+    pub waveform: Waveform,
+    pub pitch: f32,
+    pub volume: f32,
+}
+
+impl Default for ToneSettings {
+    fn default() -> Self {
+        ToneSettings {
+            waveform: Waveform::Square,
+            pitch: 440.0,
+            volume: 0.2,
+        }
+    }
+}
+
+// Produces the buzzer signal one sample at a time; the gate follows the machine's sound timer.
+pub struct ToneGenerator {
+    settings: ToneSettings,
+    sample_rate: u32,
+    phase: f32,
+    level: f32,
+}
+
+impl ToneGenerator {
+    pub fn new(settings: ToneSettings, sample_rate: u32) -> Self {
+        ToneGenerator {
+            settings,
+            sample_rate,
+            phase: 0.0,
+            level: 0.0,
+        }
+    }
+
+    pub fn next_sample(&mut self, gate: bool) -> f32 {
+        let step = 1.0 / (ENVELOPE_SECONDS * self.sample_rate as f32);
+        self.level = if gate { (self.level + step).min(1.0) } else { (self.level - step).max(0.0) };
+        if self.level == 0.0 {
+            self.phase = 0.0;
+            return 0.0;
+        }
+        let sample = self.settings.waveform.sample(self.phase);
+        self.phase = (self.phase + self.settings.pitch / self.sample_rate as f32).fract();
+        sample * self.level * self.settings.volume
+    }
+}
+
+// Renders the buzzer offline, one emulated frame at a time, instead of to a device.
+pub struct AudioCapture {
+    generator: ToneGenerator,
+    gates: Vec<bool>,
+    samples: Vec<f32>,
+}
+
+impl AudioCapture {
+    pub fn new(settings: ToneSettings) -> Self {
+        AudioCapture {
+            generator: ToneGenerator::new(settings, SAMPLE_RATE),
+            gates: Vec::new(),
+            samples: Vec::new(),
+        }
+    }
+
+    pub(crate) fn next_frame(&mut self, gate: bool) {
+        self.gates.push(gate);
+        let end = (self.gates.len() as u64 * SAMPLE_RATE as u64 / FRAME_RATE as u64) as usize;
+        while self.samples.len() < end {
+            let sample = self.generator.next_sample(gate);
+            self.samples.push(sample);
+        }
+    }
+
+    // Whether the tone was sounding during each emulated frame so far.
+    pub fn gates(&self) -> &[bool] {
+        &self.gates
+    }
+
+    pub fn samples(&self) -> &[f32] {
+        &self.samples
+    }
+
+    // Each beep as its first frame and its length in frames.
+    pub fn beeps(&self) -> Vec<(usize, usize)> {
+        let mut beeps: Vec<(usize, usize)> = Vec::new();
+        for (frame, gate) in self.gates.iter().enumerate() {
+            match beeps.last_mut() {
+                Some((start, length)) if *gate && *start + *length == frame => *length += 1,
+                _ if *gate => beeps.push((frame, 1)),
+                _ => {}
+            }
+        }
+        beeps
+    }
+
+    // Mono 16-bit PCM.
+    pub fn write_wav<W: Write>(&self, out: &mut W) -> io::Result<()> {
+        let data = self.samples.len() as u32 * 2;
+        out.write_all(b"RIFF")?;
+        out.write_all(&(36 + data).to_le_bytes())?;
+        out.write_all(b"WAVEfmt ")?;
+        out.write_all(&16u32.to_le_bytes())?;
+        out.write_all(&1u16.to_le_bytes())?;
+        out.write_all(&1u16.to_le_bytes())?;
+        out.write_all(&SAMPLE_RATE.to_le_bytes())?;
+        out.write_all(&(SAMPLE_RATE * 2).to_le_bytes())?;
+        out.write_all(&2u16.to_le_bytes())?;
+        out.write_all(&16u16.to_le_bytes())?;
+        out.write_all(b"data")?;
+        out.write_all(&data.to_le_bytes())?;
+        for sample in &self.samples {
+            out.write_all(&((sample.clamp(-1.0, 1.0) * i16::MAX as f32) as i16).to_le_bytes())?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Machine;
+
+    #[test]
+    fn records_pong_beeps() {
+        let mut machine = Machine::new();
+        machine.load_program(&include_bytes!("../pong2.c8")[..]);
+        machine.seed(1);
+        machine.set_audio_capture(AudioCapture::new(ToneSettings::default()));
+        while machine.frames() < 1200 {
+            machine.cycle();
+        }
+        let capture = machine.audio_capture().unwrap();
+        assert_eq!(capture.gates().len(), 1200);
+        assert_eq!(capture.beeps(), [(358, 32), (700, 32), (1040, 32)]);
+
+        let mut wav = Vec::new();
+        capture.write_wav(&mut wav).unwrap();
+        let samples = 1200 * SAMPLE_RATE as usize / FRAME_RATE as usize;
+        let u32_at = |offset: usize| u32::from_le_bytes(wav[offset..offset + 4].try_into().unwrap());
+        let u16_at = |offset: usize| u16::from_le_bytes(wav[offset..offset + 2].try_into().unwrap());
+        assert_eq!(wav.len(), 44 + 2 * samples);
+        assert_eq!(&wav[0..4], b"RIFF");
+        assert_eq!(u32_at(4) as usize, wav.len() - 8);
+        assert_eq!(&wav[8..16], b"WAVEfmt ");
+        assert_eq!((u32_at(16), u16_at(20), u16_at(22)), (16, 1, 1));
+        assert_eq!((u32_at(24), u32_at(28)), (SAMPLE_RATE, SAMPLE_RATE * 2));
+        assert_eq!((u16_at(32), u16_at(34)), (2, 16));
+        assert_eq!(&wav[36..40], b"data");
+        assert_eq!(u32_at(40) as usize, 2 * samples);
+    }
+}
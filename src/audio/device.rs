@@ -0,0 +1,64 @@
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+use rodio::{OutputStream, Source};
+
+use super::{ToneGenerator, ToneSettings, SAMPLE_RATE};
+use crate::frontend::Beeper;
+
+struct Tone {
+    gate: Arc<AtomicBool>,
+    generator: ToneGenerator,
+}
+
+impl Iterator for Tone {
+    type Item = f32;
+
+    fn next(&mut self) -> Option<f32> {
+        Some(self.generator.next_sample(self.gate.load(Ordering::Relaxed)))
+    }
+}
+
+impl Source for Tone {
+    fn current_frame_len(&self) -> Option<usize> {
+        None
+    }
+
+    fn channels(&self) -> u16 {
+        1
+    }
+
+    fn sample_rate(&self) -> u32 {
+        SAMPLE_RATE
+    }
+
+    fn total_duration(&self) -> Option<Duration> {
+        None
+    }
+}
+
+// Owns the output stream for as long as sound is wanted; dropping it closes the device.
+pub struct Audio {
+    _stream: OutputStream,
+    gate: Arc<AtomicBool>,
+}
+
+impl Audio {
+    pub fn new(settings: ToneSettings) -> Option<Self> {
+        let (stream, handle) = OutputStream::try_default().ok()?;
+        let gate = Arc::new(AtomicBool::new(false));
+        let tone = Tone {
+            gate: Arc::clone(&gate),
+            generator: ToneGenerator::new(settings, SAMPLE_RATE),
+        };
+        handle.play_raw(tone).ok()?;
+        Some(Audio { _stream: stream, gate })
+    }
+}
+
+impl Beeper for Audio {
+    fn set_playing(&mut self, playing: bool) {
+        self.gate.store(playing, Ordering::Relaxed);
+    }
+}
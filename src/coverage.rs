@@ -0,0 +1,162 @@
+use std::collections::{BTreeMap, HashMap};
+use std::io::{self, Write};
+
+use crate::OpCode;
+
+#[derive(Default)]
+pub struct LineMap {
+    lines: BTreeMap<usize, (String, usize)>,
+}
+
+impl LineMap {
+    pub fn parse(text: &str) -> Result<Self, String> {
+        let mut lines = BTreeMap::new();
+        for entry in text.lines().map(str::trim).filter(|l| !l.is_empty() && !l.starts_with('#')) {
+            let malformed = || format!("Malformed line map entry {}.", entry);
+            let (addr, location) = entry.split_once(char::is_whitespace).ok_or_else(malformed)?;
+            let addr = usize::from_str_radix(addr.trim_start_matches("0x"), 16).map_err(|_| malformed())?;
+            let (file, line) = location.trim().rsplit_once(':').ok_or_else(malformed)?;
+            lines.insert(addr, (file.to_string(), line.parse().map_err(|_| malformed())?));
+        }
+        Ok(LineMap { lines })
+    }
+
+    pub fn from_listing(file: &str, listing: &BTreeMap<usize, usize>) -> Self {
+        LineMap {
+            lines: listing.iter().map(|(addr, line)| (*addr, (file.to_string(), *line))).collect(),
+        }
+    }
+
+    pub fn location(&self, addr: usize) -> Option<(&str, usize)> {
+        self.lines.get(&addr).map(|(file, line)| (file.as_str(), *line))
+    }
+
+    // Every mapped address with its file and line, in address order.
+    pub fn entries(&self) -> impl Iterator<Item = (usize, &str, usize)> {
+        self.lines.iter().map(|(addr, (file, line))| (*addr, file.as_str(), *line))
+    }
+}
+
+pub struct Coverage {
+    executed: Vec<u64>,
+    skips: HashMap<usize, (u64, u64)>,
+}
+
+impl Coverage {
+    pub(crate) fn new() -> Self {
+        Coverage {
+            executed: vec![0; 4096],
+            skips: HashMap::new(),
+        }
+    }
+
+    pub(crate) fn record(&mut self, pc: usize, opcode: OpCode, next_pc: usize) {
+        if let Some(count) = self.executed.get_mut(pc) {
+            *count += 1;
+        }
+        if is_skip(opcode) {
+            let outcome = self.skips.entry(pc).or_insert((0, 0));
+            if next_pc == pc + 4 {
+                outcome.0 += 1;
+            } else {
+                outcome.1 += 1;
+            }
+        }
+    }
+
+    pub fn write_lcov<W>(&self, out: &mut W, map: &LineMap, memory: &[u8]) -> io::Result<()> where W: Write {
+        let mut files: BTreeMap<&str, BTreeMap<usize, (u64, Option<usize>)>> = BTreeMap::new();
+        for (addr, (file, line)) in &map.lines {
+            let count = self.executed.get(*addr).copied().unwrap_or(0);
+            let entry = files.entry(file).or_default().entry(*line).or_insert((0, None));
+            entry.0 = entry.0.max(count);
+            if *addr + 1 < memory.len() && is_skip((memory[*addr] as u16) << 8 | memory[*addr + 1] as u16) {
+                entry.1 = Some(*addr);
+            }
+        }
+
+        writeln!(out, "TN:")?;
+        for (file, lines) in files {
+            writeln!(out, "SF:{}", file)?;
+            let (mut branches, mut branches_hit) = (0, 0);
+            for (line, (_, skip)) in &lines {
+                let Some(addr) = skip else { continue };
+                let outcome = self.skips.get(addr);
+                for block in 0..2 {
+                    branches += 1;
+                    match outcome.map(|(taken, not_taken)| if block == 0 { *taken } else { *not_taken }) {
+                        Some(count) => {
+                            writeln!(out, "BRDA:{},0,{},{}", line, block, count)?;
+                            if count > 0 {
+                                branches_hit += 1;
+                            }
+                        }
+                        None => writeln!(out, "BRDA:{},0,{},-", line, block)?,
+                    }
+                }
+            }
+            for (line, (count, _)) in &lines {
+                writeln!(out, "DA:{},{}", line, count)?;
+            }
+            writeln!(out, "BRF:{}", branches)?;
+            writeln!(out, "BRH:{}", branches_hit)?;
+            writeln!(out, "LF:{}", lines.len())?;
+            writeln!(out, "LH:{}", lines.values().filter(|(count, _)| *count > 0).count())?;
+            writeln!(out, "end_of_record")?;
+        }
+        Ok(())
+    }
+}
+
+fn is_skip(opcode: OpCode) -> bool {
+    matches!(opcode & 0xF000, 0x3000 | 0x4000 | 0x5000 | 0x9000)
+        || (opcode & 0xF000 == 0xE000 && matches!(opcode & 0x00FF, 0x9E | 0xA1))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Machine;
+
+    #[test]
+    fn parses_line_maps() {
+        let map = LineMap::parse("# address file:line\n0x200 game.8o:1\n\n20a lib/a:b.8o:7\n").unwrap();
+        assert_eq!(map.entries().collect::<Vec<_>>(), [(0x200, "game.8o", 1), (0x20A, "lib/a:b.8o", 7)]);
+        for text in ["0x200", "0x2G0 game.8o:1", "0x200 game.8o", "0x200 game.8o:one"] {
+            assert!(LineMap::parse(text).is_err(), "{}", text);
+        }
+    }
+
+    #[test]
+    fn writes_lines_and_skip_outcomes() {
+        let mut machine = Machine::new();
+        machine.enable_coverage();
+        // v0 := 5; skip if v0 == 5 (taken); jump 0x204; skip if v0 == 6 (not taken); jump 0x208
+        machine.load_program(&[0x60, 0x05, 0x30, 0x05, 0x12, 0x04, 0x30, 0x06, 0x12, 0x08][..]);
+        for _ in 0..5 {
+            machine.cycle();
+        }
+        let map = LineMap::parse("0x200 game.8o:1\n0x202 game.8o:2\n0x204 game.8o:3\n0x206 game.8o:4\n0x208 game.8o:5").unwrap();
+        let mut report = Vec::new();
+        machine.coverage().unwrap().write_lcov(&mut report, &map, machine.memory()).unwrap();
+        let expected = [
+            "TN:", "SF:game.8o",
+            "BRDA:2,0,0,1", "BRDA:2,0,1,0", "BRDA:4,0,0,0", "BRDA:4,0,1,1",
+            "DA:1,1", "DA:2,1", "DA:3,0", "DA:4,1", "DA:5,2",
+            "BRF:4", "BRH:2", "LF:5", "LH:4", "end_of_record",
+        ];
+        assert_eq!(String::from_utf8(report).unwrap().lines().collect::<Vec<_>>(), expected);
+    }
+
+    #[test]
+    fn unreached_skips_have_no_outcome() {
+        let map = LineMap::parse("0x200 game.8o:1").unwrap();
+        let mut memory = vec![0; 4096];
+        memory[0x200] = 0x30;
+        let mut report = Vec::new();
+        Coverage::new().write_lcov(&mut report, &map, &memory).unwrap();
+        let report = String::from_utf8(report).unwrap();
+        assert!(report.contains("BRDA:1,0,0,-\nBRDA:1,0,1,-\n"));
+        assert!(report.contains("BRH:0\n"));
+    }
+}
@@ -0,0 +1,216 @@
+use std::path::PathBuf;
+use std::{env, fs, io};
+
+use crate::palette::Rgb;
+
+pub const CRT_SCALE: usize = 8;
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct CrtSettings {
+    pub scanlines: f32,
+    pub grid: f32,
+    pub bloom: f32,
+    pub curvature: f32,
+}
+
+impl CrtSettings {
+    pub const PRESETS: [&'static str; 5] = ["off", "scanlines", "grid", "soft", "crt"];
+
+    pub fn preset(name: &str) -> Option<Self> {
+        let settings = |scanlines, grid, bloom, curvature| CrtSettings { scanlines, grid, bloom, curvature };
+        match name {
+            "off" => Some(settings(0.0, 0.0, 0.0, 0.0)),
+            "scanlines" => Some(settings(0.45, 0.0, 0.0, 0.0)),
+            "grid" => Some(settings(0.0, 0.5, 0.0, 0.0)),
+            "soft" => Some(settings(0.2, 0.0, 0.6, 0.0)),
+            "crt" => Some(settings(0.35, 0.15, 0.5, 0.08)),
+            _ => None,
+        }
+    }
+
+    pub fn enabled(&self) -> bool {
+        *self != Self::preset("off").unwrap()
+    }
+
+    // ~/.rip8_crt names the preset to start with; --crt and F4 override it. A missing file means off.
+    pub fn config() -> Result<Option<String>, String> {
+        let path = match env::var_os("HOME") {
+            Some(home) => PathBuf::from(home).join(".rip8_crt"),
+            None => return Ok(None),
+        };
+        match fs::read_to_string(&path) {
+            Ok(text) => Self::parse_config(&text).map_err(|e| format!("{}: {}", path.display(), e)),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(format!("{}: {}", path.display(), e)),
+        }
+    }
+
+    // The first line that is not blank or a `#` comment.
+    fn parse_config(text: &str) -> Result<Option<String>, String> {
+        match text.lines().map(str::trim).find(|line| !line.is_empty() && !line.starts_with('#')) {
+            Some(name) if Self::preset(name).is_some() => Ok(Some(name.to_string())),
+            Some(name) => Err(format!("unknown CRT preset {}, expected one of {:?}", name, Self::PRESETS)),
+            None => Ok(None),
+        }
+    }
+}
+
+// Upscales a frame by CRT_SCALE and applies the effects, returning tightly packed RGB24 rows.
+pub fn process(frame: &[Rgb], width: usize, height: usize, settings: &CrtSettings) -> Vec<u8> {
+    let (out_width, out_height) = (width * CRT_SCALE, height * CRT_SCALE);
+    let source: Vec<[f32; 3]> = frame.iter().map(|(r, g, b)| [*r as f32, *g as f32, *b as f32]).collect();
+    let glow = if settings.bloom > 0.0 { blur(&source, width, height) } else { Vec::new() };
+
+    let mut image = vec![[0.0f32; 3]; out_width * out_height];
+    for y in 0..out_height {
+        for x in 0..out_width {
+            let mut pixel = source[(y / CRT_SCALE) * width + x / CRT_SCALE];
+            if settings.bloom > 0.0 {
+                let halo = sample(&glow, width, height, (x as f32 + 0.5) / CRT_SCALE as f32 - 0.5, (y as f32 + 0.5) / CRT_SCALE as f32 - 0.5);
+                for c in 0..3 {
+                    pixel[c] += halo[c] * settings.bloom;
+                }
+            }
+            let mut shade = 1.0;
+            if x % CRT_SCALE == 0 || y % CRT_SCALE == 0 {
+                shade -= settings.grid;
+            }
+            if y % 2 == 1 {
+                shade -= settings.scanlines;
+            }
+            image[y * out_width + x] = pixel.map(|c| c * shade.max(0.0));
+        }
+    }
+
+    let mut bytes = Vec::with_capacity(out_width * out_height * 3);
+    for y in 0..out_height {
+        for x in 0..out_width {
+            let pixel = if settings.curvature > 0.0 {
+                let u = x as f32 / out_width as f32 * 2.0 - 1.0;
+                let v = y as f32 / out_height as f32 * 2.0 - 1.0;
+                let (u, v) = (u * (1.0 + settings.curvature * v * v), v * (1.0 + settings.curvature * u * u));
+                if u.abs() > 1.0 || v.abs() > 1.0 {
+                    [0.0; 3]
+                } else {
+                    let sx = ((u + 1.0) / 2.0 * out_width as f32) as usize;
+                    let sy = ((v + 1.0) / 2.0 * out_height as f32) as usize;
+                    image[sy.min(out_height - 1) * out_width + sx.min(out_width - 1)]
+                }
+            } else {
+                image[y * out_width + x]
+            };
+            bytes.extend(pixel.iter().map(|c| c.clamp(0.0, 255.0) as u8));
+        }
+    }
+    bytes
+}
+
+fn blur(source: &[[f32; 3]], width: usize, height: usize) -> Vec<[f32; 3]> {
+    const KERNEL: [f32; 5] = [0.0625, 0.25, 0.375, 0.25, 0.0625];
+    let pass = |input: &[[f32; 3]], dx: isize, dy: isize| {
+        let mut output = vec![[0.0f32; 3]; input.len()];
+        for y in 0..height as isize {
+            for x in 0..width as isize {
+                let mut sum = [0.0; 3];
+                for (k, weight) in KERNEL.iter().enumerate() {
+                    let offset = k as isize - 2;
+                    let (sx, sy) = ((x + offset * dx).clamp(0, width as isize - 1), (y + offset * dy).clamp(0, height as isize - 1));
+                    let pixel = input[(sy * width as isize + sx) as usize];
+                    for c in 0..3 {
+                        sum[c] += pixel[c] * weight;
+                    }
+                }
+                output[(y * width as isize + x) as usize] = sum;
+            }
+        }
+        output
+    };
+    pass(&pass(source, 1, 0), 0, 1)
+}
+
+fn sample(image: &[[f32; 3]], width: usize, height: usize, x: f32, y: f32) -> [f32; 3] {
+    let (x, y) = (x.clamp(0.0, (width - 1) as f32), y.clamp(0.0, (height - 1) as f32));
+    let (x0, y0) = (x.floor() as usize, y.floor() as usize);
+    let (x1, y1) = ((x0 + 1).min(width - 1), (y0 + 1).min(height - 1));
+    let (fx, fy) = (x - x0 as f32, y - y0 as f32);
+    let mut result = [0.0; 3];
+    for (c, value) in result.iter_mut().enumerate() {
+        let top = image[y0 * width + x0][c] * (1.0 - fx) + image[y0 * width + x1][c] * fx;
+        let bottom = image[y1 * width + x0][c] * (1.0 - fx) + image[y1 * width + x1][c] * fx;
+        *value = top * (1.0 - fy) + bottom * fy;
+    }
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const WHITE: Rgb = (0xFF, 0xFF, 0xFF);
+    const BLACK: Rgb = (0x00, 0x00, 0x00);
+
+    // A 3x3 frame with only the centre lit.
+    fn dot() -> Vec<Rgb> {
+        let mut frame = vec![BLACK; 9];
+        frame[4] = WHITE;
+        frame
+    }
+
+    fn pixel(image: &[u8], width: usize, x: usize, y: usize) -> [u8; 3] {
+        let i = (y * width * CRT_SCALE + x) * 3;
+        [image[i], image[i + 1], image[i + 2]]
+    }
+
+    #[test]
+    fn off_only_upscales() {
+        let image = process(&dot(), 3, 3, &CrtSettings::preset("off").unwrap());
+        assert_eq!(image.len(), 3 * CRT_SCALE * 3 * CRT_SCALE * 3);
+        for y in 0..3 * CRT_SCALE {
+            for x in 0..3 * CRT_SCALE {
+                let lit = (CRT_SCALE..2 * CRT_SCALE).contains(&x) && (CRT_SCALE..2 * CRT_SCALE).contains(&y);
+                assert_eq!(pixel(&image, 3, x, y), if lit { [0xFF; 3] } else { [0; 3] });
+            }
+        }
+    }
+
+    #[test]
+    fn scanlines_darken_odd_rows() {
+        let image = process(&[WHITE], 1, 1, &CrtSettings::preset("scanlines").unwrap());
+        assert_eq!(pixel(&image, 1, 3, 2), [0xFF; 3]);
+        assert_eq!(pixel(&image, 1, 3, 3), [140; 3]);
+    }
+
+    #[test]
+    fn grid_darkens_cell_edges() {
+        let image = process(&[WHITE], 1, 1, &CrtSettings::preset("grid").unwrap());
+        assert_eq!(pixel(&image, 1, 0, 3), [127; 3]);
+        assert_eq!(pixel(&image, 1, 3, 0), [127; 3]);
+        assert_eq!(pixel(&image, 1, 3, 3), [0xFF; 3]);
+    }
+
+    #[test]
+    fn bloom_spills_onto_dark_neighbours() {
+        let settings = CrtSettings { scanlines: 0.0, grid: 0.0, bloom: 0.6, curvature: 0.0 };
+        let image = process(&dot(), 3, 3, &settings);
+        let near = pixel(&image, 3, CRT_SCALE - 1, CRT_SCALE + CRT_SCALE / 2);
+        let far = pixel(&image, 3, 0, CRT_SCALE + CRT_SCALE / 2);
+        assert!(near[0] > far[0] && far[0] > 0);
+        assert_eq!(pixel(&image, 3, CRT_SCALE + CRT_SCALE / 2, CRT_SCALE + CRT_SCALE / 2), [0xFF; 3]);
+    }
+
+    #[test]
+    fn curvature_blacks_out_the_corners() {
+        let settings = CrtSettings { scanlines: 0.0, grid: 0.0, bloom: 0.0, curvature: 0.2 };
+        let image = process(&[WHITE; 4], 2, 2, &settings);
+        assert_eq!(pixel(&image, 2, 0, 0), [0; 3]);
+        assert_eq!(pixel(&image, 2, 2 * CRT_SCALE - 1, 2 * CRT_SCALE - 1), [0; 3]);
+        assert_eq!(pixel(&image, 2, CRT_SCALE, CRT_SCALE), [0xFF; 3]);
+    }
+
+    #[test]
+    fn parses_config() {
+        assert_eq!(CrtSettings::parse_config("# start soft\n\n  soft \n"), Ok(Some("soft".to_string())));
+        assert_eq!(CrtSettings::parse_config("\n# nothing\n"), Ok(None));
+        assert!(CrtSettings::parse_config("vhs").is_err());
+    }
+}
@@ -0,0 +1,507 @@
+use std::collections::HashMap;
+use std::env;
+use std::fs;
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::net::TcpListener;
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{self, Receiver, Sender, TryRecvError};
+use std::thread;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use serde_json::{json, Value};
+
+use crate::coverage::LineMap;
+use crate::debugger::{Debugger, Stop};
+use crate::decompiler::Decompiler;
+use crate::frontend::NoDisplay;
+use crate::quirks::Quirks;
+use crate::Machine;
+
+const THREAD_ID: i64 = 1;
+const REGISTERS: i64 = 1;
+
+// Debug Adapter Protocol server for editors, on stdin/stdout or a localhost port. Launch arguments are
+// `program`, and optionally `lineMap` (a --line-map file; a decompiled listing is written to the temp
+// directory otherwise), `quirks`, `speed` and `stopOnEntry`. The program runs without a display.
+pub fn serve(port: Option<u16>, delay: Duration) -> io::Result<()> {
+    let (sender, messages) = mpsc::channel();
+    match port {
+        Some(port) => {
+            let listener = TcpListener::bind(("127.0.0.1", port))?;
+            eprintln!("Waiting for a DAP client on 127.0.0.1:{}.", listener.local_addr()?.port());
+            let (stream, _) = listener.accept()?;
+            let reader = stream.try_clone()?;
+            thread::spawn(move || read_messages(reader, sender));
+            Session::new(stream, delay).run(messages)
+        }
+        None => {
+            thread::spawn(move || read_messages(io::stdin(), sender));
+            Session::new(io::stdout(), delay).run(messages)
+        }
+    }
+}
+
+fn read_messages<R: Read>(reader: R, sender: Sender<Value>) {
+    let mut reader = BufReader::new(reader);
+    loop {
+        let mut length = None;
+        loop {
+            let mut header = String::new();
+            if reader.read_line(&mut header).unwrap_or(0) == 0 {
+                return;
+            }
+            match header.trim() {
+                "" => break,
+                header => if let Some(value) = header.strip_prefix("Content-Length:") {
+                    length = value.trim().parse().ok();
+                },
+            }
+        }
+        let Some(length) = length else { continue };
+        let mut body = vec![0; length];
+        if reader.read_exact(&mut body).is_err() {
+            return;
+        }
+        match serde_json::from_slice(&body) {
+            Ok(message) => if sender.send(message).is_err() {
+                return;
+            },
+            Err(e) => eprintln!("Malformed DAP message: {}", e),
+        }
+    }
+}
+
+struct Session<W: Write> {
+    out: W,
+    seq: i64,
+    delay: Duration,
+    debugger: Option<Debugger>,
+    lines: LineMap,
+    // Directory that relative paths in the line map are resolved against.
+    base: PathBuf,
+    stop_on_entry: bool,
+    breakpoints: HashMap<PathBuf, Vec<usize>>,
+    running: bool,
+    // Events to send once the response to the current request is out.
+    events: Vec<(&'static str, Value)>,
+}
+
+impl<W: Write> Session<W> {
+    fn new(out: W, delay: Duration) -> Self {
+        Session {
+            out,
+            seq: 0,
+            delay,
+            debugger: None,
+            lines: LineMap::default(),
+            base: PathBuf::new(),
+            stop_on_entry: false,
+            breakpoints: HashMap::new(),
+            running: false,
+            events: Vec::new(),
+        }
+    }
+
+    fn run(mut self, messages: Receiver<Value>) -> io::Result<()> {
+        loop {
+            let message = if self.running {
+                match messages.try_recv() {
+                    Ok(message) => Some(message),
+                    Err(TryRecvError::Empty) => None,
+                    Err(TryRecvError::Disconnected) => return Ok(()),
+                }
+            } else {
+                match messages.recv() {
+                    Ok(message) => Some(message),
+                    Err(_) => return Ok(()),
+                }
+            };
+            match message {
+                Some(request) => if !self.handle(&request)? {
+                    return Ok(());
+                },
+                None => self.advance()?,
+            }
+        }
+    }
+
+    fn advance(&mut self) -> io::Result<()> {
+        let Some(debugger) = &mut self.debugger else {
+            self.running = false;
+            return Ok(());
+        };
+        debugger.cycle(&mut NoDisplay);
+        match debugger.take_stop() {
+            Some(stop) => {
+                self.stopped(stop);
+                self.flush_events()?;
+            }
+            None if !self.delay.is_zero() => thread::sleep(self.delay),
+            None => {}
+        }
+        Ok(())
+    }
+
+    fn send(&mut self, mut message: Value) -> io::Result<()> {
+        self.seq += 1;
+        message["seq"] = json!(self.seq);
+        let body = message.to_string();
+        write!(self.out, "Content-Length: {}\r\n\r\n{}", body.len(), body)?;
+        self.out.flush()
+    }
+
+    fn flush_events(&mut self) -> io::Result<()> {
+        for (event, body) in std::mem::take(&mut self.events) {
+            self.send(json!({"type": "event", "event": event, "body": body}))?;
+        }
+        Ok(())
+    }
+
+    fn stopped(&mut self, stop: Stop) {
+        let (reason, text) = match stop {
+            Stop::Breakpoint(_) => ("breakpoint", None),
+            Stop::Watchpoint(_) => ("data breakpoint", None),
+            Stop::Halted => ("exception", Some("The machine halted.")),
+            Stop::Step => ("step", None),
+        };
+        self.pause(reason, text);
+    }
+
+    fn pause(&mut self, reason: &str, text: Option<&str>) {
+        self.running = false;
+        self.events.push(("stopped", json!({"reason": reason, "text": text, "threadId": THREAD_ID, "allThreadsStopped": true})));
+    }
+
+    // Returns false once the client has ended the session.
+    fn handle(&mut self, request: &Value) -> io::Result<bool> {
+        let command = request["command"].as_str().unwrap_or_default();
+        let arguments = &request["arguments"];
+        let result = match command {
+            "initialize" => Ok(json!({
+                "supportsConfigurationDoneRequest": true,
+                "supportsSetVariable": true,
+                "supportsReadMemoryRequest": true,
+            })),
+            "launch" => self.launch(arguments),
+            "setBreakpoints" => self.set_breakpoints(arguments),
+            "configurationDone" => self.configuration_done(),
+            "threads" => Ok(json!({"threads": [{"id": THREAD_ID, "name": "CHIP-8"}]})),
+            "stackTrace" => self.stack_trace(),
+            "scopes" => Ok(json!({"scopes": [{"name": "Registers", "variablesReference": REGISTERS, "expensive": false}]})),
+            "variables" => self.variables(arguments),
+            "setVariable" => self.set_variable(arguments),
+            "continue" => self.debugger().map(|debugger| debugger.set_paused(false)).map(|_| {
+                self.running = true;
+                json!({"allThreadsContinued": true})
+            }),
+            "next" => self.step(|debugger| {
+                debugger.step_over();
+                true
+            }),
+            "stepIn" => self.step(|debugger| {
+                debugger.step();
+                true
+            }),
+            "stepOut" => self.step(Debugger::step_out),
+            "pause" => self.debugger().map(|debugger| debugger.set_paused(true)).map(|_| {
+                self.pause("pause", None);
+                Value::Null
+            }),
+            "readMemory" => self.read_memory(arguments),
+            "disconnect" | "terminate" => Ok(Value::Null),
+            _ => Err(format!("Unsupported request {}.", command)),
+        };
+
+        let mut response = json!({"type": "response", "request_seq": request["seq"], "command": command, "success": result.is_ok()});
+        match result {
+            Ok(body) => response["body"] = body,
+            Err(message) => response["message"] = json!(message),
+        }
+        self.send(response)?;
+        if matches!(command, "disconnect" | "terminate") {
+            self.send(json!({"type": "event", "event": "terminated"}))?;
+            return Ok(false);
+        }
+        self.flush_events()?;
+        Ok(true)
+    }
+
+    fn debugger(&mut self) -> Result<&mut Debugger, String> {
+        self.debugger.as_mut().ok_or_else(|| "No program has been launched.".to_string())
+    }
+
+    fn machine(&self) -> Result<&Machine, String> {
+        self.debugger.as_ref().map(Debugger::machine).ok_or_else(|| "No program has been launched.".to_string())
+    }
+
+    fn launch(&mut self, arguments: &Value) -> Result<Value, String> {
+        let program = arguments["program"].as_str().ok_or("launch needs a program.")?;
+        let rom = fs::read(program).map_err(|e| format!("Could not read {}: {}", program, e))?;
+        let mut machine = Machine::new();
+        machine.load_program(&rom[..]);
+        machine.seed(SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |t| t.as_nanos() as u64));
+        if let Some(profile) = arguments["quirks"].as_str() {
+            machine.set_quirks(Quirks::profile(profile).ok_or(format!("Unknown quirk profile {}.", profile))?);
+        }
+        if let Some(speed) = arguments["speed"].as_u64() {
+            machine.set_speed(speed.clamp(1, 255) as u8);
+        }
+
+        match arguments["lineMap"].as_str() {
+            Some(path) => {
+                self.lines = LineMap::parse(&fs::read_to_string(path).map_err(|e| format!("Could not read {}: {}", path, e))?)?;
+                self.base = Path::new(path).parent().map(Path::to_path_buf).unwrap_or_default();
+            }
+            None => {
+                // Never next to the ROM, where it would overwrite the program's own source.
+                let name = Path::new(program).file_stem().map_or("program".into(), |stem| stem.to_string_lossy());
+                let listing_path = env::temp_dir().join(format!("rip8-{}.8o", name));
+                let (listing, lines) = Decompiler::new(&rom).decompile_with_lines();
+                fs::write(&listing_path, listing).map_err(|e| format!("Could not write {}: {}", listing_path.display(), e))?;
+                self.lines = LineMap::from_listing(&listing_path.to_string_lossy(), &lines);
+                self.base = PathBuf::new();
+            }
+        }
+
+        self.stop_on_entry = arguments["stopOnEntry"].as_bool().unwrap_or(false);
+        self.debugger = Some(Debugger::new(machine));
+        self.events.push(("initialized", Value::Null));
+        Ok(Value::Null)
+    }
+
+    fn configuration_done(&mut self) -> Result<Value, String> {
+        let stop_on_entry = self.stop_on_entry;
+        let debugger = self.debugger()?;
+        if stop_on_entry {
+            self.pause("entry", None);
+        } else {
+            debugger.set_paused(false);
+            self.running = true;
+        }
+        Ok(Value::Null)
+    }
+
+    fn source_path(&self, file: &str) -> PathBuf {
+        let path = self.base.join(file);
+        fs::canonicalize(&path).unwrap_or(path)
+    }
+
+    // Breakpoints on lines without code move to the next line that has some.
+    fn set_breakpoints(&mut self, arguments: &Value) -> Result<Value, String> {
+        let path = arguments["source"]["path"].as_str().ok_or("setBreakpoints needs a source path.")?;
+        let path = fs::canonicalize(path).unwrap_or_else(|_| PathBuf::from(path));
+        let code: Vec<(usize, usize)> = self.lines.entries()
+            .filter(|(_, file, _)| self.source_path(file) == path)
+            .map(|(address, _, line)| (line, address))
+            .collect();
+        let requested: Vec<usize> = arguments["breakpoints"].as_array()
+            .map(|breakpoints| breakpoints.iter().filter_map(|b| b["line"].as_u64()).map(|line| line as usize).collect())
+            .unwrap_or_default();
+
+        let old = self.breakpoints.remove(&path).unwrap_or_default();
+        let debugger = self.debugger()?;
+        for address in old {
+            debugger.clear_breakpoint(address);
+        }
+        let mut addresses = Vec::new();
+        let mut results = Vec::new();
+        for line in requested {
+            match code.iter().filter(|(code_line, _)| *code_line >= line).min() {
+                Some((line, address)) => {
+                    debugger.set_breakpoint(*address);
+                    addresses.push(*address);
+                    results.push(json!({"verified": true, "line": line}));
+                }
+                None => results.push(json!({"verified": false, "line": line, "message": "No code on or after this line."})),
+            }
+        }
+        self.breakpoints.insert(path, addresses);
+        Ok(json!({"breakpoints": results}))
+    }
+
+    // One frame for the current instruction and one per call on the stack, named like the decompiler's labels.
+    fn stack_trace(&self) -> Result<Value, String> {
+        let backtrace = self.debugger.as_ref().map(Debugger::backtrace).ok_or("No program has been launched.")?;
+        let frames: Vec<Value> = backtrace.iter().enumerate().map(|(id, (address, entry))| {
+            let name = entry.map_or("main".to_string(), |entry| format!("sub_{:03X}", entry));
+            let mut frame = json!({"id": id, "name": name, "line": 0, "column": 0, "instructionPointerReference": format!("0x{:03X}", address)});
+            if let Some((file, line)) = self.lines.location(*address) {
+                let path = self.source_path(file);
+                let name = path.file_name().map(|n| n.to_string_lossy().into_owned());
+                frame["source"] = json!({"name": name, "path": path});
+                frame["line"] = json!(line);
+                frame["column"] = json!(1);
+            }
+            frame
+        }).collect();
+        Ok(json!({"stackFrames": frames, "totalFrames": backtrace.len()}))
+    }
+
+    fn variables(&mut self, arguments: &Value) -> Result<Value, String> {
+        if arguments["variablesReference"].as_i64() != Some(REGISTERS) {
+            return Ok(json!({"variables": []}));
+        }
+        let machine = self.machine()?;
+        let variable = |name: String, value: String| json!({"name": name, "value": value, "variablesReference": 0});
+        let mut variables: Vec<Value> = machine.registers().iter().enumerate()
+            .map(|(x, value)| variable(format!("V{:X}", x), format!("0x{:02X}", value)))
+            .collect();
+        let mut index = variable("I".to_string(), format!("0x{:03X}", machine.index_register()));
+        index["memoryReference"] = json!(format!("0x{:03X}", machine.index_register()));
+        variables.push(index);
+        variables.push(variable("PC".to_string(), format!("0x{:03X}", machine.pc())));
+        variables.push(variable("SP".to_string(), machine.stack().len().to_string()));
+        variables.push(variable("DT".to_string(), format!("0x{:02X}", machine.delay_timer())));
+        variables.push(variable("ST".to_string(), format!("0x{:02X}", machine.sound_timer())));
+        Ok(json!({"variables": variables}))
+    }
+
+    fn set_variable(&mut self, arguments: &Value) -> Result<Value, String> {
+        let name = arguments["name"].as_str().unwrap_or_default().to_ascii_uppercase();
+        let text = arguments["value"].as_str().unwrap_or_default();
+        let value = parse_number(text).ok_or(format!("Malformed value {}.", text))?;
+        let byte = || u8::try_from(value).map_err(|_| format!("{} does not fit in a byte.", text));
+        let machine = self.debugger()?.machine_mut();
+        let shown = match name.as_str() {
+            "I" => {
+                machine.set_index_register(u16::try_from(value).map_err(|_| format!("{} does not fit in I.", text))?);
+                format!("0x{:03X}", value)
+            }
+            "PC" if value < 4096 => {
+                machine.set_pc(value);
+                format!("0x{:03X}", value)
+            }
+            "DT" => {
+                machine.set_delay_timer(byte()?);
+                format!("0x{:02X}", value)
+            }
+            "ST" => {
+                machine.set_sound_timer(byte()?);
+                format!("0x{:02X}", value)
+            }
+            register => match register.strip_prefix('V').and_then(|x| usize::from_str_radix(x, 16).ok()).filter(|x| *x < 16) {
+                Some(x) => {
+                    machine.set_register(x, byte()?);
+                    format!("0x{:02X}", value)
+                }
+                None => return Err(format!("{} cannot be changed.", name)),
+            },
+        };
+        Ok(json!({"value": shown}))
+    }
+
+    fn step(&mut self, start: impl FnOnce(&mut Debugger) -> bool) -> Result<Value, String> {
+        let debugger = self.debugger()?;
+        if !start(debugger) {
+            return Err("Not inside a subroutine.".to_string());
+        }
+        // A single step runs on the next tick; longer steps finish in the background.
+        if debugger.is_paused() {
+            debugger.cycle(&mut NoDisplay);
+            match debugger.take_stop() {
+                Some(stop) => self.stopped(stop),
+                None => self.pause("step", None),
+            }
+        } else {
+            self.running = true;
+        }
+        Ok(Value::Null)
+    }
+
+    fn read_memory(&mut self, arguments: &Value) -> Result<Value, String> {
+        let reference = arguments["memoryReference"].as_str().unwrap_or_default();
+        let address = parse_number(reference).ok_or(format!("Malformed memory reference {}.", reference))?;
+        let address = i64::try_from(address).unwrap_or(i64::MAX).saturating_add(arguments["offset"].as_i64().unwrap_or(0));
+        // Counts come straight from the client, so a huge one must not overflow.
+        let count = usize::try_from(arguments["count"].as_u64().unwrap_or(0)).unwrap_or(usize::MAX);
+        let memory = self.machine()?.memory();
+        let start = usize::try_from(address).unwrap_or(memory.len()).min(memory.len());
+        let data = &memory[start..memory.len().min(start.saturating_add(count))];
+        Ok(json!({"address": format!("0x{:03X}", start), "data": base64(data), "unreadableBytes": count.saturating_sub(data.len())}))
+    }
+}
+
+fn parse_number(text: &str) -> Option<usize> {
+    match text.strip_prefix("0x").or_else(|| text.strip_prefix("0X")) {
+        Some(hex) => usize::from_str_radix(hex, 16).ok(),
+        None => text.parse().ok(),
+    }
+}
+
+fn base64(data: &[u8]) -> String {
+    const ALPHABET: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+    let mut text = String::with_capacity(data.len().div_ceil(3) * 4);
+    for chunk in data.chunks(3) {
+        let bits = chunk.iter().enumerate().fold(0u32, |bits, (i, byte)| bits | (*byte as u32) << (16 - 8 * i));
+        for i in 0..4 {
+            if i <= chunk.len() {
+                text.push(ALPHABET[(bits >> (18 - 6 * i) & 0x3F) as usize] as char);
+            } else {
+                text.push('=');
+            }
+        }
+    }
+    text
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    fn frame(messages: &[Value]) -> Vec<u8> {
+        messages.iter().flat_map(|message| {
+            let body = message.to_string();
+            format!("Content-Length: {}\r\n\r\n{}", body.len(), body).into_bytes()
+        }).collect()
+    }
+
+    fn parse(bytes: Vec<u8>) -> Vec<Value> {
+        let (sender, messages) = mpsc::channel();
+        read_messages(Cursor::new(bytes), sender);
+        messages.try_iter().collect()
+    }
+
+    #[test]
+    fn launches_steps_and_inspects() {
+        // call 0x206; jump 0x202; ...; v1 := 7; return
+        let program = env::temp_dir().join(format!("rip8-dap-test-{}.ch8", std::process::id()));
+        fs::write(&program, [0x22, 0x06, 0x12, 0x02, 0x00, 0x00, 0x61, 0x07, 0x00, 0xEE]).unwrap();
+        let requests = [
+            json!({"seq": 1, "type": "request", "command": "initialize", "arguments": {}}),
+            json!({"seq": 2, "type": "request", "command": "launch", "arguments": {"program": program, "stopOnEntry": true}}),
+            json!({"seq": 3, "type": "request", "command": "configurationDone"}),
+            json!({"seq": 4, "type": "request", "command": "next"}),
+            json!({"seq": 5, "type": "request", "command": "variables", "arguments": {"variablesReference": REGISTERS}}),
+            json!({"seq": 6, "type": "request", "command": "readMemory", "arguments": {"memoryReference": "0x200", "count": 4}}),
+            json!({"seq": 7, "type": "request", "command": "stepOut"}),
+            json!({"seq": 8, "type": "request", "command": "disconnect"}),
+        ];
+
+        let mut session = Session::new(Vec::new(), Duration::ZERO);
+        for request in parse(frame(&requests)) {
+            let open = session.handle(&request).unwrap();
+            while session.running {
+                session.advance().unwrap();
+            }
+            if !open {
+                break;
+            }
+        }
+        fs::remove_file(&program).unwrap();
+        let _ = fs::remove_file(env::temp_dir().join(format!("rip8-rip8-dap-test-{}.8o", std::process::id())));
+
+        let messages = parse(session.out);
+        let response = |seq: i64| messages.iter().find(|m| m["type"] == "response" && m["request_seq"] == seq).unwrap();
+        let stops: Vec<&Value> = messages.iter().filter(|m| m["event"] == "stopped").map(|m| &m["body"]["reason"]).collect();
+        assert!((1..=8).all(|seq| response(seq)["success"] == (seq != 7)));
+        assert_eq!(response(6)["body"]["data"], "IgYSAg==");
+        let variables = response(5)["body"]["variables"].as_array().unwrap();
+        let variable = |name: &str| variables.iter().find(|v| v["name"] == name).unwrap()["value"].clone();
+        assert_eq!(variable("PC"), "0x202");
+        assert_eq!(variable("V1"), "0x07");
+        assert_eq!(response(7)["message"], "Not inside a subroutine.");
+        assert_eq!(stops, ["entry", "step"]);
+        assert_eq!(messages.last().unwrap()["event"], "terminated");
+    }
+}
@@ -1,117 +1,503 @@
-use sdl2::gfx::primitives::DrawRenderer;
-use sdl2::pixels::Color;
-use sdl2::render::Canvas;
-use sdl2::video::Window;
+use std::collections::BTreeSet;
+
+use crate::decompiler::Decompiler;
+use crate::display::{DisplayFilter, FilterMode};
+use crate::frontend::{Display, MemoryAction};
+use crate::memory_view::MemoryView;
+use crate::palette::Palette;
+#[cfg(feature = "script")]
+use crate::script::Script;
 use crate::Machine;
 
-pub(crate) struct Debugger {
+// Why the debugger paused the machine on its own.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum Stop {
+    Breakpoint(usize),
+    // The watched address that was written.
+    Watchpoint(usize),
+    Halted,
+    // A step over or step out finished.
+    Step,
+}
+
+// Where a step over or step out runs to.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+enum Target {
+    // An address, at a given stack depth if any, so recursive calls do not end a step over early.
+    Address(usize, Option<usize>),
+    // The stack shrinking below a depth.
+    Return(usize),
+}
+
+pub struct Debugger {
     active : bool,
     divider : u8,
     counter : u8,
     machine : Machine,
-    remaining_steps : u8
+    remaining_steps : u8,
+    filter : DisplayFilter,
+    palettes : Vec<Palette>,
+    palette : usize,
+    breakpoints : BTreeSet<usize>,
+    watchpoints : Vec<(usize, usize)>,
+    stop : Option<Stop>,
+    target : Option<Target>,
+    // The instruction picked in the debug window for run to cursor; follows pc when unset.
+    cursor : Option<usize>,
+    memory_view : MemoryView,
+    #[cfg(feature = "script")]
+    script : Option<Script>,
 }
 
 impl Debugger {
     pub fn new(machine : Machine) -> Self {
+        let memory_view = MemoryView::new(machine.memory.len());
         Debugger {
             active : false,
             divider : 1,
             counter: 0,
             remaining_steps: 0,
-            machine
+            machine,
+            filter: DisplayFilter::new(FilterMode::None),
+            palettes: Palette::builtin(),
+            palette: 0,
+            breakpoints: BTreeSet::new(),
+            watchpoints: Vec::new(),
+            stop: None,
+            target: None,
+            cursor: None,
+            memory_view,
+            #[cfg(feature = "script")]
+            script: None,
         }
     }
 
+    pub fn add_palettes(&mut self, palettes: Vec<Palette>) {
+        for palette in palettes {
+            match self.palettes.iter_mut().find(|p| p.name == palette.name) {
+                Some(existing) => *existing = palette,
+                None => self.palettes.push(palette),
+            }
+        }
+    }
+
+    pub fn select_palette(&mut self, name: &str) -> bool {
+        match self.palettes.iter().position(|p| p.name == name) {
+            Some(index) => {
+                self.palette = index;
+                self.machine.draw_flag = true;
+                true
+            }
+            None => false,
+        }
+    }
+
+    pub fn redraw(&mut self) {
+        self.machine.draw_flag = true;
+    }
+
+    pub fn next_palette(&mut self) {
+        self.palette = (self.palette + 1) % self.palettes.len();
+        self.machine.draw_flag = true;
+    }
+
+    pub fn set_filter(&mut self, mode: FilterMode) {
+        self.filter.set_mode(mode);
+    }
+
+    pub fn next_filter(&mut self) {
+        self.filter.next_mode();
+    }
+
     pub fn toggle_pause(&mut self) {
         self.active = !self.active;
+        self.target = None;
+    }
+
+    pub fn set_paused(&mut self, paused: bool) {
+        self.active = !paused;
+        self.target = None;
+    }
+
+    // Execution pauses before running the instruction at a breakpoint.
+    pub fn set_breakpoint(&mut self, address: usize) {
+        self.breakpoints.insert(address);
+    }
+
+    pub fn clear_breakpoint(&mut self, address: usize) -> bool {
+        self.breakpoints.remove(&address)
+    }
+
+    pub fn breakpoints(&self) -> &BTreeSet<usize> {
+        &self.breakpoints
     }
 
-    pub(crate) fn key_pressed(&mut self, key: usize) {
+    // Execution pauses after an instruction writes any of `length` bytes from `start`.
+    pub fn set_watchpoint(&mut self, start: usize, length: usize) {
+        if !self.watchpoints.contains(&(start, length)) {
+            self.watchpoints.push((start, length));
+        }
+    }
+
+    pub fn clear_watchpoint(&mut self, start: usize, length: usize) -> bool {
+        let count = self.watchpoints.len();
+        self.watchpoints.retain(|w| *w != (start, length));
+        self.watchpoints.len() != count
+    }
+
+    pub fn watchpoints(&self) -> &[(usize, usize)] {
+        &self.watchpoints
+    }
+
+    // The reason for the last automatic pause, cleared once read.
+    pub fn take_stop(&mut self) -> Option<Stop> {
+        self.stop.take()
+    }
+
+    pub fn key_pressed(&mut self, key: usize) {
         if self.active {
             self.machine.key_pressed(key);
+            #[cfg(feature = "script")]
+            if let Some(script) = &mut self.script {
+                script.key_event(&mut self.machine, key, true);
+            }
         }
     }
 
-    pub(crate) fn key_released(&mut self, key: usize) {
+    pub fn key_released(&mut self, key: usize) {
         if self.active {
             self.machine.key_released(key);
+            #[cfg(feature = "script")]
+            if let Some(script) = &mut self.script {
+                script.key_event(&mut self.machine, key, false);
+            }
         }
     }
 
+    // Runs the script's top level straight away; its hooks fire from then on.
+    #[cfg(feature = "script")]
+    pub fn set_script(&mut self, mut script: Script) {
+        script.start(&mut self.machine);
+        self.script = Some(script);
+    }
+
+    pub fn quit_requested(&self) -> bool {
+        #[cfg(feature = "script")]
+        if let Some(script) = &self.script {
+            return script.quit_requested();
+        }
+        false
+    }
+
+    #[cfg(feature = "script")]
+    pub fn script_error(&self) -> Option<String> {
+        self.script.as_ref().and_then(Script::error)
+    }
+
+    pub fn filter(&self) -> FilterMode {
+        self.filter.mode()
+    }
+
+    pub fn palette(&self) -> &Palette {
+        &self.palettes[self.palette]
+    }
+
+    pub fn machine(&self) -> &Machine {
+        &self.machine
+    }
+
+    // Callers may change anything, so the screen is redrawn.
+    pub fn machine_mut(&mut self) -> &mut Machine {
+        self.machine.draw_flag = true;
+        &mut self.machine
+    }
+
     pub fn step(&mut self) {
         self.remaining_steps += 1;
     }
 
-    pub fn cycle(&mut self, canvas : &mut Canvas<Window>, dbg_canvas : Option<&mut Canvas<Window>>) {
+    // Runs a 2NNN call through to the instruction after it; any other instruction is a single step.
+    pub fn step_over(&mut self) {
+        let pc = self.machine.pc.min(self.machine.memory.len() - 2);
+        if self.machine.memory[pc] & 0xF0 == 0x20 && !self.machine.waiting_for_key() {
+            self.run_until(Target::Address(pc + 2, Some(self.machine.stack.len())));
+        } else {
+            self.step();
+        }
+    }
+
+    // Runs until the current subroutine returns; false at the top level, where there is nothing to return from.
+    pub fn step_out(&mut self) -> bool {
+        match self.machine.stack.len() {
+            0 => false,
+            depth => {
+                self.run_until(Target::Return(depth));
+                true
+            }
+        }
+    }
+
+    // The current instruction and then each call site on the stack, innermost first, with the
+    // subroutine each one is in; the outermost frame has none.
+    pub fn backtrace(&self) -> Vec<(usize, Option<usize>)> {
+        let memory = &self.machine.memory;
+        let mut sites = vec![self.machine.pc];
+        sites.extend(self.machine.stack.iter().rev().map(|ret| (*ret as usize).saturating_sub(2)));
+        let entries: Vec<Option<usize>> = sites.iter().skip(1)
+            .map(|call| Some((memory[*call] as usize & 0x0F) << 8 | memory[*call + 1] as usize))
+            .chain([None])
+            .collect();
+        sites.into_iter().zip(entries).collect()
+    }
+
+    // Runs until pc reaches the address at any depth, or something else stops the machine first.
+    pub fn run_to(&mut self, address: usize) {
+        self.run_until(Target::Address(address, None));
+    }
+
+    pub fn cursor(&self) -> usize {
+        self.cursor.unwrap_or(self.machine.pc)
+    }
+
+    // Moves the cursor by whole instructions, starting from pc if it was following it.
+    pub fn move_cursor(&mut self, instructions: isize) {
+        let address = self.cursor() as isize + instructions * 2;
+        self.cursor = Some(address.clamp(0, self.machine.memory.len() as isize - 2) as usize);
+    }
+
+    pub fn reset_cursor(&mut self) {
+        self.cursor = None;
+    }
+
+    pub fn run_to_cursor(&mut self) {
+        self.run_to(self.cursor());
+    }
+
+    pub fn memory_view(&self) -> &MemoryView {
+        &self.memory_view
+    }
+
+    pub fn memory_action(&mut self, action: MemoryAction) {
+        self.memory_view.handle(action, &mut self.machine, !self.active);
+    }
+
+    fn run_until(&mut self, target: Target) {
+        self.active = true;
+        self.target = Some(target);
+    }
+
+    pub fn is_paused(&self) -> bool {
+        !self.active
+    }
+
+    // Runs one machine cycle if the debugger is running or a step is pending.
+    pub fn tick(&mut self) -> bool {
         if self.active {
             self.counter += 1;
             if self.counter == self.divider {
                 self.counter = 0;
-                self.machine_cycle(canvas, dbg_canvas);
+                self.run_cycle();
+                return true;
             }
         } else if self.remaining_steps > 0 {
             self.remaining_steps -= 1;
-            self.machine_cycle(canvas, dbg_canvas);
+            self.run_cycle();
+            return true;
         }
+        false
     }
 
-    fn machine_cycle(&mut self, canvas : &mut Canvas<Window>, dbg_canvas : Option<&mut Canvas<Window>>) {
+    fn run_cycle(&mut self) {
+        let (halted, frames) = (self.machine.halted(), self.machine.frames());
+        self.execute();
+        if self.machine.frames() != frames {
+            self.filter.sample(&self.machine.screen);
+        }
+        self.memory_view.record(&self.machine);
+        let stop = if self.machine.halted() && !halted {
+            Some(Stop::Halted)
+        } else if let Some(address) = self.watched_write() {
+            Some(Stop::Watchpoint(address))
+        } else if self.breakpoints.contains(&self.machine.pc) {
+            Some(Stop::Breakpoint(self.machine.pc))
+        } else if self.target.is_some_and(|target| self.reached(target)) {
+            Some(Stop::Step)
+        } else {
+            None
+        };
+        if stop.is_some() {
+            self.stop = stop;
+            self.active = false;
+            self.remaining_steps = 0;
+            self.target = None;
+        }
+    }
+
+    fn execute(&mut self) {
+        #[cfg(feature = "script")]
+        if let Some(script) = &mut self.script {
+            script.before_cycle(&mut self.machine);
+            let frames = self.machine.frames();
+            self.machine.cycle();
+            script.after_cycle(&mut self.machine, frames);
+            return;
+        }
         self.machine.cycle();
+    }
 
-        if self.machine.draw_flag {
-            canvas.set_draw_color(Color::RGB(0, 0, 0));
-            canvas.clear();
-            for x in 0..64 {
-                for y in 0..32 {
-                    if self.machine.screen[y * 64 + x] {
-                        canvas.set_draw_color(Color::RGB(255, 255, 255));
-                        let rect = sdl2::rect::Rect::new((x * 16) as i32, (y * 16) as i32, 16, 16);
-                        canvas.fill_rect(rect).expect("Failed to draw");
-                    }
-                }
-            }
-            canvas.present();
+    fn reached(&self, target: Target) -> bool {
+        let depth = self.machine.stack.len();
+        match target {
+            Target::Address(address, at_depth) => self.machine.pc == address && at_depth.is_none_or(|at_depth| depth == at_depth),
+            Target::Return(from_depth) => depth < from_depth,
+        }
+    }
 
-            self.machine.draw_flag = false;
+    fn watched_write(&self) -> Option<usize> {
+        let (start, length) = self.machine.last_write?;
+        (start..start + length).map(|a| a % 4096).find(|address| {
+            self.watchpoints.iter().any(|(first, length)| (*first..first + length).contains(address))
+        })
+    }
+
+    // The filtered frame and palette to show, if the screen needs redrawing.
+    pub fn frame(&mut self) -> Option<(&[f32; 64 * 32], &Palette)> {
+        if self.machine.draw_flag || self.filter.changed() {
             self.machine.draw_complete();
+            Some((self.filter.apply(&self.machine.screen), &self.palettes[self.palette]))
+        } else {
+            None
+        }
+    }
+
+    // The debug panel as plain text, for frontends without a canvas.
+    pub fn status(&self) -> Vec<String> {
+        let registers: Vec<String> = self.machine.registers.iter().map(|x| format!("{:02X}", x)).collect();
+        let stack: Vec<String> = self.machine.stack.iter().map(|x| format!("0x{:04X}", x)).collect();
+        let pc = self.machine.pc.min(self.machine.memory.len() - 2);
+        let opcode = (self.machine.memory[pc] as u16) << 8 | self.machine.memory[pc + 1] as u16;
+        let cursor = self.cursor().min(self.machine.memory.len() - 2);
+        let cursor_opcode = (self.machine.memory[cursor] as u16) << 8 | self.machine.memory[cursor + 1] as u16;
+        let mut lines = vec![
+            format!("REGISTERS  {}", registers.join(" ")),
+            format!("PC/OPCODE  0x{:04X}  0x{:04X}  {}", self.machine.pc, opcode, if self.active { "" } else { "PAUSED" }),
+            format!("STACK      {}", stack.join(", ")),
+            format!("CURSOR     0x{:04X}  {}", cursor, Decompiler::disassemble(cursor_opcode)),
+            format!("INDEX REG  0x{:04X}  DELAY 0x{:02X}  SOUND 0x{:02X}", self.machine.index_register, self.machine.delay_timer, self.machine.sound_timer),
+            format!("FILTER     {}  PALETTE {}", self.filter.mode().name().to_uppercase(), self.palettes[self.palette].name.to_uppercase()),
+        ];
+        if let Some(profiler) = &self.machine.profiler {
+            let spots: Vec<String> = profiler.hot_spots(6).iter().map(|(addr, count)| format!("0x{:03X}:{}", addr, count)).collect();
+            lines.push(format!("HOT SPOTS  {}", spots.join(" ")));
         }
+        lines
+    }
 
+    // Also draws after a redraw request while paused, so edits to a stopped machine show up.
+    pub fn cycle<D: Display>(&mut self, display: &mut D) {
+        if self.tick() || self.machine.draw_flag {
+            if let Some((intensity, palette)) = self.frame() {
+                display.draw(intensity, palette);
+            }
+            display.draw_status(self);
+        }
+    }
+}
 
-        if let Some(dbg_canvas) = dbg_canvas {
-            dbg_canvas.set_draw_color(Color::RGB(0, 0, 0));
-            dbg_canvas.clear();
-            let data_x = "REGISTERS".len() as i16 * 8 + 16;
+#[cfg(test)]
+mod tests {
+    use super::*;
 
-            let draw_string = |x: i16, y: i16, text: String| {
-                for (i, c) in text.chars().enumerate() {
-                    dbg_canvas.character(x + i as i16 * 8, y, c, Color::RGB(255, 255, 255)).expect("Failed to draw");
-                }
-            };
+    // call 0x206; v3 := 1; jump 0x204
+    // 0x206: v0 += 1; call 0x20E; v1 += 1; return
+    // 0x20E: v2 += 1; return
+    const NESTED: [u8; 18] = [0x22, 0x06, 0x63, 0x01, 0x12, 0x04, 0x70, 0x01, 0x22, 0x0E, 0x71, 0x01, 0x00, 0xEE, 0x72, 0x01, 0x00, 0xEE];
 
-            draw_string(0, 10, "REGISTERS".to_string());
+    fn debugger(program: &[u8]) -> Debugger {
+        let mut machine = Machine::new();
+        machine.load_program(program);
+        Debugger::new(machine)
+    }
 
-            for (i, x) in self.machine.registers.iter().enumerate() {
-                draw_string(data_x+i as i16*32, 10, format!("{:02X}", x));
+    // Ticks until the debugger has nothing left to run, and returns why it stopped.
+    fn settle(debugger: &mut Debugger) -> Option<Stop> {
+        for _ in 0..1000 {
+            if !debugger.tick() {
+                break;
             }
+        }
+        debugger.take_stop()
+    }
 
-            draw_string(0, 20, "PC/OPCODE".to_string());
-            draw_string(data_x, 20, format!("0x{:04X}  0x{:04X}", self.machine.pc, (self.machine.memory[self.machine.pc] as u16) << 8 | self.machine.memory[self.machine.pc + 1] as u16));
+    fn step_to(debugger: &mut Debugger, address: usize) {
+        while debugger.machine().pc() != address {
+            debugger.step();
+            settle(debugger);
+        }
+    }
 
-            draw_string(0, 30, "STACK".to_string());
-            for (i, x) in (&self.machine.stack).iter().enumerate() {
-                draw_string(data_x+i as i16*64, 30, format!("0x{:04X}, ", x));
-            }
+    #[test]
+    fn step_over_runs_nested_calls() {
+        let mut debugger = debugger(&NESTED);
+        debugger.step_over();
+        assert_eq!(settle(&mut debugger), Some(Stop::Step));
+        assert_eq!(debugger.machine().pc(), 0x202);
+        assert!(debugger.machine().stack().is_empty());
+        assert_eq!(debugger.machine().registers()[..3], [1, 1, 1]);
+
+        // Anything but a call is a single step.
+        debugger.step_over();
+        assert_eq!(settle(&mut debugger), None);
+        assert_eq!(debugger.machine().pc(), 0x204);
+    }
 
-            draw_string(0, 40, "INDEX REG".to_string());
-            draw_string(data_x, 40, format!("0x{:04X}", self.machine.index_register));
+    #[test]
+    fn step_out_returns_one_level_at_a_time() {
+        let mut debugger = debugger(&NESTED);
+        assert!(!debugger.step_out());
+        step_to(&mut debugger, 0x20E);
+        assert_eq!(debugger.machine().stack().len(), 2);
+        assert!(debugger.step_out());
+        assert_eq!(settle(&mut debugger), Some(Stop::Step));
+        assert_eq!(debugger.machine().pc(), 0x20A);
+        assert_eq!(debugger.machine().registers()[1..3], [0, 1]);
+        assert!(debugger.step_out());
+        assert_eq!(settle(&mut debugger), Some(Stop::Step));
+        assert_eq!(debugger.machine().pc(), 0x202);
+        assert!(debugger.machine().stack().is_empty());
+    }
 
-            draw_string(20*8, 40, "DELAY".to_string());
-            draw_string(data_x+20*8, 40, format!("0x{:02X}", self.machine.delay_timer));
+    #[test]
+    fn breakpoints_in_the_callee_stop_a_step_over() {
+        let mut debugger = debugger(&NESTED);
+        debugger.set_breakpoint(0x20E);
+        debugger.step_over();
+        assert_eq!(settle(&mut debugger), Some(Stop::Breakpoint(0x20E)));
+        assert_eq!(debugger.machine().stack().len(), 2);
+        // The abandoned step over does not resume.
+        assert_eq!(settle(&mut debugger), None);
+        assert_eq!(debugger.machine().pc(), 0x20E);
+    }
 
-            draw_string(40*8, 40, "SOUND".to_string());
-            draw_string(data_x+40*8, 40, format!("0x{:02X}", self.machine.sound_timer));
-            dbg_canvas.present();
-        }
+    #[test]
+    fn step_over_a_recursive_call_waits_for_the_same_depth() {
+        // v0 := 3; call 0x206; jump 0x204
+        // 0x206: if v0 != 0 then jump 0x20C; return; v0 -= 1; call 0x206; v1 += 1; return
+        let mut debugger = debugger(&[0x60, 0x03, 0x22, 0x06, 0x12, 0x04, 0x30, 0x00, 0x12, 0x0C, 0x00, 0xEE, 0x70, 0xFF, 0x22, 0x06, 0x71, 0x01, 0x00, 0xEE]);
+        step_to(&mut debugger, 0x20E);
+        debugger.step_over();
+        assert_eq!(settle(&mut debugger), Some(Stop::Step));
+        assert_eq!(debugger.machine().pc(), 0x210);
+        assert_eq!(debugger.machine().stack().len(), 1);
+        assert_eq!(debugger.machine().registers()[1], 2);
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn run_to_stops_at_any_depth() {
+        let mut debugger = debugger(&NESTED);
+        debugger.run_to(0x20A);
+        assert_eq!(settle(&mut debugger), Some(Stop::Step));
+        assert_eq!(debugger.machine().stack().len(), 1);
+        assert_eq!(debugger.machine().registers()[2], 1);
+    }
+}
@@ -0,0 +1,513 @@
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::fmt::Write;
+
+use crate::OpCode;
+
+const BASE: usize = 0x200;
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+enum Item {
+    Code(OpCode),
+    Byte(u8),
+}
+
+struct Label {
+    name: String,
+    references: usize,
+}
+
+pub struct Decompiler {
+    items: Vec<(usize, Item)>,
+    index: HashMap<usize, usize>,
+    labels: HashMap<usize, Label>,
+    end: usize,
+    output: String,
+    lines: usize,
+    line_map: BTreeMap<usize, usize>,
+}
+
+impl Decompiler {
+    pub fn new(rom: &[u8]) -> Self {
+        let code = Self::discover(rom);
+
+        let mut items = Vec::new();
+        let mut index = HashMap::new();
+        let mut offset = 0;
+        while offset < rom.len() {
+            let addr = BASE + offset;
+            index.insert(addr, items.len());
+            if code.contains(&addr) {
+                items.push((addr, Item::Code((rom[offset] as u16) << 8 | rom[offset + 1] as u16)));
+                offset += 2;
+            } else {
+                items.push((addr, Item::Byte(rom[offset])));
+                offset += 1;
+            }
+        }
+        index.insert(BASE + rom.len(), items.len());
+
+        let mut decompiler = Decompiler {
+            items,
+            index,
+            labels: HashMap::new(),
+            end: BASE + rom.len(),
+            output: String::new(),
+            lines: 0,
+            line_map: BTreeMap::new(),
+        };
+        decompiler.collect_labels();
+        decompiler
+    }
+
+    fn discover(rom: &[u8]) -> BTreeSet<usize> {
+        let end = BASE + rom.len();
+        let mut covered: HashMap<usize, usize> = HashMap::new();
+        let mut code = BTreeSet::new();
+        let mut pending = vec![BASE];
+
+        while let Some(addr) = pending.pop() {
+            if addr < BASE || addr + 1 >= end {
+                continue;
+            }
+            match (covered.get(&addr), covered.get(&(addr + 1))) {
+                (Some(&start), _) if start == addr => continue,
+                (Some(_), _) | (_, Some(_)) => continue,
+                _ => {}
+            }
+            covered.insert(addr, addr);
+            covered.insert(addr + 1, addr);
+            code.insert(addr);
+
+            let offset = addr - BASE;
+            let opcode = (rom[offset] as u16) << 8 | rom[offset + 1] as u16;
+            let target = (opcode & 0x0FFF) as usize;
+            match opcode & 0xF000 {
+                0x0000 if opcode == 0x00E0 => pending.push(addr + 2),
+                0x0000 | 0xB000 => {}
+                0x1000 => pending.push(target),
+                0x2000 => {
+                    pending.push(target);
+                    pending.push(addr + 2);
+                }
+                _ if Self::skip_condition(opcode).is_some() => {
+                    pending.push(addr + 2);
+                    pending.push(addr + 4);
+                }
+                _ => pending.push(addr + 2),
+            }
+        }
+        code
+    }
+
+    fn collect_labels(&mut self) {
+        let mut references: HashMap<usize, (usize, &str)> = HashMap::new();
+        for (_, item) in &self.items {
+            if let Item::Code(opcode) = *item {
+                let target = (opcode & 0x0FFF) as usize;
+                let kind = match opcode & 0xF000 {
+                    0x1000 => "label",
+                    0x2000 => "sub",
+                    0xA000 => "data",
+                    _ => continue,
+                };
+                if target < BASE || !self.index.contains_key(&target) {
+                    continue;
+                }
+                let entry = references.entry(target).or_insert((0, kind));
+                entry.0 += 1;
+                if kind == "sub" || (kind == "label" && entry.1 == "data") {
+                    entry.1 = kind;
+                }
+            }
+        }
+
+        for (addr, (count, kind)) in references {
+            let name = if addr == BASE { "main".to_string() } else { format!("{}_{:03X}", kind, addr) };
+            self.labels.insert(addr, Label { name, references: count });
+        }
+        self.labels.entry(BASE).or_insert(Label { name: "main".to_string(), references: 0 }).references += 1;
+    }
+
+    pub fn decompile(self) -> String {
+        self.decompile_with_lines().0
+    }
+
+    pub fn decompile_with_lines(mut self) -> (String, BTreeMap<usize, usize>) {
+        self.emit_range(0, self.items.len(), 0, false);
+        (self.output, self.line_map)
+    }
+
+    fn emit_range(&mut self, start: usize, end: usize, indent: usize, head_done: bool) {
+        let mut i = start;
+        while i < end {
+            let (addr, item) = self.items[i];
+            let head = head_done && i == start;
+
+            if !head {
+                if let Some(j) = self.find_loop(i, end) {
+                    self.release(addr);
+                    self.emit_label(addr, indent);
+                    self.line(indent, "loop");
+                    let condition = match self.items[j - 1] {
+                        (skip, Item::Code(opcode)) if j - 1 > i && !self.is_referenced(skip) && !self.is_referenced(self.items[j].0) => Self::skip_condition(opcode),
+                        _ => None,
+                    };
+                    match condition {
+                        Some(condition) => {
+                            self.emit_range(i, j - 1, indent + 1, true);
+                            self.mapped(indent + 1, &format!("if {} then", Self::negate(&condition)), &[self.items[j - 1].0]);
+                        }
+                        None => {
+                            self.emit_range(i, j, indent + 1, true);
+                            self.emit_label(self.items[j].0, indent + 1);
+                        }
+                    }
+                    self.mapped(indent, "again", &[self.items[j].0]);
+                    i = j + 1;
+                    continue;
+                }
+                self.emit_label(addr, indent);
+            }
+
+            let opcode = match item {
+                Item::Code(opcode) => opcode,
+                Item::Byte(_) => {
+                    let mut bytes = Vec::new();
+                    while i < end && bytes.len() < 8 {
+                        match self.items[i] {
+                            (addr, Item::Byte(b)) if bytes.is_empty() || !self.is_referenced(addr) => bytes.push(b),
+                            _ => break,
+                        }
+                        i += 1;
+                    }
+                    let text = bytes.iter().map(|b| format!("0x{:02X}", b)).collect::<Vec<_>>().join(" ");
+                    self.line(indent, &text);
+                    continue;
+                }
+            };
+
+            if let Some(condition) = Self::skip_condition(opcode) {
+                if let Some(next) = self.emit_if(i, end, indent, &condition) {
+                    i = next;
+                    continue;
+                }
+                let plain = i + 1 < end
+                    && matches!(self.items[i + 1].1, Item::Code(_))
+                    && !self.is_referenced(self.items[i + 1].0)
+                    && self.find_loop(i + 1, end).is_none();
+                if plain {
+                    let negated = Self::negate(&condition);
+                    self.mapped(indent, &format!("if {} then", negated), &[addr]);
+                    let statement = Self::statement(&self.labels, self.code(i + 1));
+                    self.mapped(indent + 1, &statement, &[self.items[i + 1].0]);
+                    i += 2;
+                    continue;
+                }
+                self.mapped(indent, &Self::raw(opcode), &[addr]);
+                i += 1;
+                continue;
+            }
+
+            let statement = Self::statement(&self.labels, opcode);
+            self.mapped(indent, &statement, &[addr]);
+            i += 1;
+        }
+    }
+
+    fn emit_if(&mut self, i: usize, end: usize, indent: usize, condition: &str) -> Option<usize> {
+        let (jump_addr, _) = self.items.get(i + 1).copied()?;
+        let then_end = self.forward_jump(i + 1, end)?;
+        if then_end <= i + 2 || !self.all_code(i + 2, then_end) || self.is_referenced(jump_addr) {
+            return None;
+        }
+
+        let guarded = Self::skip_condition(self.code(then_end - 2)).is_some();
+        let else_end = if then_end - 1 > i + 2 && !guarded {
+            self.forward_jump(then_end - 1, end)
+                .filter(|&e| e > then_end && self.all_code(then_end, e))
+        } else {
+            None
+        };
+
+        self.release(self.address(then_end));
+        self.mapped(indent, &format!("if {} begin", condition), &[self.items[i].0, jump_addr]);
+        match else_end {
+            Some(else_end) => {
+                self.release(self.address(else_end));
+                self.emit_range(i + 2, then_end - 1, indent + 1, false);
+                self.emit_label(self.items[then_end - 1].0, indent + 1);
+                self.mapped(indent, "else", &[self.items[then_end - 1].0]);
+                self.emit_range(then_end, else_end, indent + 1, false);
+                self.line(indent, "end");
+                Some(else_end)
+            }
+            None => {
+                self.emit_range(i + 2, then_end, indent + 1, false);
+                self.line(indent, "end");
+                Some(then_end)
+            }
+        }
+    }
+
+    fn address(&self, i: usize) -> usize {
+        self.items.get(i).map_or(self.end, |(addr, _)| *addr)
+    }
+
+    fn forward_jump(&self, i: usize, end: usize) -> Option<usize> {
+        match self.items.get(i)? {
+            (addr, Item::Code(opcode)) if opcode & 0xF000 == 0x1000 => {
+                let target = (opcode & 0x0FFF) as usize;
+                let index = *self.index.get(&target)?;
+                (target > *addr && index <= end).then_some(index)
+            }
+            _ => None,
+        }
+    }
+
+    fn find_loop(&self, i: usize, end: usize) -> Option<usize> {
+        let addr = match self.items[i] {
+            (addr, Item::Code(_)) => addr,
+            (_, Item::Byte(_)) => return None,
+        };
+        let mut found = None;
+        for j in i + 1..end {
+            match self.items[j].1 {
+                Item::Code(opcode) if opcode & 0xF000 == 0x1000 && (opcode & 0x0FFF) as usize == addr => found = Some(j),
+                Item::Code(_) => {}
+                Item::Byte(_) => break,
+            }
+        }
+        found
+    }
+
+    fn all_code(&self, start: usize, end: usize) -> bool {
+        self.items[start..end].iter().all(|(_, item)| matches!(item, Item::Code(_)))
+    }
+
+    fn code(&self, i: usize) -> OpCode {
+        match self.items[i].1 {
+            Item::Code(opcode) => opcode,
+            Item::Byte(_) => unreachable!(),
+        }
+    }
+
+    fn is_referenced(&self, addr: usize) -> bool {
+        self.labels.get(&addr).is_some_and(|label| label.references > 0)
+    }
+
+    fn release(&mut self, addr: usize) {
+        if let Some(label) = self.labels.get_mut(&addr) {
+            label.references = label.references.saturating_sub(1);
+        }
+    }
+
+    fn emit_label(&mut self, addr: usize, indent: usize) {
+        if !self.is_referenced(addr) {
+            return;
+        }
+        let name = self.labels[&addr].name.clone();
+        if indent == 0 && name != "main" && name.starts_with("sub") {
+            self.output.push('\n');
+            self.lines += 1;
+        }
+        self.line(indent, &format!(": {}", name));
+    }
+
+    fn line(&mut self, indent: usize, text: &str) {
+        let _ = writeln!(self.output, "{}{}", "\t".repeat(indent), text);
+        self.lines += 1;
+    }
+
+    fn mapped(&mut self, indent: usize, text: &str, addrs: &[usize]) {
+        self.line(indent, text);
+        for addr in addrs {
+            self.line_map.insert(*addr, self.lines);
+        }
+    }
+
+    fn target(labels: &HashMap<usize, Label>, addr: usize) -> String {
+        match labels.get(&addr) {
+            Some(label) => label.name.clone(),
+            None => format!("0x{:03X}", addr),
+        }
+    }
+
+    fn raw(opcode: OpCode) -> String {
+        format!("0x{:02X} 0x{:02X}", opcode >> 8, opcode & 0xFF)
+    }
+
+    fn skip_condition(opcode: OpCode) -> Option<String> {
+        let x = (opcode & 0x0F00) >> 8;
+        let y = (opcode & 0x00F0) >> 4;
+        let n = opcode & 0x00FF;
+        match (opcode & 0xF000, opcode & 0x000F, n) {
+            (0x3000, _, _) => Some(format!("v{:X} == 0x{:02X}", x, n)),
+            (0x4000, _, _) => Some(format!("v{:X} != 0x{:02X}", x, n)),
+            (0x5000, 0x0, _) => Some(format!("v{:X} == v{:X}", x, y)),
+            (0x9000, 0x0, _) => Some(format!("v{:X} != v{:X}", x, y)),
+            (0xE000, _, 0x9E) => Some(format!("v{:X} key", x)),
+            (0xE000, _, 0xA1) => Some(format!("v{:X} -key", x)),
+            _ => None,
+        }
+    }
+
+    fn negate(condition: &str) -> String {
+        if condition.contains("==") {
+            condition.replace("==", "!=")
+        } else if condition.contains("!=") {
+            condition.replace("!=", "==")
+        } else if condition.ends_with("-key") {
+            condition.replace("-key", "key")
+        } else {
+            condition.replace("key", "-key")
+        }
+    }
+
+    pub fn disassemble(opcode: OpCode) -> String {
+        match Self::skip_condition(opcode) {
+            Some(condition) => format!("if {} then", Self::negate(&condition)),
+            None => Self::statement(&HashMap::new(), opcode),
+        }
+    }
+
+    fn statement(labels: &HashMap<usize, Label>, opcode: OpCode) -> String {
+        let x = (opcode & 0x0F00) >> 8;
+        let y = (opcode & 0x00F0) >> 4;
+        let n = opcode & 0x000F;
+        let nn = opcode & 0x00FF;
+        let nnn = (opcode & 0x0FFF) as usize;
+        match opcode & 0xF000 {
+            0x0000 => match opcode {
+                0x00E0 => "clear".to_string(),
+                0x00EE => "return".to_string(),
+                _ => Self::raw(opcode),
+            },
+            0x1000 => format!("jump {}", Self::target(labels, nnn)),
+            0x2000 => match labels.get(&nnn) {
+                Some(label) => label.name.clone(),
+                None => format!(":call 0x{:03X}", nnn),
+            },
+            0x6000 => format!("v{:X} := 0x{:02X}", x, nn),
+            0x7000 => format!("v{:X} += 0x{:02X}", x, nn),
+            0x8000 => {
+                let operator = match n {
+                    0x0 => ":=",
+                    0x1 => "|=",
+                    0x2 => "&=",
+                    0x3 => "^=",
+                    0x4 => "+=",
+                    0x5 => "-=",
+                    0x6 => ">>=",
+                    0x7 => "=-",
+                    0xE => "<<=",
+                    _ => return Self::raw(opcode),
+                };
+                format!("v{:X} {} v{:X}", x, operator, y)
+            }
+            0xA000 => format!("i := {}", Self::target(labels, nnn)),
+            0xB000 => format!("jump0 0x{:03X}", nnn),
+            0xC000 => format!("v{:X} := random 0x{:02X}", x, nn),
+            0xD000 => format!("sprite v{:X} v{:X} 0x{:X}", x, y, n),
+            0xF000 => match nn {
+                0x07 => format!("v{:X} := delay", x),
+                0x0A => format!("v{:X} := key", x),
+                0x15 => format!("delay := v{:X}", x),
+                0x18 => format!("buzzer := v{:X}", x),
+                0x1E => format!("i += v{:X}", x),
+                0x29 => format!("i := hex v{:X}", x),
+                0x33 => format!("bcd v{:X}", x),
+                0x55 => format!("save v{:X}", x),
+                0x65 => format!("load v{:X}", x),
+                _ => Self::raw(opcode),
+            },
+            _ => Self::raw(opcode),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn disassembles_each_opcode_class() {
+        let cases = [
+            (0x00E0, "clear"),
+            (0x00EE, "return"),
+            (0x0123, "0x01 0x23"),
+            (0x1234, "jump 0x234"),
+            (0x2345, ":call 0x345"),
+            (0x3A12, "if vA != 0x12 then"),
+            (0x4A12, "if vA == 0x12 then"),
+            (0x5AB0, "if vA != vB then"),
+            (0x6A12, "vA := 0x12"),
+            (0x7A12, "vA += 0x12"),
+            (0x8AB0, "vA := vB"),
+            (0x8AB1, "vA |= vB"),
+            (0x8AB2, "vA &= vB"),
+            (0x8AB3, "vA ^= vB"),
+            (0x8AB4, "vA += vB"),
+            (0x8AB5, "vA -= vB"),
+            (0x8AB6, "vA >>= vB"),
+            (0x8AB7, "vA =- vB"),
+            (0x8ABE, "vA <<= vB"),
+            (0x8AB8, "0x8A 0xB8"),
+            (0x9AB0, "if vA == vB then"),
+            (0xA123, "i := 0x123"),
+            (0xB123, "jump0 0x123"),
+            (0xCA0F, "vA := random 0x0F"),
+            (0xDAB5, "sprite vA vB 0x5"),
+            (0xEA9E, "if vA -key then"),
+            (0xEAA1, "if vA key then"),
+            (0xFA07, "vA := delay"),
+            (0xFA0A, "vA := key"),
+            (0xFA15, "delay := vA"),
+            (0xFA18, "buzzer := vA"),
+            (0xFA1E, "i += vA"),
+            (0xFA29, "i := hex vA"),
+            (0xFA33, "bcd vA"),
+            (0xFA55, "save vA"),
+            (0xFA65, "load vA"),
+            (0xFA99, "0xFA 0x99"),
+        ];
+        for (opcode, text) in cases {
+            assert_eq!(Decompiler::disassemble(opcode), text, "opcode {:04X}", opcode);
+        }
+    }
+
+    #[test]
+    fn decompiles_labels_and_structure() {
+        let rom = [
+            0x60, 0x00, // 0x200  v0 := 0
+            0x22, 0x0C, // 0x202  call 0x20C
+            0x30, 0x05, // 0x204  skip if v0 == 5
+            0x12, 0x02, // 0x206  jump 0x202
+            0xA2, 0x10, // 0x208  i := 0x210
+            0x12, 0x0A, // 0x20A  jump 0x20A
+            0x70, 0x01, // 0x20C  v0 += 1
+            0x00, 0xEE, // 0x20E  return
+            0xF0, 0x90, // 0x210  data
+        ];
+        let (listing, lines) = Decompiler::new(&rom).decompile_with_lines();
+        let expected = [
+            ": main",
+            "v0 := 0x00",
+            "loop",
+            "\tsub_20C",
+            "\tif v0 != 0x05 then",
+            "again",
+            "i := data_210",
+            ": label_20A",
+            "jump label_20A",
+            "",
+            ": sub_20C",
+            "v0 += 0x01",
+            "return",
+            ": data_210",
+            "0xF0 0x90",
+        ];
+        assert_eq!(listing.lines().collect::<Vec<_>>(), expected);
+        assert_eq!(lines.get(&0x202), Some(&4));
+        assert_eq!(lines.get(&0x20C), Some(&12));
+    }
+}
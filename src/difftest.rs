@@ -0,0 +1,352 @@
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+
+use crate::decompiler::Decompiler;
+use crate::{Machine, OpCode, State};
+
+const PROGRAM_LENGTH: usize = 48;
+const MAX_CYCLES: usize = 400;
+
+// Deliberately naive model of the instruction set, written straight from the spec.
+#[derive(Clone)]
+struct Reference {
+    memory: [u8; 4096],
+    v: [u8; 16],
+    i: usize,
+    pc: usize,
+    stack: Vec<usize>,
+    delay: u8,
+    sound: u8,
+    ticks: u8,
+    screen: [bool; 64 * 32],
+    keys: [bool; 16],
+    waiting: Option<usize>,
+}
+
+// The reference refuses to execute anything it considers out of bounds; the run simply ends there.
+struct Fault;
+
+impl Reference {
+    fn new(memory: [u8; 4096]) -> Self {
+        Reference {
+            memory,
+            v: [0; 16],
+            i: 0,
+            pc: 0x200,
+            stack: Vec::new(),
+            delay: 0,
+            sound: 0,
+            ticks: 0,
+            screen: [false; 64 * 32],
+            keys: [false; 16],
+            waiting: None,
+        }
+    }
+
+    fn peek(&self) -> Option<OpCode> {
+        (self.waiting.is_none() && self.pc + 1 < 4096).then(|| (self.memory[self.pc] as u16) << 8 | self.memory[self.pc + 1] as u16)
+    }
+
+    fn step(&mut self, random: Option<u8>) -> Result<(), Fault> {
+        if let Some(x) = self.waiting {
+            if let Some(key) = self.keys.iter().position(|k| *k) {
+                self.v[x] = key as u8;
+                self.waiting = None;
+            }
+            return Ok(());
+        }
+
+        if self.pc + 1 >= 4096 {
+            return Err(Fault);
+        }
+        let op = (self.memory[self.pc] as u16) << 8 | self.memory[self.pc + 1] as u16;
+        self.pc += 2;
+
+        let x = ((op >> 8) & 0xF) as usize;
+        let y = ((op >> 4) & 0xF) as usize;
+        let n = (op & 0xF) as usize;
+        let nn = (op & 0xFF) as u8;
+        let nnn = (op & 0xFFF) as usize;
+
+        match op >> 12 {
+            0x0 if op == 0x00E0 => self.screen = [false; 64 * 32],
+            0x0 if op == 0x00EE => self.pc = self.stack.pop().ok_or(Fault)?,
+            0x1 => self.pc = nnn,
+            0x2 => {
+                if self.stack.len() == 16 {
+                    return Err(Fault);
+                }
+                self.stack.push(self.pc);
+                self.pc = nnn;
+            }
+            0x3 => if self.v[x] == nn { self.pc += 2 },
+            0x4 => if self.v[x] != nn { self.pc += 2 },
+            0x5 if n == 0 => if self.v[x] == self.v[y] { self.pc += 2 },
+            0x6 => self.v[x] = nn,
+            0x7 => self.v[x] = self.v[x].wrapping_add(nn),
+            0x8 => {
+                let (vx, vy) = (self.v[x], self.v[y]);
+                match n {
+                    0x0 => self.v[x] = vy,
+                    0x1 => self.v[x] = vx | vy,
+                    0x2 => self.v[x] = vx & vy,
+                    0x3 => self.v[x] = vx ^ vy,
+                    0x4 => {
+                        self.v[x] = vx.wrapping_add(vy);
+                        self.v[0xF] = (vx as u16 + vy as u16 > 0xFF) as u8;
+                    }
+                    0x5 => {
+                        self.v[x] = vx.wrapping_sub(vy);
+                        self.v[0xF] = (vx >= vy) as u8;
+                    }
+                    0x6 => {
+                        self.v[x] = vx >> 1;
+                        self.v[0xF] = vx & 1;
+                    }
+                    0x7 => {
+                        self.v[x] = vy.wrapping_sub(vx);
+                        self.v[0xF] = (vy >= vx) as u8;
+                    }
+                    0xE => {
+                        self.v[x] = vx << 1;
+                        self.v[0xF] = vx >> 7;
+                    }
+                    _ => return Err(Fault),
+                }
+            }
+            0x9 if n == 0 => if self.v[x] != self.v[y] { self.pc += 2 },
+            0xA => self.i = nnn,
+            0xB => self.pc = nnn + self.v[0] as usize,
+            0xC => {
+                let value = random.ok_or(Fault)?;
+                if value & !nn != 0 {
+                    return Err(Fault);
+                }
+                self.v[x] = value;
+            }
+            0xD => {
+                if self.i + n > 4096 {
+                    return Err(Fault);
+                }
+                let (left, top) = (self.v[x] as usize % 64, self.v[y] as usize % 32);
+                self.v[0xF] = 0;
+                for row in 0..n {
+                    for col in 0..8 {
+                        let (px, py) = (left + col, top + row);
+                        if px >= 64 || py >= 32 || self.memory[self.i + row] & (0x80 >> col) == 0 {
+                            continue;
+                        }
+                        if self.screen[py * 64 + px] {
+                            self.v[0xF] = 1;
+                        }
+                        self.screen[py * 64 + px] ^= true;
+                    }
+                }
+            }
+            0xE if nn == 0x9E || nn == 0xA1 => {
+                let pressed = *self.keys.get(self.v[x] as usize).ok_or(Fault)?;
+                if pressed == (nn == 0x9E) {
+                    self.pc += 2;
+                }
+            }
+            0xF => match nn {
+                0x07 => self.v[x] = self.delay,
+                0x0A => match self.keys.iter().position(|k| *k) {
+                    Some(key) => self.v[x] = key as u8,
+                    None => self.waiting = Some(x),
+                },
+                0x15 => self.delay = self.v[x],
+                0x18 => self.sound = self.v[x],
+                0x1E => self.i += self.v[x] as usize,
+                0x29 => self.i = (self.v[x] as usize & 0xF) * 5,
+                0x33 => {
+                    if self.i + 2 >= 4096 {
+                        return Err(Fault);
+                    }
+                    self.memory[self.i] = self.v[x] / 100;
+                    self.memory[self.i + 1] = self.v[x] / 10 % 10;
+                    self.memory[self.i + 2] = self.v[x] % 10;
+                }
+                0x55 | 0x65 => {
+                    if self.i + x >= 4096 {
+                        return Err(Fault);
+                    }
+                    for r in 0..=x {
+                        if nn == 0x55 {
+                            self.memory[self.i + r] = self.v[r];
+                        } else {
+                            self.v[r] = self.memory[self.i + r];
+                        }
+                    }
+                    self.i += x + 1;
+                }
+                _ => return Err(Fault),
+            },
+            _ => return Err(Fault),
+        }
+
+        if self.ticks == 0 {
+            self.delay = self.delay.saturating_sub(1);
+            self.sound = self.sound.saturating_sub(1);
+            self.ticks = 4;
+        }
+        self.ticks -= 1;
+        Ok(())
+    }
+
+    fn compare(&self, machine: &Machine) -> Option<String> {
+        let waiting = match machine.state {
+            State::WaitingForKey(x) => Some(x),
+            _ => None,
+        };
+        let checks = [
+            ("pc", self.pc == machine.pc),
+            ("registers", self.v == machine.registers),
+            ("index register", self.i == machine.index_register as usize),
+            ("stack", self.stack.iter().copied().eq(machine.stack.iter().map(|s| *s as usize))),
+            ("delay timer", self.delay == machine.delay_timer),
+            ("sound timer", self.sound == machine.sound_timer),
+            ("memory", self.memory == machine.memory),
+            ("screen", self.screen == machine.screen),
+            ("key wait", self.waiting == waiting),
+        ];
+        checks.iter().find(|(_, ok)| !ok).map(|(name, _)| {
+            format!("{} differs\n  reference: pc=0x{:03X} I=0x{:03X} V={:02X?} stack={:03X?}\n  machine:   pc=0x{:03X} I=0x{:03X} V={:02X?} stack={:03X?}",
+                    name, self.pc, self.i, self.v, self.stack,
+                    machine.pc, machine.index_register, machine.registers, machine.stack)
+        })
+    }
+}
+
+struct Case {
+    program: Vec<OpCode>,
+    keys: Vec<u16>,
+}
+
+fn generate(rng: &mut StdRng) -> Case {
+    let mut program = Vec::with_capacity(PROGRAM_LENGTH);
+    for _ in 0..PROGRAM_LENGTH {
+        let addr = 0x200 + rng.gen_range(0..PROGRAM_LENGTH) as u16 * 2;
+        let (x, y) = (rng.gen_range(0..16u16), rng.gen_range(0..16u16));
+        let nn = rng.gen::<u8>() as u16;
+        let op = match rng.gen_range(0..24) {
+            0 => 0x00E0,
+            1 => 0x00EE,
+            2 => 0x1000 | addr,
+            3 => 0x2000 | addr,
+            4 => 0x3000 | x << 8 | nn,
+            5 => 0x4000 | x << 8 | nn,
+            6 => 0x5000 | x << 8 | y << 4,
+            7 => 0x6000 | x << 8 | nn,
+            8 => 0x7000 | x << 8 | nn,
+            9..=11 => {
+                let n = [0x0, 0x1, 0x2, 0x3, 0x4, 0x5, 0x6, 0x7, 0xE][rng.gen_range(0..9)];
+                0x8000 | x << 8 | y << 4 | n
+            }
+            12 => 0x9000 | x << 8 | y << 4,
+            13 => 0xA000 | rng.gen_range(0x300..0xF00),
+            14 => 0xB000 | addr,
+            15 => 0xC000 | x << 8 | nn,
+            16 => 0xD000 | x << 8 | y << 4 | rng.gen_range(0..16),
+            17 => 0xE000 | x << 8 | if rng.gen() { 0x9E } else { 0xA1 },
+            _ => {
+                let nn = [0x07, 0x0A, 0x15, 0x18, 0x1E, 0x29, 0x33, 0x55, 0x65][rng.gen_range(0..9)];
+                0xF000 | x << 8 | nn
+            }
+        };
+        program.push(op);
+    }
+    let keys = (0..MAX_CYCLES).map(|_| match rng.gen_range(0..8) {
+        0 | 1 => 1 << rng.gen_range(0..16),
+        2 => rng.gen::<u16>() & rng.gen::<u16>(),
+        _ => 0,
+    }).collect();
+    Case { program, keys }
+}
+
+fn run(case: &Case) -> Option<(usize, String)> {
+    let mut bytes = Vec::with_capacity(case.program.len() * 2);
+    for op in &case.program {
+        bytes.extend_from_slice(&op.to_be_bytes());
+    }
+    let mut machine = Machine::new();
+    machine.load_program(&bytes[..]);
+    let mut reference = Reference::new(machine.memory);
+
+    for (cycle, keys) in case.keys.iter().enumerate() {
+        for key in 0..16 {
+            let pressed = keys & (1 << key) != 0;
+            reference.keys[key] = pressed;
+            if pressed {
+                machine.key_pressed(key);
+            } else {
+                machine.key_released(key);
+            }
+        }
+
+        if reference.clone().step(Some(0)).is_err() {
+            return None;
+        }
+        let op = reference.peek();
+        let halted = machine.cycle();
+        let random = op.filter(|op| op >> 12 == 0xC).map(|op| machine.registers[((op >> 8) & 0xF) as usize]);
+        if reference.step(random).is_err() {
+            return Some((cycle, "machine produced a random value outside the mask".to_string()));
+        }
+        if let Some(difference) = reference.compare(&machine) {
+            return Some((cycle, difference));
+        }
+        if halted {
+            return None;
+        }
+    }
+    None
+}
+
+fn minimize(mut case: Case) -> Case {
+    let mut changed = true;
+    while changed {
+        changed = false;
+        for i in 0..case.program.len() {
+            if case.program[i] == 0x8000 {
+                continue;
+            }
+            let original = case.program[i];
+            case.program[i] = 0x8000;
+            if run(&case).is_some() {
+                changed = true;
+            } else {
+                case.program[i] = original;
+            }
+        }
+    }
+    while case.program.last() == Some(&0x8000) {
+        case.program.pop();
+    }
+    case
+}
+
+// Prints the first divergence found, shrunk to the instructions that still cause it.
+fn difftest(iterations: usize, seed: u64) -> bool {
+    let mut rng = StdRng::seed_from_u64(seed);
+    for iteration in 0..iterations {
+        let case = generate(&mut rng);
+        if run(&case).is_none() {
+            continue;
+        }
+        let case = minimize(case);
+        let (cycle, difference) = run(&case).expect("Minimized case no longer diverges.");
+        println!("Divergence in iteration {} (seed {}) after {} cycles: {}", iteration, seed, cycle + 1, difference);
+        println!("Minimized program:");
+        for (i, op) in case.program.iter().enumerate() {
+            println!("  0x{:03X}  0x{:04X}  {}", 0x200 + i * 2, op, Decompiler::disassemble(*op));
+        }
+        return false;
+    }
+    true
+}
+
+#[test]
+fn matches_reference() {
+    assert!(difftest(1000, 0), "The machine diverged from the reference model.");
+}
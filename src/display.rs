@@ -0,0 +1,159 @@
+use std::collections::VecDeque;
+
+const PIXELS: usize = 64 * 32;
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum FilterMode {
+    None,
+    Phosphor(f32),
+    Blend(usize),
+    Or,
+}
+
+impl FilterMode {
+    pub fn parse(text: &str) -> Option<Self> {
+        let (name, parameter) = match text.split_once(':') {
+            Some((name, parameter)) => (name, Some(parameter)),
+            None => (text, None),
+        };
+        match name {
+            "none" => Some(FilterMode::None),
+            "phosphor" => Some(FilterMode::Phosphor(parameter.map_or(Some(0.6f32), |p| p.parse().ok())?.clamp(0.0, 0.99))),
+            "blend" => Some(FilterMode::Blend(parameter.map_or(Some(3), |p| p.parse().ok())?.max(1))),
+            "or" => Some(FilterMode::Or),
+            _ => None,
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            FilterMode::None => "none",
+            FilterMode::Phosphor(_) => "phosphor",
+            FilterMode::Blend(_) => "blend",
+            FilterMode::Or => "or",
+        }
+    }
+
+    fn next(self) -> Self {
+        match self {
+            FilterMode::None => FilterMode::Phosphor(0.6),
+            FilterMode::Phosphor(_) => FilterMode::Blend(3),
+            FilterMode::Blend(_) => FilterMode::Or,
+            FilterMode::Or => FilterMode::None,
+        }
+    }
+}
+
+// Filters work on whole 60 Hz frames: the debugger samples the screen once per frame, so flicker from
+// sprites erased and redrawn within a frame never reaches the history and decay does not depend on speed.
+pub struct DisplayFilter {
+    mode: FilterMode,
+    glow: [f32; PIXELS],
+    history: VecDeque<[bool; PIXELS]>,
+    intensity: [f32; PIXELS],
+    sampled: bool,
+}
+
+impl DisplayFilter {
+    pub fn new(mode: FilterMode) -> Self {
+        DisplayFilter {
+            mode,
+            glow: [0.0; PIXELS],
+            history: VecDeque::new(),
+            intensity: [0.0; PIXELS],
+            sampled: false,
+        }
+    }
+
+    pub fn mode(&self) -> FilterMode {
+        self.mode
+    }
+
+    pub fn set_mode(&mut self, mode: FilterMode) {
+        self.mode = mode;
+        self.glow = [0.0; PIXELS];
+        self.history.clear();
+    }
+
+    pub fn next_mode(&mut self) {
+        self.set_mode(self.mode.next());
+    }
+
+    // Whether a frame was sampled since the last apply, so the output changed without new draws.
+    pub fn changed(&self) -> bool {
+        self.sampled
+    }
+
+    // Called once per timer frame with the screen as the frame ends.
+    pub fn sample(&mut self, screen: &[bool; PIXELS]) {
+        let frames = match self.mode {
+            FilterMode::None => return,
+            FilterMode::Blend(n) => n,
+            FilterMode::Or => 2,
+            FilterMode::Phosphor(_) => 1,
+        };
+        if let FilterMode::Phosphor(decay) = self.mode {
+            for (glow, lit) in self.glow.iter_mut().zip(screen) {
+                *glow = (*lit as u8 as f32).max(*glow * decay);
+            }
+        }
+        self.history.push_front(*screen);
+        self.history.truncate(frames);
+        self.sampled = true;
+    }
+
+    // Phosphor shows new draws straight away; blend and or only show sampled frames, falling back to
+    // the live screen until there is one.
+    pub fn apply(&mut self, screen: &[bool; PIXELS]) -> &[f32; PIXELS] {
+        self.sampled = false;
+        for (i, intensity) in self.intensity.iter_mut().enumerate() {
+            let lit = screen[i] as u8 as f32;
+            *intensity = match self.mode {
+                FilterMode::None => lit,
+                FilterMode::Phosphor(_) => lit.max(self.glow[i]),
+                _ if self.history.is_empty() => lit,
+                FilterMode::Blend(_) => {
+                    self.history.iter().filter(|frame| frame[i]).count() as f32 / self.history.len() as f32
+                }
+                FilterMode::Or => self.history.iter().any(|frame| frame[i]) as u8 as f32,
+            };
+        }
+        &self.intensity
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn draws_between_samples_do_not_reach_the_history() {
+        let (mut lit, dark) = ([false; PIXELS], [false; PIXELS]);
+        lit[0] = true;
+        let mut filter = DisplayFilter::new(FilterMode::Or);
+        filter.sample(&lit);
+        // A sprite erased and redrawn within one frame.
+        for _ in 0..10 {
+            assert_eq!(filter.apply(&dark)[0], 1.0);
+            assert_eq!(filter.apply(&lit)[0], 1.0);
+        }
+        filter.sample(&dark);
+        filter.sample(&dark);
+        assert_eq!(filter.apply(&lit)[0], 0.0);
+    }
+
+    #[test]
+    fn phosphor_decays_once_per_frame() {
+        let (mut lit, dark) = ([false; PIXELS], [false; PIXELS]);
+        lit[0] = true;
+        let mut filter = DisplayFilter::new(FilterMode::Phosphor(0.5));
+        filter.sample(&lit);
+        for _ in 0..10 {
+            filter.apply(&dark);
+        }
+        filter.sample(&dark);
+        assert!(filter.changed());
+        assert_eq!(filter.apply(&dark)[0], 0.5);
+        assert!(!filter.changed());
+    }
+}
@@ -0,0 +1,379 @@
+use std::thread;
+
+use crate::quirks::Quirks;
+use crate::Machine;
+
+// One byte per pixel, 1 for lit, row-major.
+pub type Observation = Vec<u8>;
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Location {
+    Register(usize),
+    Index,
+    DelayTimer,
+    SoundTimer,
+    Memory(usize),
+    // Three decimal digits at an address, as written by FX33.
+    Bcd(usize),
+}
+
+impl Location {
+    fn read(self, machine: &Machine) -> i64 {
+        let memory = machine.memory();
+        match self {
+            Location::Register(x) => machine.registers()[x] as i64,
+            Location::Index => machine.index_register() as i64,
+            Location::DelayTimer => machine.delay_timer() as i64,
+            Location::SoundTimer => machine.sound_timer() as i64,
+            Location::Memory(address) => memory[address] as i64,
+            Location::Bcd(address) => memory[address..address + 3].iter().fold(0, |n, digit| n * 10 + *digit as i64),
+        }
+    }
+
+    fn parse<'a>(words: &mut impl Iterator<Item = &'a str>) -> Option<Location> {
+        let word = words.next()?;
+        let location = match word.to_ascii_uppercase().as_str() {
+            "I" => Location::Index,
+            "DT" => Location::DelayTimer,
+            "ST" => Location::SoundTimer,
+            "MEM" => Location::Memory(parse_number(words.next()?).filter(|a| *a < 4096)? as usize),
+            "BCD" => Location::Bcd(parse_number(words.next()?).filter(|a| *a < 4094)? as usize),
+            register => Location::Register(usize::from_str_radix(register.strip_prefix('V')?, 16).ok().filter(|x| *x < 16)?),
+        };
+        Some(location)
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Comparison {
+    Equal,
+    NotEqual,
+    Less,
+    LessOrEqual,
+    Greater,
+    GreaterOrEqual,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Condition {
+    pub location: Location,
+    pub comparison: Comparison,
+    pub value: i64,
+}
+
+impl Condition {
+    fn holds(&self, machine: &Machine) -> bool {
+        let value = self.location.read(machine);
+        match self.comparison {
+            Comparison::Equal => value == self.value,
+            Comparison::NotEqual => value != self.value,
+            Comparison::Less => value < self.value,
+            Comparison::LessOrEqual => value <= self.value,
+            Comparison::Greater => value > self.value,
+            Comparison::GreaterOrEqual => value >= self.value,
+        }
+    }
+}
+
+// What an agent may press, what it is paid for and when an episode ends, for one ROM.
+// The reward for a frame is the sum of each term's change in value times its scale.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Rules {
+    // Key masks, bit n for key n; an empty list means no-op plus each single key.
+    pub actions: Vec<u16>,
+    pub rewards: Vec<(Location, f32)>,
+    pub done: Vec<Condition>,
+}
+
+impl Rules {
+    // One rule per line; blank lines and `#` comments are skipped.
+    //   action [KEY...]              keys as hex digits, none for a no-op
+    //   reward LOCATION [SCALE]      LOCATION is V0-VF, I, DT, ST, `mem ADDR` or `bcd ADDR`
+    //   done LOCATION OP VALUE       OP is one of == != < <= > >=
+    pub fn parse(text: &str) -> Result<Rules, String> {
+        let mut rules = Rules::default();
+        for (number, line) in text.lines().enumerate().map(|(i, l)| (i + 1, l.trim())) {
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let mut words = line.split_whitespace();
+            let malformed = || format!("line {}: malformed rule `{}`", number, line);
+            match words.next() {
+                Some("action") => {
+                    let mut mask = 0;
+                    for key in words.by_ref() {
+                        let key = u8::from_str_radix(key, 16).ok().filter(|k| *k < 16).ok_or_else(malformed)?;
+                        mask |= 1 << key;
+                    }
+                    rules.actions.push(mask);
+                }
+                Some("reward") => {
+                    let location = Location::parse(&mut words).ok_or_else(malformed)?;
+                    let scale = match words.next() {
+                        Some(scale) => scale.parse().map_err(|_| malformed())?,
+                        None => 1.0,
+                    };
+                    rules.rewards.push((location, scale));
+                }
+                Some("done") => {
+                    let location = Location::parse(&mut words).ok_or_else(malformed)?;
+                    let comparison = match words.next() {
+                        Some("==") => Comparison::Equal,
+                        Some("!=") => Comparison::NotEqual,
+                        Some("<") => Comparison::Less,
+                        Some("<=") => Comparison::LessOrEqual,
+                        Some(">") => Comparison::Greater,
+                        Some(">=") => Comparison::GreaterOrEqual,
+                        _ => return Err(malformed()),
+                    };
+                    let value = words.next().and_then(parse_number).ok_or_else(malformed)?;
+                    rules.done.push(Condition { location, comparison, value });
+                }
+                _ => return Err(format!("line {}: expected action, reward or done", number)),
+            }
+            if words.next().is_some() {
+                return Err(malformed());
+            }
+        }
+        Ok(rules)
+    }
+}
+
+fn parse_number(text: &str) -> Option<i64> {
+    match text.strip_prefix("0x").or_else(|| text.strip_prefix("0X")) {
+        Some(hex) => i64::from_str_radix(hex, 16).ok(),
+        None => text.parse().ok(),
+    }
+}
+
+// Gym-style wrapper around a headless machine. Each reset starts a fresh machine seeded
+// with the next seed in sequence, so runs are reproducible but episodes differ.
+pub struct Env {
+    rom: Vec<u8>,
+    rules: Rules,
+    quirks: Quirks,
+    speed: u8,
+    frame_skip: u32,
+    max_frames: Option<u64>,
+    seed: u64,
+    machine: Machine,
+    values: Vec<i64>,
+}
+
+impl Env {
+    pub fn new(rom: &[u8], mut rules: Rules, seed: u64) -> Env {
+        if rules.actions.is_empty() {
+            rules.actions = (0..=16).map(|key| if key == 0 { 0 } else { 1 << (key - 1) }).collect();
+        }
+        let mut env = Env {
+            rom: rom.to_vec(),
+            rules,
+            quirks: Quirks::default(),
+            speed: Machine::new().speed(),
+            frame_skip: 4,
+            max_frames: None,
+            seed,
+            machine: Machine::new(),
+            values: Vec::new(),
+        };
+        env.reset();
+        env
+    }
+
+    // Quirks and speed apply to the current episode straight away, and to every one after it.
+    pub fn set_quirks(&mut self, quirks: Quirks) {
+        self.quirks = quirks;
+        self.machine.set_quirks(quirks);
+    }
+
+    pub fn set_speed(&mut self, cycles_per_frame: u8) {
+        self.speed = cycles_per_frame.max(1);
+        self.machine.set_speed(self.speed);
+    }
+
+    // Frames run per step with the action's keys held.
+    pub fn set_frame_skip(&mut self, frames: u32) {
+        self.frame_skip = frames.max(1);
+    }
+
+    // Ends episodes that run this many frames.
+    pub fn set_max_frames(&mut self, frames: Option<u64>) {
+        self.max_frames = frames;
+    }
+
+    pub fn action_count(&self) -> usize {
+        self.rules.actions.len()
+    }
+
+    pub fn machine(&self) -> &Machine {
+        &self.machine
+    }
+
+    pub fn reset(&mut self) -> Observation {
+        let mut machine = Machine::new();
+        machine.seed(self.seed);
+        machine.set_quirks(self.quirks);
+        machine.set_speed(self.speed);
+        machine.load_program(&self.rom[..]);
+        self.machine = machine;
+        self.seed = self.seed.wrapping_add(1);
+        self.values = self.rules.rewards.iter().map(|(location, _)| location.read(&self.machine)).collect();
+        self.observation()
+    }
+
+    // Panics if the action is not below action_count().
+    pub fn step(&mut self, action: usize) -> (Observation, f32, bool) {
+        let mask = *self.rules.actions.get(action)
+            .unwrap_or_else(|| panic!("action {} is out of range, there are {}", action, self.rules.actions.len()));
+        for key in 0..16 {
+            if mask & (1 << key) != 0 {
+                self.machine.key_pressed(key);
+            } else {
+                self.machine.key_released(key);
+            }
+        }
+
+        let mut reward = 0.0;
+        let mut done = false;
+        for _ in 0..self.frame_skip {
+            for _ in 0..self.speed {
+                if self.machine.cycle() {
+                    break;
+                }
+            }
+            for ((location, scale), previous) in self.rules.rewards.iter().zip(&mut self.values) {
+                let value = location.read(&self.machine);
+                reward += (value - *previous) as f32 * scale;
+                *previous = value;
+            }
+            done = self.machine.halted()
+                || self.max_frames.is_some_and(|max| self.machine.frames() >= max)
+                || self.rules.done.iter().any(|condition| condition.holds(&self.machine));
+            if done {
+                break;
+            }
+        }
+        (self.observation(), reward, done)
+    }
+
+    fn observation(&self) -> Observation {
+        self.machine.screen().iter().map(|pixel| *pixel as u8).collect()
+    }
+}
+
+// Steps many environments across threads. An environment that finishes is reset straight
+// away, so its returned observation is the first of the next episode.
+pub struct VecEnv {
+    envs: Vec<Env>,
+    threads: usize,
+}
+
+impl VecEnv {
+    pub fn new(envs: Vec<Env>) -> VecEnv {
+        let threads = thread::available_parallelism().map_or(1, |n| n.get());
+        VecEnv { envs, threads }
+    }
+
+    pub fn len(&self) -> usize {
+        self.envs.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.envs.is_empty()
+    }
+
+    pub fn envs(&self) -> &[Env] {
+        &self.envs
+    }
+
+    pub fn reset(&mut self) -> Vec<Observation> {
+        self.envs.iter_mut().map(Env::reset).collect()
+    }
+
+    pub fn step(&mut self, actions: &[usize]) -> Vec<(Observation, f32, bool)> {
+        assert_eq!(actions.len(), self.envs.len(), "one action per environment");
+        let chunk = self.envs.len().div_ceil(self.threads).max(1);
+        thread::scope(|scope| {
+            let workers: Vec<_> = self.envs.chunks_mut(chunk).zip(actions.chunks(chunk))
+                .map(|(envs, actions)| scope.spawn(move || {
+                    envs.iter_mut().zip(actions).map(|(env, action)| {
+                        let (observation, reward, done) = env.step(*action);
+                        (if done { env.reset() } else { observation }, reward, done)
+                    }).collect::<Vec<_>>()
+                }))
+                .collect();
+            workers.into_iter().flat_map(|worker| worker.join().expect("environment thread panicked")).collect()
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // v1 := 5; if v1 -key then; jump 0x202; v0 += 1; jump 0x202
+    const COUNTER: [u8; 10] = [0x61, 0x05, 0xE1, 0x9E, 0x12, 0x02, 0x70, 0x01, 0x12, 0x02];
+
+    fn env(rules: &str) -> Env {
+        let mut env = Env::new(&COUNTER, Rules::parse(rules).unwrap(), 0);
+        env.set_speed(3);
+        env.set_frame_skip(2);
+        env
+    }
+
+    #[test]
+    fn parses_rules() {
+        let rules = Rules::parse("# counter\naction\naction 5 a\n\nreward v0 0.5\nreward bcd 0x300\ndone mem 0x300 >= 2\n").unwrap();
+        assert_eq!(rules.actions, [0, 1 << 5 | 1 << 10]);
+        assert_eq!(rules.rewards, [(Location::Register(0), 0.5), (Location::Bcd(0x300), 1.0)]);
+        assert_eq!(rules.done, [Condition { location: Location::Memory(0x300), comparison: Comparison::GreaterOrEqual, value: 2 }]);
+        for text in ["action 10", "reward vG", "reward bcd 4094", "done v0 = 1", "done v0 == x", "reward v0 1 2", "jump"] {
+            assert!(Rules::parse(text).is_err(), "{}", text);
+        }
+        assert_eq!(Env::new(&COUNTER, Rules::default(), 0).action_count(), 17);
+    }
+
+    #[test]
+    fn speed_applies_to_the_current_episode() {
+        let mut env = env("");
+        env.set_frame_skip(4);
+        env.step(0);
+        assert_eq!(env.machine().frames(), 4);
+    }
+
+    #[test]
+    fn rewards_changes_and_stops_when_done() {
+        let mut env = env("action\naction 5\nreward v0 0.5\ndone v0 >= 5");
+        assert_eq!(env.step(0).1, 0.0);
+        // Holding key 5, the loop adds one to v0 every three cycles, one frame each.
+        let (_, reward, done) = env.step(1);
+        assert_eq!((reward, done), (1.0, false));
+        assert_eq!(env.step(1).1, 1.0);
+        let (_, reward, done) = env.step(1);
+        assert_eq!((reward, done), (0.5, true));
+        assert_eq!(env.machine().registers()[0], 5);
+    }
+
+    #[test]
+    #[should_panic(expected = "action 2 is out of range")]
+    fn rejects_unknown_actions() {
+        env("action\naction 5").step(2);
+    }
+
+    #[test]
+    fn vec_env_matches_single_envs_and_resets_finished_ones() {
+        let rules = "action\naction 5\nreward v0\ndone v0 >= 3";
+        let mut envs = VecEnv::new((0..3).map(|_| env(rules)).collect());
+        let mut single = env(rules);
+        assert_eq!(envs.reset().len(), 3);
+        single.reset();
+        let results = envs.step(&[1, 0, 1]);
+        assert_eq!(results.len(), 3);
+        assert_eq!(results[0], single.step(1));
+        assert_eq!((results[1].1, results[1].2), (0.0, false));
+        let results = envs.step(&[1, 1, 1]);
+        assert!(results[0].2 && results[2].2 && !results[1].2);
+        assert_eq!(envs.envs()[0].machine().registers()[0], 0);
+        assert_eq!(envs.envs()[1].machine().registers()[0], 2);
+    }
+}
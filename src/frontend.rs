@@ -0,0 +1,184 @@
+use std::time::Duration;
+
+use crate::debugger::Debugger;
+use crate::palette::Palette;
+use crate::Machine;
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum Input {
+    Press(usize),
+    Release(usize),
+    TogglePause,
+    Step,
+    StepOver,
+    StepOut,
+    RunToCursor,
+    // Moves the run to cursor target by a number of instructions.
+    MoveCursor(isize),
+    ResetCursor,
+    NextFilter,
+    NextPalette,
+    Display(DisplayAction),
+    Memory(MemoryAction),
+    Quit,
+}
+
+// Requests that only the display can act on; back-ends without the feature ignore them.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum DisplayAction {
+    ToggleScaling,
+    NextCrt,
+    ToggleFullscreen,
+    Refresh,
+}
+
+// Commands for the debug window's memory editor.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum MemoryAction {
+    // Moves the selection by a number of bytes.
+    Move(isize),
+    GoToPc,
+    GoToIndex,
+    // Starts typing an address to jump to; Enter finishes it.
+    StartJump,
+    // Part of a jump address, or half of a new value for the selected byte.
+    Digit(u8),
+    Enter,
+    Erase,
+}
+
+pub trait Display {
+    fn draw(&mut self, intensity: &[f32; 64 * 32], palette: &Palette);
+
+    fn draw_status(&mut self, _debugger: &Debugger) {}
+
+    // Returns whether the frame has to be rendered again.
+    fn handle(&mut self, _action: DisplayAction) -> bool {
+        false
+    }
+}
+
+pub trait InputSource {
+    fn poll(&mut self, machine: &Machine) -> Vec<Input>;
+}
+
+pub trait Beeper {
+    fn set_playing(&mut self, playing: bool);
+}
+
+impl<B: Beeper> Beeper for Option<B> {
+    fn set_playing(&mut self, playing: bool) {
+        if let Some(beeper) = self {
+            beeper.set_playing(playing);
+        }
+    }
+}
+
+// Runs once per loop with full control of the debugger; returns false to quit.
+pub trait Console {
+    fn update(&mut self, debugger: &mut Debugger) -> bool;
+}
+
+impl<C: Console> Console for Option<C> {
+    fn update(&mut self, debugger: &mut Debugger) -> bool {
+        match self {
+            Some(console) => console.update(debugger),
+            None => true,
+        }
+    }
+}
+
+pub fn run<D: Display, I: InputSource, B: Beeper, C: Console>(debugger: &mut Debugger, display: &mut D, input: &mut I, beeper: &mut B, console: &mut C, delay: Duration) {
+    loop {
+        debugger.cycle(display);
+        beeper.set_playing(debugger.machine().sound_playing());
+        if debugger.quit_requested() || !console.update(debugger) {
+            return;
+        }
+
+        for event in input.poll(debugger.machine()) {
+            match event {
+                Input::Press(key) => debugger.key_pressed(key),
+                Input::Release(key) => debugger.key_released(key),
+                Input::TogglePause => {
+                    debugger.toggle_pause();
+                    display.draw_status(debugger);
+                }
+                Input::Step => debugger.step(),
+                Input::StepOver => debugger.step_over(),
+                Input::StepOut => {
+                    debugger.step_out();
+                }
+                Input::RunToCursor => debugger.run_to_cursor(),
+                Input::MoveCursor(instructions) => {
+                    debugger.move_cursor(instructions);
+                    display.draw_status(debugger);
+                }
+                Input::ResetCursor => {
+                    debugger.reset_cursor();
+                    display.draw_status(debugger);
+                }
+                Input::NextFilter => debugger.next_filter(),
+                Input::NextPalette => debugger.next_palette(),
+                Input::Display(action) => {
+                    if display.handle(action) {
+                        debugger.redraw();
+                    }
+                }
+                Input::Memory(action) => {
+                    debugger.memory_action(action);
+                    display.draw_status(debugger);
+                }
+                Input::Quit => return,
+            }
+        }
+
+        if !delay.is_zero() {
+            std::thread::sleep(delay);
+        }
+    }
+}
+
+pub struct NoDisplay;
+
+impl Display for NoDisplay {
+    fn draw(&mut self, _intensity: &[f32; 64 * 32], _palette: &Palette) {}
+}
+
+pub struct Silence;
+
+pub struct NoConsole;
+
+impl Console for NoConsole {
+    fn update(&mut self, _debugger: &mut Debugger) -> bool {
+        true
+    }
+}
+
+impl Beeper for Silence {
+    fn set_playing(&mut self, _playing: bool) {}
+}
+
+// Headless input: no keys, and stops after a number of timer frames.
+pub struct FrameLimit {
+    frames: u64,
+}
+
+impl FrameLimit {
+    pub fn new(frames: u64) -> Self {
+        FrameLimit { frames }
+    }
+}
+
+impl InputSource for FrameLimit {
+    fn poll(&mut self, machine: &Machine) -> Vec<Input> {
+        if machine.waiting_for_key() {
+            eprintln!("Program is waiting for a key at frame {}, stopping.", machine.frames());
+            return vec![Input::Quit];
+        }
+        if machine.frames() >= self.frames || machine.halted() {
+            return vec![Input::Quit];
+        }
+        Vec::new()
+    }
+}
@@ -0,0 +1,384 @@
+use std::collections::VecDeque;
+use std::io::{self, ErrorKind, Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::time::Duration;
+
+use crate::debugger::{Debugger, Stop};
+use crate::frontend::Display;
+
+// GDB register numbers: V0-VF, then I, PC, SP (stack depth), DT and ST.
+const REGISTER_COUNT: usize = 21;
+const SIGINT: u8 = 2;
+const SIGILL: u8 = 4;
+const SIGTRAP: u8 = 5;
+
+enum Request {
+    Packet(String),
+    Interrupt,
+}
+
+struct Connection {
+    stream: TcpStream,
+    pending: VecDeque<u8>,
+    ack: bool,
+}
+
+impl Connection {
+    fn next_byte(&mut self) -> io::Result<u8> {
+        if self.pending.is_empty() {
+            let mut buffer = [0; 4096];
+            let count = self.stream.read(&mut buffer)?;
+            if count == 0 {
+                return Err(ErrorKind::UnexpectedEof.into());
+            }
+            self.pending.extend(&buffer[..count]);
+        }
+        Ok(self.pending.pop_front().unwrap())
+    }
+
+    fn read_request(&mut self) -> io::Result<Request> {
+        loop {
+            match self.next_byte()? {
+                0x03 => return Ok(Request::Interrupt),
+                b'$' => {}
+                _ => continue,
+            }
+            let mut payload = Vec::new();
+            loop {
+                match self.next_byte()? {
+                    b'#' => break,
+                    byte => payload.push(byte),
+                }
+            }
+            let checksum = [self.next_byte()?, self.next_byte()?];
+            let valid = std::str::from_utf8(&checksum).ok()
+                .and_then(|c| u8::from_str_radix(c, 16).ok())
+                .is_some_and(|c| c == checksum_of(&payload));
+            if self.ack {
+                self.stream.write_all(if valid { b"+" } else { b"-" })?;
+            }
+            if valid {
+                return Ok(Request::Packet(String::from_utf8_lossy(&payload).into_owned()));
+            }
+        }
+    }
+
+    // Checks for a Ctrl-C from the client without blocking.
+    fn interrupted(&mut self) -> io::Result<bool> {
+        self.stream.set_nonblocking(true)?;
+        let mut buffer = [0; 4096];
+        let result = self.stream.read(&mut buffer);
+        self.stream.set_nonblocking(false)?;
+        match result {
+            Ok(0) => Err(ErrorKind::UnexpectedEof.into()),
+            Ok(count) => {
+                self.pending.extend(&buffer[..count]);
+                Ok(self.pending.iter().any(|byte| *byte == 0x03))
+            }
+            Err(e) if e.kind() == ErrorKind::WouldBlock => Ok(false),
+            Err(e) => Err(e),
+        }
+    }
+
+    fn send(&mut self, payload: &str) -> io::Result<()> {
+        write!(self.stream, "${}#{:02x}", payload, checksum_of(payload.as_bytes()))
+    }
+}
+
+fn checksum_of(data: &[u8]) -> u8 {
+    data.iter().fold(0u8, |sum, byte| sum.wrapping_add(*byte))
+}
+
+fn hex(data: &[u8]) -> String {
+    data.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+fn unhex(text: &str) -> Option<Vec<u8>> {
+    if !text.len().is_multiple_of(2) {
+        return None;
+    }
+    (0..text.len()).step_by(2).map(|i| u8::from_str_radix(text.get(i..i + 2)?, 16).ok()).collect()
+}
+
+fn number(text: &str) -> Option<usize> {
+    usize::from_str_radix(text, 16).ok()
+}
+
+fn target_xml() -> String {
+    let mut registers: Vec<String> = (0..16).map(|x| format!(r#"<reg name="v{:x}" bitsize="8" type="uint8"/>"#, x)).collect();
+    registers.push(r#"<reg name="i" bitsize="16" type="data_ptr"/>"#.to_string());
+    registers.push(r#"<reg name="pc" bitsize="16" type="code_ptr"/>"#.to_string());
+    for name in ["sp", "dt", "st"] {
+        registers.push(format!(r#"<reg name="{}" bitsize="8" type="uint8"/>"#, name));
+    }
+    format!(r#"<?xml version="1.0"?><!DOCTYPE target SYSTEM "gdb-target.dtd"><target version="1.0"><feature name="org.rip8.chip8">{}</feature></target>"#, registers.join(""))
+}
+
+// Serves one GDB remote serial protocol client on localhost. The machine starts paused; software
+// breakpoints are kept by the debugger rather than patched into memory, and only write watchpoints exist.
+pub fn serve<D: Display>(debugger: &mut Debugger, display: &mut D, port: u16, delay: Duration) -> io::Result<()> {
+    let listener = TcpListener::bind(("127.0.0.1", port))?;
+    eprintln!("Waiting for a GDB connection on 127.0.0.1:{}.", listener.local_addr()?.port());
+    accept(debugger, display, listener, delay)
+}
+
+fn accept<D: Display>(debugger: &mut Debugger, display: &mut D, listener: TcpListener, delay: Duration) -> io::Result<()> {
+    let (stream, _) = listener.accept()?;
+    stream.set_nodelay(true)?;
+    debugger.set_paused(true);
+    let mut session = Session { debugger, display, connection: Connection { stream, pending: VecDeque::new(), ack: true }, delay };
+    match session.run() {
+        Err(e) if e.kind() == ErrorKind::UnexpectedEof => Ok(()),
+        result => result,
+    }
+}
+
+struct Session<'a, D: Display> {
+    debugger: &'a mut Debugger,
+    display: &'a mut D,
+    connection: Connection,
+    delay: Duration,
+}
+
+impl<D: Display> Session<'_, D> {
+    fn run(&mut self) -> io::Result<()> {
+        loop {
+            let packet = match self.connection.read_request()? {
+                Request::Packet(packet) => packet,
+                Request::Interrupt => continue,
+            };
+            let reply = match packet.as_bytes().first() {
+                Some(b'c') => self.resume(&packet[1..], false)?,
+                Some(b's') => self.resume(&packet[1..], true)?,
+                Some(b'D') => {
+                    self.connection.send("OK")?;
+                    return Ok(());
+                }
+                Some(b'k') => return Ok(()),
+                _ => self.handle(&packet),
+            };
+            self.connection.send(&reply)?;
+        }
+    }
+
+    fn handle(&mut self, packet: &str) -> String {
+        let machine = self.debugger.machine();
+        let reply = match packet.as_bytes().first() {
+            Some(b'?') => Some(format!("S{:02x}", SIGTRAP)),
+            Some(b'g') => Some(hex(&(0..REGISTER_COUNT).flat_map(|n| self.register(n)).collect::<Vec<_>>())),
+            Some(b'G') => unhex(&packet[1..]).and_then(|data| self.write_registers(&data)),
+            Some(b'p') => number(&packet[1..]).filter(|n| *n < REGISTER_COUNT).map(|n| hex(&self.register(n))),
+            Some(b'P') => packet[1..].split_once('=').and_then(|(n, value)| self.write_register(number(n)?, &unhex(value)?)),
+            Some(b'm') => packet[1..].split_once(',').and_then(|(address, length)| {
+                let (address, length) = (number(address)?, number(length)?);
+                let memory = machine.memory();
+                (address < memory.len()).then(|| hex(&memory[address..memory.len().min(address.saturating_add(length))]))
+            }),
+            Some(b'M') => packet[1..].split_once(':').and_then(|(range, data)| {
+                let (address, length) = range.split_once(',')?;
+                let (address, length, data) = (number(address)?, number(length)?, unhex(data)?);
+                if data.len() != length || address.checked_add(length).is_none_or(|end| end > self.debugger.machine().memory().len()) {
+                    return None;
+                }
+                let machine = self.debugger.machine_mut();
+                for (offset, byte) in data.into_iter().enumerate() {
+                    machine.write_memory(address + offset, byte);
+                }
+                Some("OK".to_string())
+            }),
+            Some(b'Z') | Some(b'z') => return self.point(packet),
+            Some(b'H') | Some(b'T') => Some("OK".to_string()),
+            _ => return self.query(packet),
+        };
+        reply.unwrap_or_else(|| "E01".to_string())
+    }
+
+    fn query(&mut self, packet: &str) -> String {
+        if packet.starts_with("qSupported") {
+            return "PacketSize=4000;qXfer:features:read+;swbreak+;QStartNoAckMode+".to_string();
+        }
+        if let Some(range) = packet.strip_prefix("qXfer:features:read:target.xml:") {
+            let xml = target_xml();
+            return match range.split_once(',').and_then(|(offset, length)| Some((number(offset)?, number(length)?))) {
+                Some((offset, length)) if offset <= xml.len() => {
+                    let end = xml.len().min(offset.saturating_add(length));
+                    format!("{}{}", if end == xml.len() { 'l' } else { 'm' }, &xml[offset..end])
+                }
+                _ => "E01".to_string(),
+            };
+        }
+        match packet {
+            "QStartNoAckMode" => {
+                self.connection.ack = false;
+                "OK".to_string()
+            }
+            "qAttached" => "1".to_string(),
+            "qfThreadInfo" => "m1".to_string(),
+            "qsThreadInfo" => "l".to_string(),
+            "qC" => "QC1".to_string(),
+            _ => String::new(),
+        }
+    }
+
+    // Z0/Z1 set breakpoints and Z2 write watchpoints; z removes them.
+    fn point(&mut self, packet: &str) -> String {
+        let insert = packet.starts_with('Z');
+        let mut fields = packet[1..].split(',');
+        let (kind, address, length) = match (fields.next(), fields.next().and_then(number), fields.next().and_then(number)) {
+            (Some(kind), Some(address), Some(length)) => (kind, address, length),
+            _ => return "E01".to_string(),
+        };
+        match (kind, insert) {
+            ("0" | "1", true) => self.debugger.set_breakpoint(address),
+            ("0" | "1", false) => {
+                self.debugger.clear_breakpoint(address);
+            }
+            ("2", true) => self.debugger.set_watchpoint(address, length.max(1)),
+            ("2", false) => {
+                self.debugger.clear_watchpoint(address, length.max(1));
+            }
+            _ => return String::new(),
+        }
+        "OK".to_string()
+    }
+
+    fn register(&self, n: usize) -> Vec<u8> {
+        let machine = self.debugger.machine();
+        match n {
+            0..=15 => vec![machine.registers()[n]],
+            16 => machine.index_register().to_le_bytes().to_vec(),
+            17 => (machine.pc() as u16).to_le_bytes().to_vec(),
+            18 => vec![machine.stack().len() as u8],
+            19 => vec![machine.delay_timer()],
+            _ => vec![machine.sound_timer()],
+        }
+    }
+
+    // SP is read-only; writes to it are accepted and ignored, as GDB writes all registers at once.
+    fn write_register(&mut self, n: usize, value: &[u8]) -> Option<String> {
+        let machine = self.debugger.machine_mut();
+        match (n, value) {
+            (0..=15, [value]) => machine.set_register(n, *value),
+            (16, [low, high]) => machine.set_index_register(u16::from_le_bytes([*low, *high])),
+            (17, [low, high]) => machine.set_pc(u16::from_le_bytes([*low, *high]) as usize % 4096),
+            (18, [_]) => {}
+            (19, [value]) => machine.set_delay_timer(*value),
+            (20, [value]) => machine.set_sound_timer(*value),
+            _ => return None,
+        }
+        Some("OK".to_string())
+    }
+
+    fn write_registers(&mut self, data: &[u8]) -> Option<String> {
+        let sizes = (0..REGISTER_COUNT).map(|n| self.register(n).len());
+        if data.len() != sizes.clone().sum::<usize>() {
+            return None;
+        }
+        let mut offset = 0;
+        for (n, size) in sizes.collect::<Vec<_>>().into_iter().enumerate() {
+            self.write_register(n, &data[offset..offset + size])?;
+            offset += size;
+        }
+        Some("OK".to_string())
+    }
+
+    // Runs until a breakpoint, watchpoint, halt or Ctrl-C, or for one instruction, and builds the stop reply.
+    fn resume(&mut self, address: &str, step: bool) -> io::Result<String> {
+        if let Some(address) = number(address) {
+            self.debugger.machine_mut().set_pc(address % 4096);
+        }
+        self.debugger.take_stop();
+        if step {
+            self.debugger.step();
+            self.debugger.cycle(self.display);
+            return Ok(stop_reply(self.debugger.take_stop()));
+        }
+        self.debugger.set_paused(false);
+        loop {
+            self.debugger.cycle(self.display);
+            if let Some(stop) = self.debugger.take_stop() {
+                return Ok(stop_reply(Some(stop)));
+            }
+            if self.connection.interrupted()? {
+                self.connection.pending.retain(|byte| *byte != 0x03);
+                self.debugger.set_paused(true);
+                return Ok(format!("S{:02x}", SIGINT));
+            }
+            if !self.delay.is_zero() {
+                std::thread::sleep(self.delay);
+            }
+        }
+    }
+}
+
+fn stop_reply(stop: Option<Stop>) -> String {
+    match stop {
+        Some(Stop::Halted) => format!("S{:02x}", SIGILL),
+        Some(Stop::Watchpoint(address)) => format!("T{:02x}watch:{:x};", SIGTRAP, address),
+        Some(Stop::Breakpoint(_)) => format!("T{:02x}swbreak:;", SIGTRAP),
+        Some(Stop::Step) | None => format!("S{:02x}", SIGTRAP),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::frontend::NoDisplay;
+    use crate::Machine;
+
+    fn request(stream: &mut TcpStream, payload: &str) -> String {
+        write!(stream, "${}#{:02x}", payload, checksum_of(payload.as_bytes())).unwrap();
+        let mut byte = [0];
+        stream.read_exact(&mut byte).unwrap();
+        assert_eq!(byte[0], b'+');
+        stream.read_exact(&mut byte).unwrap();
+        assert_eq!(byte[0], b'$');
+        let mut reply = Vec::new();
+        loop {
+            stream.read_exact(&mut byte).unwrap();
+            if byte[0] == b'#' {
+                break;
+            }
+            reply.push(byte[0]);
+        }
+        let mut checksum = [0; 2];
+        stream.read_exact(&mut checksum).unwrap();
+        assert_eq!(std::str::from_utf8(&checksum).unwrap(), format!("{:02x}", checksum_of(&reply)));
+        String::from_utf8(reply).unwrap()
+    }
+
+    #[test]
+    fn scripted_client() {
+        let mut machine = Machine::new();
+        // v1 := 7; call 0x206; jump 0x204; v2 := 8; return
+        machine.load_program(&[0x61, 0x07, 0x22, 0x06, 0x12, 0x04, 0x62, 0x08, 0x00, 0xEE][..]);
+        let mut debugger = Debugger::new(machine);
+        let listener = TcpListener::bind(("127.0.0.1", 0)).unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let client = std::thread::spawn(move || {
+            let mut stream = TcpStream::connect(("127.0.0.1", port)).unwrap();
+            let replies: Vec<String> = ["?", "g", "m200,4", "m1,ffffffffffffffff", "Mffffffffffffffff,1:00", "qXfer:features:read:target.xml:1,ffffffffffffffff", "Z0,206,2", "c", "p1", "p11", "s", "p11", "D"]
+                .iter()
+                .map(|packet| request(&mut stream, packet))
+                .collect();
+            replies
+        });
+        accept(&mut debugger, &mut NoDisplay, listener, Duration::ZERO).unwrap();
+        let replies = client.join().unwrap();
+
+        assert_eq!(replies[0], "S05");
+        assert_eq!(replies[1], format!("{}{}{}", "00".repeat(16), "00000002", "000000"));
+        assert_eq!(replies[2], "61072206");
+        assert_eq!(replies[3].len(), 2 * 4095);
+        assert_eq!(replies[4], "E01");
+        assert!(replies[5].starts_with("l?xml"));
+        assert_eq!(replies[6], "OK");
+        assert_eq!(replies[7], "T05swbreak:;");
+        assert_eq!(replies[8], "07");
+        assert_eq!(replies[9], "0602");
+        assert_eq!(replies[10], "S05");
+        assert_eq!(replies[11], "0802");
+        assert_eq!(replies[12], "OK");
+        assert_eq!(debugger.machine().registers()[2], 8);
+    }
+}
@@ -0,0 +1,33 @@
+pub mod audio;
+pub mod coverage;
+pub mod crt;
+#[cfg(feature = "dap")]
+pub mod dap;
+pub mod debugger;
+pub mod decompiler;
+#[cfg(test)]
+mod difftest;
+pub mod display;
+pub mod env;
+pub mod frontend;
+pub mod gdb;
+pub mod machine;
+pub mod memory_view;
+pub mod palette;
+pub mod profiler;
+pub mod quirks;
+#[cfg(feature = "console")]
+pub mod repl;
+#[cfg(feature = "script")]
+pub mod script;
+mod snapshot;
+#[cfg(feature = "sdl")]
+pub mod screen;
+#[cfg(feature = "sdl")]
+pub mod sdl;
+#[cfg(feature = "tui")]
+pub mod terminal;
+pub mod trace;
+
+pub use machine::{Machine, OpCode};
+pub(crate) use machine::State;
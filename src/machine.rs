@@ -0,0 +1,780 @@
+use std::io::Read;
+
+use crate::audio::AudioCapture;
+use crate::coverage::Coverage;
+use crate::profiler::Profiler;
+use crate::quirks::Quirks;
+use crate::trace::{TraceRecord, Tracer};
+
+const TIMER_DIVIDER : u8 = 4;
+pub(crate) const STACK_SIZE : usize = 16;
+const DEFAULT_SEED : u64 = 0x9E37_79B9_7F4A_7C15;
+pub struct Machine {
+    pub(crate) memory: [u8; 4096],
+    pub(crate) stack: Vec<u16>,
+    pub(crate) pc: usize,
+    pub(crate) index_register: u16,
+    pub(crate) registers: [u8; 16],
+    pub(crate) keys: [bool; 16],
+    pub(crate) screen: [bool; 64 * 32],
+    pub(crate) delay_timer: u8,
+    pub(crate) sound_timer: u8,
+    pub(crate) frame_timer: u8,
+    pub(crate) cycles_per_frame: u8,
+    pub(crate) frames: u64,
+    pub(crate) draw_flag: bool,
+    pub(crate) last_write: Option<(usize, usize)>,
+    pub(crate) state: State,
+    pub(crate) quirks: Quirks,
+    pub(crate) rng: u64,
+    pub(crate) profiler: Option<Profiler>,
+    pub(crate) coverage: Option<Coverage>,
+    pub(crate) tracer: Option<Tracer>,
+    pub(crate) audio_capture: Option<AudioCapture>,
+}
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub(crate) enum State {
+    Running,
+    Halted,
+    WaitingForKey(usize),
+}
+
+pub type OpCode = u16;
+
+impl Default for Machine {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Machine {
+    const INSTRUCTIONS: [fn(&mut Self, OpCode); 16] = [
+        Self::zero,
+        Self::goto,
+        Self::call,
+        Self::cond_eq_const,
+        Self::cond_neq_const,
+        Self::cond_eq_reg,
+        Self::set_const,
+        Self::add_const,
+        Self::arith,
+        Self::cond_neq_reg,
+        Self::set_index,
+        Self::jump,
+        Self::rand,
+        Self::draw,
+        Self::cond_key,
+        Self::util,
+    ];
+
+    const ARITHMETIC: [fn(&mut Self, OpCode); 16] = [
+        Self::set_reg,
+        Self::bit_or,
+        Self::bit_and,
+        Self::bit_xor,
+        Self::add_reg,
+        Self::sub_reg,
+        Self::shift_right,
+        Self::rev_sub,
+        Self::invalid_opcode_opcode,
+        Self::invalid_opcode_opcode,
+        Self::invalid_opcode_opcode,
+        Self::invalid_opcode_opcode,
+        Self::invalid_opcode_opcode,
+        Self::invalid_opcode_opcode,
+        Self::shift_left,
+        Self::invalid_opcode_opcode
+    ];
+
+    const FONTSET: [u8; 0x50] = [
+        0xF0, 0x90, 0x90, 0x90, 0xF0, // 0
+        0x20, 0x60, 0x20, 0x20, 0x70, // 1
+        0xF0, 0x10, 0xF0, 0x80, 0xF0, // 2
+        0xF0, 0x10, 0xF0, 0x10, 0xF0, // 3
+        0x90, 0x90, 0xF0, 0x10, 0x10, // 4
+        0xF0, 0x80, 0xF0, 0x10, 0xF0, // 5
+        0xF0, 0x80, 0xF0, 0x90, 0xF0, // 6
+        0xF0, 0x10, 0x20, 0x40, 0x40, // 7
+        0xF0, 0x90, 0xF0, 0x90, 0xF0, // 8
+        0xF0, 0x90, 0xF0, 0x10, 0xF0, // 9
+        0xF0, 0x90, 0xF0, 0x90, 0x90, // A
+        0xE0, 0x90, 0xE0, 0x90, 0xE0, // B
+        0xF0, 0x80, 0x80, 0x80, 0xF0, // C
+        0xE0, 0x90, 0x90, 0x90, 0xE0, // D
+        0xF0, 0x80, 0xF0, 0x80, 0xF0, // E
+        0xF0, 0x80, 0xF0, 0x80, 0x80,  // F
+    ];
+
+    pub fn new() -> Machine {
+        let mut memory = [0; 4096];
+        memory[..0x50].copy_from_slice(&Self::FONTSET);
+        Machine {
+            memory,
+            stack: Vec::with_capacity(STACK_SIZE),
+            pc: 0x200,
+            index_register: 0,
+            registers: [0; 16],
+            keys: [false; 16],
+            screen: [false; 64 * 32],
+            delay_timer: 0,
+            sound_timer: 0,
+            frame_timer: 0,
+            cycles_per_frame: TIMER_DIVIDER,
+            frames: 0,
+            draw_flag: false,
+            last_write: None,
+            state: State::Running,
+            quirks: Quirks::default(),
+            rng: DEFAULT_SEED,
+            profiler: None,
+            coverage: None,
+            tracer: None,
+            audio_capture: None,
+        }
+    }
+
+    pub fn load_program<R>(&mut self, program: R) where R: Read {
+        let mut rom = Vec::new();
+        program.take(0x1000 - 0x200).read_to_end(&mut rom).expect("Could not read program.");
+        self.memory[0x200..0x200 + rom.len()].copy_from_slice(&rom);
+    }
+
+    // CXNN draws from a xorshift generator; every machine starts from the same seed unless given one.
+    pub fn seed(&mut self, seed: u64) {
+        self.rng = match seed ^ DEFAULT_SEED {
+            0 => DEFAULT_SEED,
+            state => state,
+        };
+    }
+
+    pub fn set_quirks(&mut self, quirks: Quirks) {
+        self.quirks = quirks;
+    }
+
+    pub fn quirks(&self) -> Quirks {
+        self.quirks
+    }
+
+    // Instructions executed per 60 Hz timer tick; 0 leaves ticking to the caller through tick_timers().
+    pub fn set_speed(&mut self, cycles_per_frame: u8) {
+        self.cycles_per_frame = cycles_per_frame;
+        self.frame_timer = self.frame_timer.min(cycles_per_frame);
+    }
+
+    pub fn tick_timers(&mut self) {
+        if let Some(capture) = &mut self.audio_capture {
+            capture.next_frame(self.sound_timer > 0);
+        }
+        self.frames += 1;
+        self.delay_timer = self.delay_timer.saturating_sub(1);
+        self.sound_timer = self.sound_timer.saturating_sub(1);
+        if let Some(tracer) = &mut self.tracer {
+            tracer.next_frame();
+        }
+    }
+
+    pub fn speed(&self) -> u8 {
+        self.cycles_per_frame
+    }
+
+    pub fn sound_playing(&self) -> bool {
+        self.sound_timer > 0
+    }
+
+    pub fn halted(&self) -> bool {
+        self.state == State::Halted
+    }
+
+    pub fn waiting_for_key(&self) -> bool {
+        matches!(self.state, State::WaitingForKey(_))
+    }
+
+    pub fn frames(&self) -> u64 {
+        self.frames
+    }
+
+    pub fn needs_redraw(&self) -> bool {
+        self.draw_flag
+    }
+
+    pub fn draw_complete(&mut self) {
+        self.draw_flag = false;
+    }
+
+    pub fn registers(&self) -> &[u8; 16] {
+        &self.registers
+    }
+
+    pub fn pc(&self) -> usize {
+        self.pc
+    }
+
+    pub fn index_register(&self) -> u16 {
+        self.index_register
+    }
+
+    pub fn stack(&self) -> &[u16] {
+        &self.stack
+    }
+
+    pub fn delay_timer(&self) -> u8 {
+        self.delay_timer
+    }
+
+    pub fn sound_timer(&self) -> u8 {
+        self.sound_timer
+    }
+
+    pub fn screen(&self) -> &[bool; 64 * 32] {
+        &self.screen
+    }
+
+    pub fn memory(&self) -> &[u8] {
+        &self.memory
+    }
+
+    // Start and length of the memory written by the last instruction; the range wraps at the end of memory.
+    pub fn last_write(&self) -> Option<(usize, usize)> {
+        self.last_write
+    }
+
+    // Panics unless x is a register number from 0 to 15.
+    pub fn set_register(&mut self, x: usize, value: u8) {
+        assert!(x < 16, "register {} does not exist", x);
+        self.registers[x] = value;
+    }
+
+    pub fn set_pc(&mut self, address: usize) {
+        self.pc = address;
+    }
+
+    pub fn set_index_register(&mut self, value: u16) {
+        self.index_register = value;
+    }
+
+    pub fn set_delay_timer(&mut self, value: u8) {
+        self.delay_timer = value;
+    }
+
+    pub fn set_sound_timer(&mut self, value: u8) {
+        self.sound_timer = value;
+    }
+
+    // Panics if the address is outside memory.
+    pub fn write_memory(&mut self, address: usize, value: u8) {
+        assert!(address < self.memory.len(), "address 0x{:X} is outside memory", address);
+        self.memory[address] = value;
+    }
+
+    pub fn enable_profiler(&mut self) {
+        self.profiler = Some(Profiler::new());
+    }
+
+    pub fn profiler(&self) -> Option<&Profiler> {
+        self.profiler.as_ref()
+    }
+
+    pub fn enable_coverage(&mut self) {
+        self.coverage = Some(Coverage::new());
+    }
+
+    pub fn coverage(&self) -> Option<&Coverage> {
+        self.coverage.as_ref()
+    }
+
+    pub fn set_tracer(&mut self, tracer: Tracer) {
+        self.tracer = Some(tracer);
+    }
+
+    pub fn tracer_mut(&mut self) -> Option<&mut Tracer> {
+        self.tracer.as_mut()
+    }
+
+    pub fn set_audio_capture(&mut self, capture: AudioCapture) {
+        self.audio_capture = Some(capture);
+    }
+
+    pub fn audio_capture(&self) -> Option<&AudioCapture> {
+        self.audio_capture.as_ref()
+    }
+
+    pub fn key_pressed(&mut self, key: usize) {
+        self.keys[key] = true;
+    }
+
+    pub fn key_released(&mut self, key: usize) {
+        self.keys[key] = false;
+    }
+
+    pub fn fetch_opcode(&mut self) -> OpCode {
+        let opcode = (self.memory[self.pc] as u16) << 8 | self.memory[self.pc + 1] as u16;
+        self.pc += 2;
+        opcode
+    }
+
+    pub fn cycle(&mut self) -> bool {
+        match self.state {
+            State::Running if self.pc + 1 >= self.memory.len() => {
+                self.state = State::Halted;
+            }
+            State::Running => {
+                let pc = self.pc;
+                let opcode = self.fetch_opcode();
+                let x = ((opcode & 0xF000) >> 12) as usize;
+                let before = self.registers;
+                self.last_write = None;
+
+                Self::INSTRUCTIONS[x](self, opcode);
+                if let Some(profiler) = &mut self.profiler {
+                    profiler.record(pc, opcode);
+                }
+                if let Some(coverage) = &mut self.coverage {
+                    coverage.record(pc, opcode, self.pc);
+                }
+                if let Some(tracer) = &mut self.tracer {
+                    tracer.record(&before, TraceRecord {
+                        frame: tracer.frame(),
+                        pc: pc as u16,
+                        opcode,
+                        registers: self.registers,
+                        index_register: self.index_register,
+                        delay_timer: self.delay_timer,
+                        sound_timer: self.sound_timer,
+                    });
+                }
+                if self.cycles_per_frame > 0 {
+                    if self.frame_timer == 0 {
+                        self.tick_timers();
+                        self.frame_timer = self.cycles_per_frame;
+                    }
+                    self.frame_timer -= 1;
+                }
+
+                if self.pc >= 0x1000 {
+                    self.state = State::Halted;
+                }
+            }
+            State::Halted => {}
+            State::WaitingForKey(x) => {
+                if let Some(i) = self.keys.iter().position(|v| *v) {
+                    self.registers[x] = i as u8;
+                    self.state = State::Running;
+                }
+            }
+        }
+        self.state == State::Halted
+    }
+
+    fn invalid_opcode(&mut self, v: OpCode) {
+        self.crash(format!("Unknown opcode: 0x{:04X}", v));
+    }
+
+    fn crash(&mut self, error: String) {
+        let mut stack_string = "[".to_string();
+        for i in &self.stack {
+            stack_string.push_str(&format!("0x{:04X}, ", i));
+        }
+        stack_string = stack_string.trim_end_matches(", ").to_string();
+        stack_string.push(']');
+
+        let mut register_string = "[".to_string();
+        for i in &self.registers {
+            register_string.push_str(&format!("0x{:02X}, ", i));
+        }
+        register_string = register_string.trim_end_matches(", ").to_string();
+        register_string.push(']');
+
+        let builder : String = format!("\
+        -----------CRASH INFO-----------\n\
+        ERROR {}\n\
+        --------------DATA--------------\n\
+        Stack: {}\n\
+        Current address: 0x{:04X}\n\
+        Index selector: 0x{:04X}\n\
+        Registers: {}\n\
+        ---------END CRASH INFO---------", error, stack_string, self.pc, self.index_register, register_string);
+        // TODO: full memory dump
+        eprintln!("{}", builder);
+        self.state = State::Halted;
+    }
+
+    fn invalid_opcode_opcode(&mut self, v: OpCode) {
+        self.invalid_opcode(v);
+    }
+
+    fn zero(&mut self, v: OpCode) {
+        match v & 0x00FF {
+            0xE0 => {
+                self.draw_flag = true;
+                self.screen = [false; 64 * 32];
+            }
+            0xEE => match self.stack.pop() {
+                Some(addr) => self.pc = addr as usize,
+                None => self.crash("Stack underflow".to_string()),
+            },
+            _ => self.invalid_opcode(v),
+        }
+    }
+
+    fn goto(&mut self, v: OpCode) {
+        self.pc = (v & 0x0FFF) as usize;
+    }
+
+    fn call(&mut self, v: OpCode) {
+        if self.stack.len() == STACK_SIZE {
+            self.crash("Stack overflow".to_string());
+            return;
+        }
+        self.stack.push(self.pc as u16);
+        self.pc = (v & 0x0FFF) as usize;
+    }
+
+    fn cond_eq_const(&mut self, v: OpCode) {
+        let x = ((v & 0x0F00) >> 8) as usize;
+        let n = (v & 0x00FF) as u8;
+        if self.registers[x] == n {
+            self.pc += 2;
+        }
+    }
+
+    fn cond_neq_const(&mut self, v: OpCode) {
+        let x = ((v & 0x0F00) >> 8) as usize;
+        let n = (v & 0x00FF) as u8;
+        if self.registers[x] != n {
+            self.pc += 2;
+        }
+    }
+
+    fn cond_eq_reg(&mut self, v: OpCode) {
+        let x = ((v & 0x0F00) >> 8) as usize;
+        let y = ((v & 0x00F0) >> 4) as usize;
+        if self.registers[x] == self.registers[y] {
+            self.pc += 2;
+        }
+    }
+
+    fn set_const(&mut self, v: OpCode) {
+        let x = ((v & 0x0F00) >> 8) as usize;
+        let n = (v & 0x00FF) as u8;
+        self.registers[x] = n;
+    }
+
+    fn add_const(&mut self, v: OpCode) {
+        let x = ((v & 0x0F00) >> 8) as usize;
+        let n = (v & 0x00FF) as u8;
+        self.registers[x] = self.registers[x].wrapping_add(n);
+    }
+
+    fn arith(&mut self, v: OpCode) {
+        Self::ARITHMETIC[(v & 0x000F) as usize](self, v);
+    }
+
+    fn set_reg(&mut self, v: OpCode) {
+        let x = ((v & 0x0F00) >> 8) as usize;
+        let y = ((v & 0x00F0) >> 4) as usize;
+        self.registers[x] = self.registers[y];
+    }
+
+    fn bit_or(&mut self, v: OpCode) {
+        let x = ((v & 0x0F00) >> 8) as usize;
+        let y = ((v & 0x00F0) >> 4) as usize;
+        self.registers[x] |= self.registers[y];
+        if self.quirks.vf_reset {
+            self.registers[0xF] = 0;
+        }
+    }
+
+    fn bit_and(&mut self, v: OpCode) {
+        let x = ((v & 0x0F00) >> 8) as usize;
+        let y = ((v & 0x00F0) >> 4) as usize;
+        self.registers[x] &= self.registers[y];
+        if self.quirks.vf_reset {
+            self.registers[0xF] = 0;
+        }
+    }
+
+    fn bit_xor(&mut self, v: OpCode) {
+        let x = ((v & 0x0F00) >> 8) as usize;
+        let y = ((v & 0x00F0) >> 4) as usize;
+        self.registers[x] ^= self.registers[y];
+        if self.quirks.vf_reset {
+            self.registers[0xF] = 0;
+        }
+    }
+
+    fn add_reg(&mut self, v: OpCode) {
+        let x = ((v & 0x0F00) >> 8) as usize;
+        let y = ((v & 0x00F0) >> 4) as usize;
+
+        let (result, overflow) = self.registers[x].overflowing_add(self.registers[y]);
+        self.registers[x] = result;
+        self.registers[0xF] = overflow as u8;
+    }
+
+    fn sub_reg(&mut self, v: OpCode) {
+        let x = ((v & 0x0F00) >> 8) as usize;
+        let y = ((v & 0x00F0) >> 4) as usize;
+
+        let (result, overflow) = self.registers[x].overflowing_sub(self.registers[y]);
+        self.registers[x] = result;
+        self.registers[0xF] = (!overflow) as u8;
+    }
+
+    fn shift_right(&mut self, v: OpCode) {
+        let x = ((v & 0x0F00) >> 8) as usize;
+        let y = ((v & 0x00F0) >> 4) as usize;
+        if self.quirks.shift_vy {
+            self.registers[x] = self.registers[y];
+        }
+        let flag = self.registers[x] & 0x1;
+        self.registers[x] >>= 1;
+        self.registers[0xF] = flag;
+    }
+
+    fn shift_left(&mut self, v: OpCode) {
+        let x = ((v & 0x0F00) >> 8) as usize;
+        let y = ((v & 0x00F0) >> 4) as usize;
+        if self.quirks.shift_vy {
+            self.registers[x] = self.registers[y];
+        }
+        let flag = self.registers[x] >> 7;
+        self.registers[x] <<= 1;
+        self.registers[0xF] = flag;
+    }
+
+    fn rev_sub(&mut self, v: OpCode) {
+        let x = ((v & 0x0F00) >> 8) as usize;
+        let y = ((v & 0x00F0) >> 4) as usize;
+
+        let (result, overflow) = self.registers[y].overflowing_sub(self.registers[x]);
+        self.registers[x] = result;
+        self.registers[0xF] = (!overflow) as u8;
+    }
+
+    fn cond_neq_reg(&mut self, v: OpCode) {
+        let x = ((v & 0x0F00) >> 8) as usize;
+        let y = ((v & 0x00F0) >> 4) as usize;
+        if self.registers[x] != self.registers[y] {
+            self.pc += 2;
+        }
+    }
+
+    fn set_index(&mut self, v: OpCode) {
+        self.index_register = v & 0x0FFF;
+    }
+
+    fn jump(&mut self, v: OpCode) {
+        let offset = if self.quirks.jump_vx { (v & 0x0F00) >> 8 } else { 0x0 };
+        self.pc = (v & 0x0FFF) as usize + self.registers[offset as usize] as usize;
+    }
+
+    fn rand(&mut self, v: OpCode) {
+        let x = ((v & 0x0F00) >> 8) as usize;
+        let n = (v & 0x00FF) as u8;
+        self.registers[x] = self.next_random() & n;
+    }
+
+    fn next_random(&mut self) -> u8 {
+        self.rng ^= self.rng >> 12;
+        self.rng ^= self.rng << 25;
+        self.rng ^= self.rng >> 27;
+        (self.rng.wrapping_mul(0x2545_F491_4F6C_DD1D) >> 56) as u8
+    }
+
+    fn draw(&mut self, v: OpCode) {
+        let x = ((v & 0x0F00) >> 8) as usize;
+        let y = ((v & 0x00F0) >> 4) as usize;
+        let base_y = self.registers[y] as usize % 32;
+        let base_x = self.registers[x] as usize % 64;
+        let n = (v & 0x000F) as usize;
+        self.registers[0xF] = 0;
+        for i in 0..n {
+            if self.index_register as usize + i >= self.memory.len() {
+                break;
+            }
+            let sprite = self.memory[self.index_register as usize + i];
+            let sprite_y = base_y + i;
+            for j in 0..8 {
+                let (mut sprite_x, mut sprite_y) = (base_x + j, sprite_y);
+                if self.quirks.wrap_sprites {
+                    sprite_x %= 64;
+                    sprite_y %= 32;
+                }
+                let pixel = (sprite >> (7 - j)) & 0x1;
+                if sprite_x >= 64 || sprite_y >= 32 || pixel == 0 {
+                    continue;
+                }
+                let pixel_index = sprite_y * 64 + sprite_x;
+                if self.screen[pixel_index] {
+                    self.registers[0xF] = 1;
+                }
+                self.screen[pixel_index] = !self.screen[pixel_index];
+            }
+        }
+
+        self.draw_flag = true;
+    }
+
+    fn cond_key(&mut self, v: OpCode) {
+        if v & 0x00FF == 0x009E {
+            self.cond_key_pressed(v);
+        } else if v & 0x00FF == 0x00A1 {
+            self.cond_key_not_pressed(v);
+        } else {
+            self.invalid_opcode(v);
+        }
+    }
+
+    fn cond_key_pressed(&mut self, v: OpCode) {
+        let x = ((v & 0x0F00) >> 8) as usize;
+        let key = (self.registers[x] & 0xF) as usize;
+        if self.keys[key] {
+            self.pc += 2;
+        }
+    }
+
+    fn cond_key_not_pressed(&mut self, v: OpCode) {
+        let x = ((v & 0x0F00) >> 8) as usize;
+        let key = (self.registers[x] & 0xF) as usize;
+        if !self.keys[key] {
+            self.pc += 2;
+        }
+    }
+
+    fn util(&mut self, v: OpCode) {
+        match v & 0x00FF {
+            0x07 => self.get_delay(v),
+            0x0A => self.await_key(v),
+            0x15 => self.set_delay(v),
+            0x18 => self.set_sound(v),
+            0x1E => self.add_index(v),
+            0x29 => self.set_index_char(v),
+            0x33 => self.set_index_bcd(v),
+            0x55 => self.reg_dump(v),
+            0x65 => self.reg_load(v),
+            _ => self.invalid_opcode(v)
+        }
+    }
+
+    fn get_delay(&mut self, v: OpCode) {
+        let x = ((v & 0x0F00) >> 8) as usize;
+        self.registers[x] = self.delay_timer;
+    }
+
+    fn await_key(&mut self, v: OpCode) {
+        for i in 0..16 {
+            if self.keys[i] {
+                let x = ((v & 0x0F00) >> 8) as usize;
+                self.registers[x] = i as u8;
+                return;
+            }
+        }
+        self.state = State::WaitingForKey(((v & 0x0F00) >> 8) as usize);
+    }
+
+    fn set_delay(&mut self, v: OpCode) {
+        let x = ((v & 0x0F00) >> 8) as usize;
+        self.delay_timer = self.registers[x];
+    }
+
+    fn set_sound(&mut self, v: OpCode) {
+        let x = ((v & 0x0F00) >> 8) as usize;
+        self.sound_timer = self.registers[x];
+    }
+
+    fn add_index(&mut self, v: OpCode) {
+        let x = ((v & 0x0F00) >> 8) as usize;
+        self.index_register = self.index_register.wrapping_add(self.registers[x] as u16);
+    }
+
+    fn set_index_char(&mut self, v: OpCode) {
+        let x = ((v & 0x0F00) >> 8) as usize;
+        self.index_register = (self.registers[x] & 0xF) as u16 * 5;
+    }
+
+    fn set_index_bcd(&mut self, v: OpCode) {
+        let x = ((v & 0x0F00) >> 8) as usize;
+        let bcd = self.registers[x];
+        self.last_write = Some((self.index_address(0), 3));
+        self.memory[self.index_address(0)] = bcd / 100;
+        self.memory[self.index_address(1)] = (bcd / 10) % 10;
+        self.memory[self.index_address(2)] = bcd % 10;
+    }
+
+    fn reg_dump(&mut self, v: OpCode) {
+        let x = ((v & 0x0F00) >> 8) as usize;
+        self.last_write = Some((self.index_address(0), x + 1));
+        for i in 0..=x {
+            self.memory[self.index_address(i)] = self.registers[i];
+        }
+        if self.quirks.increment_index {
+            self.index_register = self.index_register.wrapping_add(x as u16 + 1);
+        }
+    }
+
+    fn reg_load(&mut self, v: OpCode) {
+        let x = ((v & 0x0F00) >> 8) as usize;
+        for i in 0..=x {
+            self.registers[i] = self.memory[self.index_address(i)];
+        }
+        if self.quirks.increment_index {
+            self.index_register = self.index_register.wrapping_add(x as u16 + 1);
+        }
+    }
+
+    fn index_address(&self, offset: usize) -> usize {
+        (self.index_register as usize + offset) % self.memory.len()
+    }
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn run(program: &[u8], cycles: usize) -> Machine {
+        let mut machine = Machine::new();
+        machine.load_program(program);
+        for _ in 0..cycles {
+            machine.cycle();
+        }
+        machine
+    }
+
+    #[test]
+    fn fetching_past_the_end_halts() {
+        let mut machine = Machine::new();
+        machine.set_pc(0xFFF);
+        assert!(machine.cycle());
+        assert_eq!(machine.pc(), 0xFFF);
+    }
+
+    #[test]
+    fn bcd_wraps_at_the_end_of_memory() {
+        // v0 := 123; i := 0xFFE; bcd v0
+        let machine = run(&[0x60, 123, 0xAF, 0xFE, 0xF0, 0x33], 3);
+        assert_eq!(machine.memory()[0xFFE..], [1, 2]);
+        assert_eq!(machine.memory()[0], 3);
+        assert_eq!(machine.last_write(), Some((0xFFE, 3)));
+    }
+
+    #[test]
+    fn register_dump_wraps_at_the_end_of_memory() {
+        // v0 := 0xAA; v1 := 0xBB; i := 0xFFF; save v1
+        let machine = run(&[0x60, 0xAA, 0x61, 0xBB, 0xAF, 0xFF, 0xF1, 0x55], 4);
+        assert_eq!(machine.memory()[0xFFF], 0xAA);
+        assert_eq!(machine.memory()[0], 0xBB);
+        assert!(!machine.halted());
+    }
+
+    #[test]
+    fn key_skips_use_the_low_nibble() {
+        // v0 := 0x15; if v0 -key then; jump 0x206 (skipped while key 5 is down)
+        let mut machine = Machine::new();
+        machine.load_program(&[0x60, 0x15, 0xE0, 0x9E, 0x12, 0x06][..]);
+        machine.key_pressed(5);
+        machine.cycle();
+        machine.cycle();
+        assert_eq!(machine.pc(), 0x206);
+        assert!(!machine.halted());
+    }
+}
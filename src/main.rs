@@ -1,598 +1,221 @@
-mod debugger;
-
-use std::collections::HashMap;
-use std::{env, mem};
+use std::env;
 use std::fs::File;
 use std::io::Read;
-use std::time::Duration;
-
-use lazy_static::lazy_static;
-use rodio::{OutputStream, OutputStreamHandle, Sink};
-use rodio::source::SineWave;
-use sdl2::event::Event;
-use sdl2::keyboard::Keycode;
-use sdl2::pixels::Color;
-
-lazy_static! {
-    static ref DEFAULT_MAPPINGS: HashMap<Keycode, usize> = [
-
-    (Keycode::Num1, 0x1), (Keycode::Num2, 0x2), (Keycode::Num3, 0x3), (Keycode::Num4, 0xC),
-    (Keycode::Q   , 0x4), (Keycode::W   , 0x5), (Keycode::E   , 0x6), (Keycode::R   , 0xD),
-    (Keycode::A   , 0x7), (Keycode::S   , 0x8), (Keycode::D   , 0x9), (Keycode::F   , 0xE),
-    (Keycode::Z   , 0xA), (Keycode::X   , 0x0), (Keycode::C   , 0xB), (Keycode::V   , 0xF),
-    ].iter().copied().collect();
-}
-
-fn create_audio() -> Option<(OutputStream, OutputStreamHandle, Sink)> {
-    let (_stream, handle) = OutputStream::try_default().ok()?;
-    let sink = Sink::try_new(&handle).ok()?;
-    let source = SineWave::new(1024.0);
-    sink.set_volume(0.2);
-    sink.pause();
-    sink.append(source);
-    Some((_stream, handle, sink))
-}
+use std::path::Path;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
 
+use rip_8::{audio, coverage, dap, debugger, decompiler, display, frontend, gdb, palette, quirks, repl, screen, script, sdl, terminal, trace, Machine};
 
 fn main() {
-    let sdl_context = sdl2::init().unwrap();
-    let video_subsystem = sdl_context.video().unwrap();
-
-    let window = video_subsystem.window("RIP-8", 1024, 512)
-        .position_centered()
-        .build()
-        .expect("Failed to create window");
-    let dbg_window = video_subsystem.window("Debug", 1024, 512)
-        .position_centered()
-        .build()
-        .expect("Failed to create window");
-    let dbg_id = dbg_window.id();
-
-        let mut canvas = window.into_canvas().build().expect("Failed to create canvas");
-        let mut dbg_canvas = dbg_window.into_canvas().build().expect("Failed to create canvas");
-
-        canvas.set_draw_color(Color::RGB(0, 0, 0));
-        canvas.clear();
-        canvas.present();
-        dbg_canvas.set_draw_color(Color::RGB(0, 0, 0));
-        dbg_canvas.clear();
-        dbg_canvas.present();
-
-        let mut event_pump = sdl_context.event_pump().expect("Failed to create event pump");
-
-
-        let audio = create_audio();
-
-        let mut machine = Machine::new(audio.map(|(x, y, sink)| {
-            mem::forget(x);
-            mem::forget(y);
-            sink
-        }));
-
-        let path = env::args().skip(1).next().expect("No input file.");
-        let file = File::open(path).expect("Could not open file.");
-        machine.load_program(file);
-        let mut debugger = debugger::Debugger::new(machine);
-        'main: loop {
-            debugger.cycle(&mut canvas, Some(&mut dbg_canvas));
-
-
-            for event in event_pump.poll_iter() {
-                if event.get_window_id() == Some(dbg_id) {
-                    match event {
-                        Event::Quit {..} |
-                        Event::KeyDown { keycode: Some(Keycode::Escape), .. } => {
-                            break 'main;
-                        }
-                        Event::KeyDown { keycode: Some(Keycode::Space), .. } => {
-                            debugger.toggle_pause();
-                        }
-                        Event::KeyDown { keycode: Some(Keycode::Tab), .. } => {
-                            debugger.step();
-                        }
-                        _ => {}
-                    }
-                } else {
-                    match event {
-                        Event::Quit { .. } |
-                        Event::KeyDown { keycode: Some(Keycode::Escape), .. } => {
-                            break 'main;
-                        }
-                        Event::KeyDown { keycode: Some(x), .. } => {
-                            if let Some(key) = DEFAULT_MAPPINGS.get(&x) {
-                                debugger.key_pressed(*key);
-                            }
-                        }
-                        Event::KeyUp { keycode: Some(x), .. } => {
-                            if let Some(key) = DEFAULT_MAPPINGS.get(&x) {
-                                debugger.key_released(*key);
-                            }
-                        }
-                        _ => {}
-                    }
-                }
-            }
-
-            std::thread::sleep(Duration::new(0, 1_000_000_000u32 / 240));
-        }
-}
-
-const TIMER_DIVIDER : u8 = 4;
-struct Machine {
-    memory: [u8; 4096],
-    stack: Vec<u16>,
-    pc: usize,
-    index_register: u16,
-    registers: [u8; 16],
-    keys: [bool; 16],
-    screen: [bool; 64 * 32],
-    delay_timer: u8,
-    sound_timer: u8,
-    frame_timer: u8,
-    draw_flag: bool,
-    audio: Option<Sink>,
-    state: State,
-}
-
-#[derive(Copy, Clone, Debug, Eq, PartialEq)]
-enum State {
-    Running,
-    Halted,
-    WaitingForKey(usize),
-}
-
-type OpCode = u16;
-
-impl Machine {
-    const INSTRUCTIONS: [fn(&mut Self, OpCode); 16] = [
-        Self::zero,
-        Self::goto,
-        Self::call,
-        Self::cond_eq_const,
-        Self::cond_neq_const,
-        Self::cond_eq_reg,
-        Self::set_const,
-        Self::add_const,
-        Self::arith,
-        Self::cond_neq_reg,
-        Self::set_index,
-        Self::jump,
-        Self::rand,
-        Self::draw,
-        Self::cond_key,
-        Self::util,
-    ];
-
-    const ARITHMETIC: [fn(&mut Self, OpCode); 16] = [
-        Self::set_reg,
-        Self::bit_or,
-        Self::bit_and,
-        Self::bit_xor,
-        Self::add_reg,
-        Self::sub_reg,
-        Self::shift_right,
-        Self::rev_sub,
-        Self::invalid_opcode_opcode,
-        Self::invalid_opcode_opcode,
-        Self::invalid_opcode_opcode,
-        Self::invalid_opcode_opcode,
-        Self::invalid_opcode_opcode,
-        Self::invalid_opcode_opcode,
-        Self::shift_left,
-        Self::invalid_opcode_opcode
-    ];
-
-    const FONTSET: [u8; 0x50] = [
-        0xF0, 0x90, 0x90, 0x90, 0xF0, // 0
-        0x20, 0x60, 0x20, 0x20, 0x70, // 1
-        0xF0, 0x10, 0xF0, 0x80, 0xF0, // 2
-        0xF0, 0x10, 0xF0, 0x10, 0xF0, // 3
-        0x90, 0x90, 0xF0, 0x10, 0x10, // 4
-        0xF0, 0x80, 0xF0, 0x10, 0xF0, // 5
-        0xF0, 0x80, 0xF0, 0x90, 0xF0, // 6
-        0xF0, 0x10, 0x20, 0x40, 0x40, // 7
-        0xF0, 0x90, 0xF0, 0x90, 0xF0, // 8
-        0xF0, 0x90, 0xF0, 0x10, 0xF0, // 9
-        0xF0, 0x90, 0xF0, 0x90, 0x90, // A
-        0xE0, 0x90, 0xE0, 0x90, 0xE0, // B
-        0xF0, 0x80, 0x80, 0x80, 0xF0, // C
-        0xE0, 0x90, 0x90, 0x90, 0xE0, // D
-        0xF0, 0x80, 0xF0, 0x80, 0xF0, // E
-        0xF0, 0x80, 0xF0, 0x80, 0x80,  // F
-    ];
-
-    pub fn new(audio: Option<Sink>) -> Machine {
-        let mut memory = [0; 4096];
-        memory[..0x50].copy_from_slice(&Self::FONTSET);
-        Machine {
-            memory,
-            stack: Vec::with_capacity(16),
-            pc: 0x200,
-            index_register: 0,
-            registers: [0; 16],
-            keys: [false; 16],
-            screen: [false; 64 * 32],
-            delay_timer: 0,
-            sound_timer: 0,
-            frame_timer: 0,
-            draw_flag: false,
-            audio,
-            state: State::Running,
-        }
-    }
-
-    pub fn load_program<R>(&mut self, mut program: R) where R: Read {
-        program.read(&mut self.memory[0x200..]).expect("Could not read program.");
-    }
-
-    pub fn draw_complete(&mut self) {
-        self.draw_flag = false;
-    }
-
-    pub fn key_pressed(&mut self, key: usize) {
-        self.keys[key] = true;
-    }
-
-    pub fn key_released(&mut self, key: usize) {
-        self.keys[key] = false;
-    }
-
-    pub fn fetch_opcode(&mut self) -> OpCode {
-        let opcode = (self.memory[self.pc] as u16) << 8 | self.memory[self.pc + 1] as u16;
-        self.pc += 2;
-        opcode
-    }
-
-    pub fn cycle(&mut self) -> bool {
-        match self.state {
-            State::Running => {
-                let opcode = self.fetch_opcode();
-                let x = ((opcode & 0xF000) >> 12) as usize;
-
-                Self::INSTRUCTIONS[x](self, opcode);
-                if self.frame_timer == 0 {
-                    self.delay_timer = self.delay_timer.saturating_sub(1);
-
-                    if self.sound_timer == 1 {
-                        if let Some(x) = &self.audio {
-                            x.pause();
-                        }
-                    }
-                    self.sound_timer = self.sound_timer.saturating_sub(1);
-                    self.frame_timer = TIMER_DIVIDER;
-                }
-                self.frame_timer -= 1;
-
-                if self.pc >= 0x1000 {
-                    self.state = State::Halted;
-                }
-            }
-            State::Halted => {}
-            State::WaitingForKey(x) => {
-                for (i, v) in self.keys.iter().enumerate() {
-                    if *v {
-                        self.registers[x] = i as u8;
-                        self.state = State::Running;
-                    }
-                }
+    let mut args = env::args().skip(1);
+    let mut path = None;
+    let mut profile = None;
+    let mut coverage = None;
+    let mut line_map = None;
+    let mut trace = None;
+    let mut trace_format = trace::TraceFormat::Text;
+    let mut trace_filter = trace::TraceFilter::default();
+    let mut filter = display::FilterMode::None;
+    let mut palette = None;
+    let mut palettes = palette::PaletteFile::config().unwrap_or_else(|e| panic!("Malformed palette file: {}", e));
+    let mut scaling = screen::Scaling::Aspect;
+    let mut crt = rip_8::crt::CrtSettings::config().unwrap_or_else(|e| panic!("Malformed CRT config: {}", e));
+    let mut tone = audio::ToneSettings::default();
+    let mut record_audio = None;
+    let mut headless = None;
+    let mut tui = None;
+    let mut quirks = quirks::Quirks::default();
+    let mut speed = None;
+    let mut script = None;
+    let mut gdb = None;
+    let mut console = None;
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--decompile" => {
+                let path = args.next().expect("No input file.");
+                let mut rom = Vec::new();
+                File::open(path).expect("Could not open file.").read_to_end(&mut rom).expect("Could not read program.");
+                print!("{}", decompiler::Decompiler::new(&rom).decompile());
+                return;
             }
-        }
-        self.state == State::Halted
-    }
-
-    fn invalid_opcode(&self, v: OpCode) {
-        let mut stack_string = "[".to_string();
-        for i in &self.stack {
-            stack_string.push_str(&format!("0x{:04X}, ", i));
-        }
-        stack_string = stack_string.trim_end_matches(", ").to_string();
-        stack_string.push_str("]");
-
-        let mut register_string = "[".to_string();
-        for i in &self.registers {
-            register_string.push_str(&format!("0x{:02X}, ", i));
-        }
-        register_string = stack_string.trim_end_matches(", ").to_string();
-        register_string.push_str("]");
-
-        let builder : String = format!("\
-        -----------CRASH INFO-----------\n\
-        ERROR Unknown opcode: 0x{:04X}\n\
-        --------------DATA--------------\n\
-        Stack: {}\n\
-        Current address: 0x{:04X}\n\
-        Index selector: 0x{:04X}\n\
-        Registers: {}\n\
-        ---------END CRASH INFO---------", v, stack_string, self.pc, self.index_register, register_string);
-        // TODO: full memory dump
-        eprintln!("{}", builder);
-        panic!();
-    }
-
-    fn invalid_opcode_opcode(&mut self, v: OpCode) {
-        self.invalid_opcode(v);
-    }
-
-    fn zero(&mut self, v: OpCode) {
-        match v & 0x00FF {
-            0xE0 => {
-                self.draw_flag = true;
-                self.screen = [false; 64 * 32];
+            "--profile" => profile = Some(args.next().expect("No profile output file.")),
+            "--coverage" => coverage = Some(args.next().expect("No coverage output file.")),
+            "--line-map" => line_map = Some(args.next().expect("No line map file.")),
+            "--filter" => filter = display::FilterMode::parse(&args.next().expect("No display filter."))
+                .expect("Display filter must be none, phosphor[:decay], blend[:frames] or or."),
+            "--palette" => palette = Some(args.next().expect("No palette name.")),
+            "--palettes" => {
+                let text = std::fs::read_to_string(args.next().expect("No palette file.")).expect("Could not read palette file.");
+                palettes.extend(palette::PaletteFile::parse(&text).unwrap_or_else(|e| panic!("Malformed palette file: {}", e)));
             }
-            0xEE => self.pc = self.stack.pop().unwrap() as usize,
-            _ => self.invalid_opcode(v),
-        }
-    }
-
-    fn goto(&mut self, v: OpCode) {
-        self.pc = (v & 0x0FFF) as usize;
-    }
-
-    fn call(&mut self, v: OpCode) {
-        if self.stack.len() == self.stack.capacity() {
-            panic!("Stack overflow! Stacktrace: {:?}", self.stack);
-        }
-        self.stack.push(self.pc as u16);
-        self.pc = (v & 0x0FFF) as usize;
-    }
-
-    fn cond_eq_const(&mut self, v: OpCode) {
-        let x = ((v & 0x0F00) >> 8) as usize;
-        let n = (v & 0x00FF) as u8;
-        if self.registers[x] == n {
-            self.pc += 2;
-        }
-    }
-
-    fn cond_neq_const(&mut self, v: OpCode) {
-        let x = ((v & 0x0F00) >> 8) as usize;
-        let n = (v & 0x00FF) as u8;
-        if self.registers[x] != n {
-            self.pc += 2;
-        }
-    }
-
-    fn cond_eq_reg(&mut self, v: OpCode) {
-        let x = ((v & 0x0F00) >> 8) as usize;
-        let y = ((v & 0x00F0) >> 4) as usize;
-        if self.registers[x] == self.registers[y] {
-            self.pc += 2;
-        }
-    }
-
-    fn set_const(&mut self, v: OpCode) {
-        let x = ((v & 0x0F00) >> 8) as usize;
-        let n = (v & 0x00FF) as u8;
-        self.registers[x] = n;
-    }
-
-    fn add_const(&mut self, v: OpCode) {
-        let x = ((v & 0x0F00) >> 8) as usize;
-        let n = (v & 0x00FF) as u8;
-        self.registers[x] = self.registers[x].wrapping_add(n);
-    }
-
-    fn arith(&mut self, v: OpCode) {
-        Self::ARITHMETIC[(v & 0x000F) as usize](self, v);
-    }
-
-    fn set_reg(&mut self, v: OpCode) {
-        let x = ((v & 0x0F00) >> 8) as usize;
-        let y = ((v & 0x00F0) >> 4) as usize;
-        self.registers[x] = self.registers[y];
-    }
-
-    fn bit_or(&mut self, v: OpCode) {
-        let x = ((v & 0x0F00) >> 8) as usize;
-        let y = ((v & 0x00F0) >> 4) as usize;
-        self.registers[x] = self.registers[x] | self.registers[y];
-    }
-
-    fn bit_and(&mut self, v: OpCode) {
-        let x = ((v & 0x0F00) >> 8) as usize;
-        let y = ((v & 0x00F0) >> 4) as usize;
-        self.registers[x] = self.registers[x] & self.registers[y];
-    }
-
-    fn bit_xor(&mut self, v: OpCode) {
-        let x = ((v & 0x0F00) >> 8) as usize;
-        let y = ((v & 0x00F0) >> 4) as usize;
-        self.registers[x] = self.registers[x] ^ self.registers[y];
-    }
-
-    fn add_reg(&mut self, v: OpCode) {
-        let x = ((v & 0x0F00) >> 8) as usize;
-        let y = ((v & 0x00F0) >> 4) as usize;
-
-        let (result, overflow) = self.registers[x].overflowing_add(self.registers[y]);
-        self.registers[0xF] = overflow as u8;
-        self.registers[x] = result;
-    }
-
-    fn sub_reg(&mut self, v: OpCode) {
-        let x = ((v & 0x0F00) >> 8) as usize;
-        let y = ((v & 0x00F0) >> 4) as usize;
-
-        let (result, overflow) = self.registers[x].overflowing_sub(self.registers[y]);
-        self.registers[0xF] = (!overflow) as u8;
-        self.registers[x] = result;
-    }
-
-    fn shift_right(&mut self, v: OpCode) {
-        let x = ((v & 0x0F00) >> 8) as usize;
-        self.registers[0xF] = self.registers[x] & 0x1;
-        self.registers[x] >>= 1;
-    }
-
-    fn shift_left(&mut self, v: OpCode) {
-        let x = ((v & 0x0F00) >> 8) as usize;
-        self.registers[0xF] = self.registers[x] >> 7;
-        self.registers[x] <<= 1;
-    }
-
-    fn rev_sub(&mut self, v: OpCode) {
-        let x = ((v & 0x0F00) >> 8) as usize;
-        let y = ((v & 0x00F0) >> 4) as usize;
-
-        let (result, overflow) = self.registers[y].overflowing_sub(self.registers[x]);
-        self.registers[0xF] = (!overflow) as u8;
-        self.registers[x] = result;
-    }
-
-    fn cond_neq_reg(&mut self, v: OpCode) {
-        let x = ((v & 0x0F00) >> 8) as usize;
-        let y = ((v & 0x00F0) >> 4) as usize;
-        if self.registers[x] != self.registers[y] {
-            self.pc += 2;
-        }
-    }
-
-    fn set_index(&mut self, v: OpCode) {
-        self.index_register = v & 0x0FFF;
-    }
-
-    fn jump(&mut self, v: OpCode) {
-        self.pc = (v & 0x0FFF) as usize + self.registers[0x0] as usize;
-    }
-
-    fn rand(&mut self, v: OpCode) {
-        let x = ((v & 0x0F00) >> 8) as usize;
-        let n = (v & 0x00FF) as u8;
-        self.registers[x] = rand::random::<u8>() & n;
-    }
-
-    fn draw(&mut self, v: OpCode) {
-        let x = ((v & 0x0F00) >> 8) as usize;
-        let y = ((v & 0x00F0) >> 4) as usize;
-        let base_y = self.registers[y] as usize;
-        let base_x = self.registers[x] as usize;
-        let n = (v & 0x000F) as usize;
-        self.registers[0xF] = 0;
-        for i in 0..n {
-            if self.index_register as usize + i >= self.memory.len() {
-                break;
+            "--scaling" => scaling = screen::Scaling::parse(&args.next().expect("No scaling mode."))
+                .expect("Scaling must be integer or aspect."),
+            "--crt" => crt = Some(args.next().expect("No CRT preset.")),
+            "--waveform" => tone.waveform = audio::Waveform::parse(&args.next().expect("No waveform."))
+                .expect("Waveform must be square, sine or triangle."),
+            // Audible range, and below the Nyquist frequency of SAMPLE_RATE.
+            "--pitch" => tone.pitch = args.next().expect("No pitch.").parse::<f32>().ok().filter(|pitch| pitch.is_finite())
+                .expect("Malformed pitch.").clamp(20.0, 20_000.0),
+            "--volume" => tone.volume = args.next().expect("No volume.").parse::<f32>().expect("Malformed volume.").clamp(0.0, 1.0),
+            "--record-audio" => record_audio = Some(args.next().expect("No audio output file.")),
+            "--headless" => headless = Some(args.next().expect("No frame count.").parse().expect("Malformed frame count.")),
+            "--tui" => tui = Some(terminal::Glyphs::HalfBlock),
+            "--glyphs" => tui = Some(terminal::Glyphs::parse(&args.next().expect("No glyph set."))
+                .expect("Glyphs must be half or braille.")),
+            "--quirks" => {
+                let profile = args.next().expect("No quirk profile.");
+                quirks = quirks::Quirks::profile(&profile)
+                    .unwrap_or_else(|| panic!("Unknown quirk profile {}, expected one of {:?}.", profile, quirks::Quirks::PROFILES));
             }
-            let sprite = self.memory[self.index_register as usize + i];
-            let sprite_y = base_y + i;
-            for j in 0..8 {
-                let sprite_x = base_x + j;
-                let pixel = (sprite >> (7 - j)) & 0x1;
-                let pixel_index = sprite_y * 64 + sprite_x;
-                if pixel_index >= self.screen.len() || pixel == 0 {
-                    continue;
-                }
-                if self.screen[pixel_index] {
-                    self.registers[0xF] = 1;
-                }
-                self.screen[pixel_index] = !self.screen[pixel_index];
+            "--speed" => speed = Some(args.next().expect("No speed.").parse::<u8>().expect("Malformed speed.")),
+            "--gdb" => gdb = Some(args.next().expect("No GDB port.").parse::<u16>().expect("Malformed GDB port.")),
+            "--console" => console = Some(repl::Repl::new()),
+            "--script" => script = Some(args.next().expect("No script file.")),
+            "--trace" => trace = Some(args.next().expect("No trace output file.")),
+            "--trace-format" => trace_format = match args.next().as_deref() {
+                Some("text") => trace::TraceFormat::Text,
+                Some("binary") => trace::TraceFormat::Binary,
+                _ => panic!("Trace format must be text or binary."),
+            },
+            "--trace-addr" => trace_filter.parse_addresses(&args.next().expect("No address range.")).unwrap_or_else(|e| panic!("{}", e)),
+            "--trace-ops" => trace_filter.parse_classes(&args.next().expect("No opcode classes.")).unwrap_or_else(|e| panic!("{}", e)),
+            "--trace-frames" => trace_filter.parse_frames(&args.next().expect("No frame range.")).unwrap_or_else(|e| panic!("{}", e)),
+            "--dap" => {
+                dap::serve(None, Duration::new(0, 1_000_000_000u32 / 240)).expect("DAP connection failed");
+                return;
             }
-        }
-
-        self.draw_flag = true;
-    }
-
-    fn cond_key(&mut self, v: OpCode) {
-        if v & 0x00FF == 0x009E {
-            self.cond_key_pressed(v);
-        } else if v & 0x00FF == 0x00A1 {
-            self.cond_key_not_pressed(v);
-        } else {
-            self.invalid_opcode(v);
-        }
-    }
-
-    fn cond_key_pressed(&mut self, v: OpCode) {
-        let x = ((v & 0x0F00) >> 8) as usize;
-        let key = self.registers[x] as usize;
-        if self.keys[key] {
-            self.pc += 2;
-        }
-    }
-
-    fn cond_key_not_pressed(&mut self, v: OpCode) {
-        let x = ((v & 0x0F00) >> 8) as usize;
-        let key = self.registers[x] as usize;
-        if !self.keys[key] {
-            self.pc += 2;
-        }
-    }
-
-    fn util(&mut self, v: OpCode) {
-        match v & 0x00FF {
-            0x07 => self.get_delay(v),
-            0x0A => self.await_key(v),
-            0x15 => self.set_delay(v),
-            0x18 => self.set_sound(v),
-            0x1E => self.add_index(v),
-            0x29 => self.set_index_char(v),
-            0x33 => self.set_index_bcd(v),
-            0x55 => self.reg_dump(v),
-            0x65 => self.reg_load(v),
-            _ => self.invalid_opcode(v)
-        }
-    }
-
-    fn get_delay(&mut self, v: OpCode) {
-        let x = ((v & 0x0F00) >> 8) as usize;
-        self.registers[x] = self.delay_timer;
-    }
-
-    fn await_key(&mut self, v: OpCode) {
-        for i in 0..16 {
-            if self.keys[i] {
-                let x = ((v & 0x0F00) >> 8) as usize;
-                self.registers[x] = i as u8;
+            "--dap-port" => {
+                let port = args.next().expect("No DAP port.").parse().expect("Malformed DAP port.");
+                dap::serve(Some(port), Duration::new(0, 1_000_000_000u32 / 240)).expect("DAP connection failed");
                 return;
             }
-        }
-        self.state = State::WaitingForKey(((v & 0x0F00) >> 8) as usize);
-    }
-
-    fn set_delay(&mut self, v: OpCode) {
-        let x = ((v & 0x0F00) >> 8) as usize;
-        self.delay_timer = self.registers[x];
-    }
-
-    fn set_sound(&mut self, v: OpCode) {
-        let x = ((v & 0x0F00) >> 8) as usize;
-        self.sound_timer = self.registers[x];
-        if self.sound_timer != 0 {
-            if let Some(x) = &mut self.audio {
-                x.play();
+            "--trace-diff" => {
+                let left = args.next().expect("No trace file.");
+                let right = args.next().expect("No trace file.");
+                let identical = trace::diff(&left, &right).expect("Could not compare traces.");
+                std::process::exit(if identical { 0 } else { 1 });
+            }
+            _ => path = Some(arg),
+        }
+    }
+    let path = path.expect("No input file.");
+
+    let mut machine = Machine::new();
+    let mut rom = Vec::new();
+    File::open(&path).expect("Could not open file.").read_to_end(&mut rom).expect("Could not read program.");
+    machine.load_program(&rom[..]);
+    machine.seed(SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |t| t.as_nanos() as u64));
+    machine.set_quirks(quirks);
+    if let Some(speed) = speed {
+        machine.set_speed(speed.max(1));
+    }
+    if profile.is_some() {
+        machine.enable_profiler();
+    }
+    if coverage.is_some() {
+        machine.enable_coverage();
+    }
+    if let Some(trace) = trace {
+        machine.set_tracer(trace::Tracer::new(&trace, trace_format, trace_filter).expect("Could not create trace file."));
+    }
+    if record_audio.is_some() {
+        machine.set_audio_capture(audio::AudioCapture::new(tone));
+    }
+
+    let mut debugger = debugger::Debugger::new(machine);
+    debugger.set_filter(filter);
+    // --palette wins over the ROM's entry in a palette file.
+    let palette = palette.or_else(|| palettes.rom_palette(Path::new(&path)).map(str::to_string));
+    debugger.add_palettes(palettes.palettes);
+    if let Some(palette) = palette {
+        if !debugger.select_palette(&palette) {
+            panic!("Unknown palette {}.", palette);
+        }
+    }
+    if let Some(path) = script {
+        debugger.set_script(script::Script::load(&path).unwrap_or_else(|e| panic!("Could not load script {}: {}", path, e)));
+    }
+    let delay = Duration::new(0, 1_000_000_000u32 / 240);
+
+    if let Some(port) = gdb {
+        gdb::serve(&mut debugger, &mut frontend::NoDisplay, port, delay).expect("GDB connection failed");
+    } else if let Some(frames) = headless {
+        debugger.toggle_pause();
+        frontend::run(&mut debugger, &mut frontend::NoDisplay, &mut frontend::FrameLimit::new(frames), &mut frontend::Silence, &mut console, Duration::ZERO);
+    } else if let Some(glyphs) = tui {
+        if console.is_some() {
+            panic!("The console cannot share the terminal with --tui.");
+        }
+        let mut display = terminal::TerminalDisplay::new(glyphs).expect("Failed to set up terminal");
+        let mut input = terminal::TerminalInput::new(!display.reports_releases());
+        frontend::run(&mut debugger, &mut display, &mut input, &mut terminal::TerminalBell::default(), &mut frontend::NoConsole, delay);
+    } else {
+        let sdl_context = sdl2::init().unwrap();
+        let video_subsystem = sdl_context.video().unwrap();
+
+        let window = video_subsystem.window("RIP-8", 1024, 512)
+            .position_centered()
+            .resizable()
+            .build()
+            .expect("Failed to create window");
+        let dbg_window = video_subsystem.window("Debug", 1024, 512)
+            .position_centered()
+            .build()
+            .expect("Failed to create window");
+        let dbg_id = dbg_window.id();
+
+        let canvas = window.into_canvas().build().expect("Failed to create canvas");
+        let dbg_canvas = dbg_window.into_canvas().build().expect("Failed to create canvas");
+
+        let texture_creator = canvas.texture_creator();
+        let mut screen = screen::Screen::new(canvas, &texture_creator, 64, 32);
+        screen.set_scaling(scaling);
+        if let Some(crt) = crt {
+            if !screen.set_crt(&crt) {
+                panic!("Unknown CRT preset {}, expected one of {:?}.", crt, rip_8::crt::CrtSettings::PRESETS);
             }
         }
-    }
+        screen.present();
 
-    fn add_index(&mut self, v: OpCode) {
-        self.index_register += self.registers[v as usize & 0x0F00 >> 8] as u16;
+        let mut display = sdl::SdlDisplay::new(screen, dbg_canvas);
+        let mut input = sdl::SdlInput::new(sdl_context.event_pump().expect("Failed to create event pump"), dbg_id);
+        frontend::run(&mut debugger, &mut display, &mut input, &mut audio::Audio::new(tone), &mut console, delay);
     }
 
-    fn set_index_char(&mut self, v: OpCode) {
-        let x = ((v & 0x0F00) >> 8) as usize;
-        self.index_register = self.registers[x] as u16 * 5;
+    if let Some(tracer) = debugger.machine_mut().tracer_mut() {
+        tracer.finish().unwrap_or_else(|e| panic!("Could not write trace: {}", e));
     }
-
-    fn set_index_bcd(&mut self, v: OpCode) {
-        let x = ((v & 0x0F00) >> 8) as usize;
-        let bcd = self.registers[x];
-        self.memory[self.index_register as usize] = bcd / 100;
-        self.memory[self.index_register as usize + 1] = (bcd / 10) % 10;
-        self.memory[self.index_register as usize + 2] = bcd % 10;
+    write_reports(debugger.machine(), &rom, profile, coverage, line_map, record_audio);
+    if let Some(error) = debugger.script_error() {
+        eprintln!("Script error: {}", error);
+        std::process::exit(1);
     }
+}
 
-    fn reg_dump(&mut self, v: OpCode) {
-        let x = ((v & 0x0F00) >> 8) as usize;
-        for i in 0..=x {
-            self.memory[self.index_register as usize + i] = self.registers[i];
-        }
-        self.index_register += x as u16 + 1;
+fn write_reports(machine: &Machine, rom: &[u8], profile: Option<String>, coverage: Option<String>, line_map: Option<String>, record_audio: Option<String>) {
+    if let (Some(path), Some(profiler)) = (profile, machine.profiler()) {
+        let mut file = File::create(path).expect("Could not create profile report.");
+        profiler.write_report(&mut file, machine.memory()).expect("Could not write profile report.");
+    }
+
+    if let (Some(report), Some(coverage)) = (coverage, machine.coverage()) {
+        let map = match line_map {
+            Some(line_map) => coverage::LineMap::parse(&std::fs::read_to_string(line_map).expect("Could not read line map."))
+                .unwrap_or_else(|e| panic!("{}", e)),
+            None => {
+                // A distinct name, so a report next to the program's source never replaces it.
+                let listing_path = Path::new(&report).with_extension("listing.8o");
+                let (listing, lines) = decompiler::Decompiler::new(rom).decompile_with_lines();
+                std::fs::write(&listing_path, listing).expect("Could not write listing.");
+                coverage::LineMap::from_listing(&listing_path.to_string_lossy(), &lines)
+            }
+        };
+        let mut file = File::create(report).expect("Could not create coverage report.");
+        coverage.write_lcov(&mut file, &map, machine.memory()).expect("Could not write coverage report.");
     }
 
-    fn reg_load(&mut self, v: OpCode) {
-        let x = ((v & 0x0F00) >> 8) as usize;
-        for i in 0..=x {
-            self.registers[i] = self.memory[self.index_register as usize + i];
-        }
-        self.index_register += x as u16 + 1;
+    if let (Some(path), Some(capture)) = (record_audio, machine.audio_capture()) {
+        let mut file = File::create(path).expect("Could not create audio file.");
+        capture.write_wav(&mut file).expect("Could not write audio file.");
     }
-}
\ No newline at end of file
+}
@@ -0,0 +1,96 @@
+use crate::frontend::MemoryAction;
+use crate::Machine;
+
+// Where Machine::new loads the hex font.
+pub const FONT: std::ops::Range<usize> = 0x000..0x050;
+
+// How long a write stays highlighted, in timer frames.
+pub const RECENT_FRAMES: u64 = 30;
+
+// The debug window's hex editor: the selected byte, any half-typed edit or jump address, and the
+// frame each byte was last written in.
+pub struct MemoryView {
+    selected: usize,
+    // The high nibble of an edit in progress.
+    nibble: Option<u8>,
+    // Hex digits typed since a jump was started.
+    jump: Option<String>,
+    written: Vec<Option<u64>>,
+}
+
+impl MemoryView {
+    pub fn new(size: usize) -> Self {
+        MemoryView { selected: 0x200, nibble: None, jump: None, written: vec![None; size] }
+    }
+
+    pub fn selected(&self) -> usize {
+        self.selected
+    }
+
+    pub fn pending_nibble(&self) -> Option<u8> {
+        self.nibble
+    }
+
+    pub fn jump_entry(&self) -> Option<&str> {
+        self.jump.as_deref()
+    }
+
+    // Frames since the byte was written, if that was recent enough to highlight.
+    pub fn write_age(&self, address: usize, frame: u64) -> Option<u64> {
+        self.written.get(address).copied().flatten().map(|written| frame.saturating_sub(written)).filter(|age| *age < RECENT_FRAMES)
+    }
+
+    pub(crate) fn record(&mut self, machine: &Machine) {
+        if let Some((start, length)) = machine.last_write() {
+            let size = self.written.len();
+            for address in (start..start + length).map(|a| a % size) {
+                self.written[address] = Some(machine.frames());
+            }
+        }
+    }
+
+    // Bytes can only be edited while the machine is paused; everything else works while it runs.
+    pub(crate) fn handle(&mut self, action: MemoryAction, machine: &mut Machine, paused: bool) {
+        let size = self.written.len();
+        match action {
+            MemoryAction::Move(offset) => self.select(self.selected as isize + offset),
+            MemoryAction::GoToPc => self.select(machine.pc() as isize),
+            MemoryAction::GoToIndex => self.select(machine.index_register() as isize),
+            MemoryAction::StartJump => {
+                self.jump = Some(String::new());
+                self.nibble = None;
+            }
+            MemoryAction::Digit(digit) => match (&mut self.jump, self.nibble) {
+                (Some(jump), _) => {
+                    if usize::from_str_radix(&format!("{}{:X}", jump, digit), 16).is_ok_and(|address| address < size) {
+                        jump.push_str(&format!("{:X}", digit));
+                    }
+                }
+                (None, _) if !paused => {}
+                (None, None) => self.nibble = Some(digit),
+                (None, Some(high)) => {
+                    machine.write_memory(self.selected, high << 4 | digit);
+                    self.written[self.selected] = Some(machine.frames());
+                    self.select(self.selected as isize + 1);
+                }
+            },
+            MemoryAction::Enter => {
+                if let Some(address) = self.jump.take().and_then(|jump| usize::from_str_radix(&jump, 16).ok()) {
+                    self.select(address as isize);
+                }
+            }
+            MemoryAction::Erase => match &mut self.jump {
+                Some(jump) if !jump.is_empty() => {
+                    jump.pop();
+                }
+                Some(_) => self.jump = None,
+                None => self.nibble = None,
+            },
+        }
+    }
+
+    fn select(&mut self, address: isize) {
+        self.selected = address.clamp(0, self.written.len() as isize - 1) as usize;
+        self.nibble = None;
+    }
+}
@@ -0,0 +1,159 @@
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::{env, fs, io};
+
+pub type Rgb = (u8, u8, u8);
+
+// Colours are background, plane 1, plane 2 and both planes; monochrome output only uses the first two.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Palette {
+    pub name: String,
+    pub colors: [Rgb; 4],
+}
+
+impl Palette {
+    pub fn builtin() -> Vec<Palette> {
+        let palette = |name: &str, colors| Palette { name: name.to_string(), colors };
+        vec![
+            palette("classic", [(0x00, 0x00, 0x00), (0xFF, 0xFF, 0xFF), (0xAA, 0xAA, 0xAA), (0x55, 0x55, 0x55)]),
+            palette("amber", [(0x1A, 0x0F, 0x00), (0xFF, 0xB0, 0x00), (0xB3, 0x6B, 0x00), (0x66, 0x3D, 0x00)]),
+            palette("green", [(0x00, 0x14, 0x00), (0x33, 0xFF, 0x33), (0x1A, 0xA3, 0x1A), (0x0D, 0x5C, 0x0D)]),
+            palette("octo", [(0x99, 0x66, 0x00), (0xFF, 0xCC, 0x00), (0xFF, 0x66, 0x00), (0x66, 0x22, 0x00)]),
+            palette("high-contrast", [(0x00, 0x00, 0x00), (0xFF, 0xFF, 0xFF), (0xFF, 0xFF, 0x00), (0x00, 0xFF, 0xFF)]),
+        ]
+    }
+
+    fn parse_line(number: usize, name: &str, colors: &str) -> Result<Palette, String> {
+        let colors = colors.split_whitespace()
+            .map(|c| Self::parse_color(c).ok_or(format!("line {}: malformed colour {}", number, c)))
+            .collect::<Result<Vec<_>, _>>()?;
+        let colors = match colors[..] {
+            [background, foreground] => [background, foreground, foreground, foreground],
+            [background, plane1, plane2, both] => [background, plane1, plane2, both],
+            _ => return Err(format!("line {}: expected 2 or 4 colours", number)),
+        };
+        Ok(Palette { name: name.trim().to_string(), colors })
+    }
+
+    fn parse_color(text: &str) -> Option<Rgb> {
+        let hex = text.strip_prefix('#')?;
+        // from_str_radix would also take a sign.
+        if hex.len() != 6 || !hex.bytes().all(|b| b.is_ascii_hexdigit()) {
+            return None;
+        }
+        let value = u32::from_str_radix(hex, 16).ok()?;
+        Some(((value >> 16) as u8, (value >> 8) as u8, value as u8))
+    }
+
+    pub fn background(&self) -> Rgb {
+        self.colors[0]
+    }
+
+    // Blends background and foreground for the filtered intensity of a monochrome pixel.
+    pub fn shade(&self, intensity: f32) -> Rgb {
+        let (b, f) = (self.colors[0], self.colors[1]);
+        let mix = |b: u8, f: u8| (b as f32 + (f as f32 - b as f32) * intensity).round() as u8;
+        (mix(b.0, f.0), mix(b.1, f.1), mix(b.2, f.2))
+    }
+}
+
+// Custom palettes and the palette each ROM starts with, from ~/.rip8_palettes and --palettes files.
+#[derive(Debug, Default, PartialEq)]
+pub struct PaletteFile {
+    pub palettes: Vec<Palette>,
+    // ROM file name to palette name.
+    pub roms: HashMap<String, String>,
+}
+
+impl PaletteFile {
+    // One entry per line: `name = #RRGGBB #RRGGBB [#RRGGBB #RRGGBB]` defines a palette and
+    // `rom file.ch8 = name` picks one for a ROM. Blank lines and `#` comments are skipped.
+    pub fn parse(text: &str) -> Result<Self, String> {
+        let mut file = PaletteFile::default();
+        for (number, line) in text.lines().enumerate().map(|(i, l)| (i + 1, l.trim())) {
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let (name, value) = line.split_once('=').ok_or(format!("line {}: expected `name = colours`", number))?;
+            match name.strip_prefix("rom ") {
+                Some(rom) => {
+                    file.roms.insert(rom.trim().to_string(), value.trim().to_string());
+                }
+                None => file.palettes.push(Palette::parse_line(number, name, value)?),
+            }
+        }
+        Ok(file)
+    }
+
+    // The config file is optional; a missing one is empty.
+    pub fn config() -> Result<Self, String> {
+        let path = match env::var_os("HOME") {
+            Some(home) => PathBuf::from(home).join(".rip8_palettes"),
+            None => return Ok(PaletteFile::default()),
+        };
+        match fs::read_to_string(&path) {
+            Ok(text) => Self::parse(&text).map_err(|e| format!("{}: {}", path.display(), e)),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(PaletteFile::default()),
+            Err(e) => Err(format!("{}: {}", path.display(), e)),
+        }
+    }
+
+    // Later files override earlier ones.
+    pub fn extend(&mut self, other: PaletteFile) {
+        self.palettes.extend(other.palettes);
+        self.roms.extend(other.roms);
+    }
+
+    pub fn rom_palette(&self, rom: &Path) -> Option<&str> {
+        let name = rom.file_name()?.to_str()?;
+        self.roms.get(name).map(String::as_str)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_colors() {
+        assert_eq!(Palette::parse_color("#1A2b3C"), Some((0x1A, 0x2B, 0x3C)));
+        assert_eq!(Palette::parse_color("1A2B3C"), None);
+        assert_eq!(Palette::parse_color("#1A2B3"), None);
+        assert_eq!(Palette::parse_color("#+1A2B3"), None);
+        assert_eq!(Palette::parse_color("#-1A2B3"), None);
+        assert_eq!(Palette::parse_color("#1A2B3G"), None);
+    }
+
+    #[test]
+    fn parses_palettes_and_roms() {
+        let file = PaletteFile::parse("# comment\n\nmono = #000000 #FFFFFF\nquad=#000000 #FF0000 #00FF00 #0000FF\nrom pong 2.ch8 = mono\n").unwrap();
+        assert_eq!(file.palettes, [
+            Palette { name: "mono".to_string(), colors: [(0, 0, 0), (0xFF, 0xFF, 0xFF), (0xFF, 0xFF, 0xFF), (0xFF, 0xFF, 0xFF)] },
+            Palette { name: "quad".to_string(), colors: [(0, 0, 0), (0xFF, 0, 0), (0, 0xFF, 0), (0, 0, 0xFF)] },
+        ]);
+        assert_eq!(file.rom_palette(Path::new("roms/pong 2.ch8")), Some("mono"));
+        assert_eq!(file.rom_palette(Path::new("roms/tetris.ch8")), None);
+    }
+
+    #[test]
+    fn reports_the_bad_line() {
+        assert_eq!(PaletteFile::parse("a = #000000 #FFFFFF\nb #000000"), Err("line 2: expected `name = colours`".to_string()));
+        assert_eq!(PaletteFile::parse("b = #000000 #+FFFFF"), Err("line 1: malformed colour #+FFFFF".to_string()));
+        assert_eq!(PaletteFile::parse("b = #000000 #FFFFFF #FFFFFF"), Err("line 1: expected 2 or 4 colours".to_string()));
+    }
+
+    #[test]
+    fn later_files_override_earlier_ones() {
+        let mut file = PaletteFile::parse("rom pong.ch8 = amber").unwrap();
+        file.extend(PaletteFile::parse("rom pong.ch8 = green").unwrap());
+        assert_eq!(file.rom_palette(Path::new("pong.ch8")), Some("green"));
+    }
+
+    #[test]
+    fn shades_between_background_and_foreground() {
+        let palette = Palette { name: "test".to_string(), colors: [(0, 100, 200), (200, 100, 0), (0, 0, 0), (0, 0, 0)] };
+        assert_eq!(palette.shade(0.0), (0, 100, 200));
+        assert_eq!(palette.shade(0.5), (100, 100, 100));
+        assert_eq!(palette.shade(1.0), (200, 100, 0));
+    }
+}
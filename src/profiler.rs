@@ -0,0 +1,135 @@
+use std::collections::HashMap;
+use std::io::{self, Write};
+
+use crate::OpCode;
+
+const CLASSES: [&str; 16] = [
+    "0 clear/return",
+    "1 goto",
+    "2 call",
+    "3 skip eq const",
+    "4 skip neq const",
+    "5 skip eq reg",
+    "6 set const",
+    "7 add const",
+    "8 arithmetic",
+    "9 skip neq reg",
+    "A set index",
+    "B jump",
+    "C rand",
+    "D draw",
+    "E skip key",
+    "F util",
+];
+
+const ROOT: u16 = 0x200;
+
+struct Frame {
+    routine: u16,
+    entered: u64,
+    children: u64,
+}
+
+#[derive(Default)]
+struct Routine {
+    calls: u64,
+    inclusive: u64,
+    exclusive: u64,
+}
+
+pub struct Profiler {
+    cycles: u64,
+    executions: Vec<u64>,
+    classes: [u64; 16],
+    frames: Vec<Frame>,
+    routines: HashMap<u16, Routine>,
+    edges: HashMap<(u16, u16), u64>,
+}
+
+impl Profiler {
+    pub(crate) fn new() -> Self {
+        Profiler {
+            cycles: 0,
+            executions: vec![0; 4096],
+            classes: [0; 16],
+            frames: Vec::with_capacity(16),
+            routines: HashMap::new(),
+            edges: HashMap::new(),
+        }
+    }
+
+    pub(crate) fn record(&mut self, pc: usize, opcode: OpCode) {
+        self.cycles += 1;
+        if let Some(count) = self.executions.get_mut(pc) {
+            *count += 1;
+        }
+        self.classes[(opcode >> 12) as usize] += 1;
+
+        if opcode & 0xF000 == 0x2000 {
+            let callee = opcode & 0x0FFF;
+            *self.edges.entry((self.current(), callee)).or_insert(0) += 1;
+            self.frames.push(Frame { routine: callee, entered: self.cycles, children: 0 });
+        } else if opcode == 0x00EE {
+            if let Some(frame) = self.frames.pop() {
+                let inclusive = self.cycles - frame.entered;
+                let routine = self.routines.entry(frame.routine).or_default();
+                routine.calls += 1;
+                routine.inclusive += inclusive;
+                routine.exclusive += inclusive - frame.children;
+                if let Some(parent) = self.frames.last_mut() {
+                    parent.children += inclusive;
+                }
+            }
+        }
+    }
+
+    fn current(&self) -> u16 {
+        self.frames.last().map_or(ROOT, |frame| frame.routine)
+    }
+
+    pub fn hot_spots(&self, n: usize) -> Vec<(usize, u64)> {
+        let mut spots: Vec<(usize, u64)> = self.executions.iter().copied().enumerate().filter(|(_, c)| *c > 0).collect();
+        spots.sort_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(&b.0)));
+        spots.truncate(n);
+        spots
+    }
+
+    pub fn write_report<W>(&self, out: &mut W, memory: &[u8]) -> io::Result<()> where W: Write {
+        let percent = |count: u64| if self.cycles == 0 { 0.0 } else { count as f64 * 100.0 / self.cycles as f64 };
+
+        writeln!(out, "Total cycles: {}", self.cycles)?;
+        writeln!(out)?;
+        writeln!(out, "Flat profile")?;
+        writeln!(out, "  {:<8}{:<8}{:>12}{:>9}", "addr", "opcode", "count", "%")?;
+        for (addr, count) in self.hot_spots(self.executions.len()) {
+            let opcode = (memory[addr] as u16) << 8 | memory.get(addr + 1).copied().unwrap_or(0) as u16;
+            writeln!(out, "  0x{:03X}   0x{:04X}  {:>12}{:>8.2}%", addr, opcode, count, percent(count))?;
+        }
+
+        writeln!(out)?;
+        writeln!(out, "Opcode classes")?;
+        for (name, count) in CLASSES.iter().zip(self.classes.iter()) {
+            if *count > 0 {
+                writeln!(out, "  {:<20}{:>12}{:>8.2}%", name, count, percent(*count))?;
+            }
+        }
+
+        writeln!(out)?;
+        writeln!(out, "Subroutines")?;
+        writeln!(out, "  {:<8}{:>10}{:>14}{:>14}", "addr", "calls", "inclusive", "self")?;
+        let mut routines: Vec<_> = self.routines.iter().collect();
+        routines.sort_by(|a, b| b.1.inclusive.cmp(&a.1.inclusive).then(a.0.cmp(b.0)));
+        for (addr, routine) in routines {
+            writeln!(out, "  0x{:03X}   {:>10}{:>14}{:>14}", addr, routine.calls, routine.inclusive, routine.exclusive)?;
+        }
+
+        writeln!(out)?;
+        writeln!(out, "Call graph")?;
+        let mut edges: Vec<_> = self.edges.iter().collect();
+        edges.sort();
+        for ((caller, callee), count) in edges {
+            writeln!(out, "  0x{:03X} -> 0x{:03X}{:>12}", caller, callee, count)?;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,35 @@
+// Behaviours that differ between CHIP-8 interpreters; the default profile is this emulator's own.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub struct Quirks {
+    // 8XY6/8XYE shift VY into VX instead of shifting VX in place.
+    pub shift_vy: bool,
+    // FX55/FX65 advance I past the last register.
+    pub increment_index: bool,
+    // BNNN jumps to XNN + VX instead of NNN + V0.
+    pub jump_vx: bool,
+    // 8XY1/8XY2/8XY3 clear VF.
+    pub vf_reset: bool,
+    // Sprites wrap around the screen edges instead of being clipped.
+    pub wrap_sprites: bool,
+}
+
+impl Quirks {
+    pub const PROFILES: [&'static str; 4] = ["default", "cosmac", "schip", "xo-chip"];
+
+    pub fn profile(name: &str) -> Option<Self> {
+        let quirks = |shift_vy, increment_index, jump_vx, vf_reset, wrap_sprites| Quirks { shift_vy, increment_index, jump_vx, vf_reset, wrap_sprites };
+        match name {
+            "default" => Some(quirks(false, true, false, false, false)),
+            "cosmac" => Some(quirks(true, true, false, true, false)),
+            "schip" => Some(quirks(false, false, true, false, false)),
+            "xo-chip" => Some(quirks(true, true, false, false, true)),
+            _ => None,
+        }
+    }
+}
+
+impl Default for Quirks {
+    fn default() -> Self {
+        Self::profile("default").unwrap()
+    }
+}
@@ -0,0 +1,332 @@
+use std::env;
+use std::path::PathBuf;
+use std::sync::mpsc::{self, Receiver, Sender, TryRecvError};
+use std::thread;
+
+use rustyline::error::ReadlineError;
+use rustyline::{DefaultEditor, ExternalPrinter};
+
+use crate::debugger::{Debugger, Stop};
+use crate::decompiler::Decompiler;
+use crate::frontend::Console;
+use crate::Machine;
+
+const HELP: &str = "\
+break [ADDR]          set a breakpoint, or list them
+delete ADDR           remove a breakpoint
+watch [ADDR [LEN]]    pause after memory is written, or list watchpoints
+unwatch ADDR [LEN]    remove a watchpoint
+step [N]              run N instructions
+next                  step over a call
+finish                run until the current subroutine returns
+until ADDR            run until pc reaches ADDR
+continue              run until something stops the machine
+pause                 stop running (also Ctrl-C)
+regs                  show the registers
+set REG|ADDR VALUE    change V0-VF, I, PC, DT, ST or a byte of memory
+x/N [ADDR]            dump N bytes of memory
+disasm [ADDR] [N]     disassemble N instructions
+bt                    show the call stack
+sprite [ADDR] [N]     draw an N-row sprite
+save|load [FILE]      save or restore the machine
+history               list earlier commands; !N repeats one
+quit                  leave the emulator
+Addresses and values are decimal, 0x-prefixed hex, I or PC. An empty line repeats the last command.";
+
+// Debugger commands read from stdin on a separate thread, so the frontend keeps running while the prompt waits.
+pub struct Repl {
+    lines: Receiver<String>,
+    done: Sender<()>,
+    printer: Option<Box<dyn ExternalPrinter + Send>>,
+    history: Vec<String>,
+    saved: Option<Vec<u8>>,
+}
+
+impl Repl {
+    pub fn new() -> Repl {
+        let (line_sender, lines) = mpsc::channel();
+        let (done, ready) = mpsc::channel();
+        let (printer_sender, printer) = mpsc::channel();
+        thread::spawn(move || {
+            let mut editor = DefaultEditor::new().expect("Could not set up the console");
+            let _ = printer_sender.send(editor.create_external_printer().ok().map(|p| Box::new(p) as Box<dyn ExternalPrinter + Send>));
+            let history = env::var_os("HOME").map(|home| PathBuf::from(home).join(".rip8_history"));
+            if let Some(path) = &history {
+                let _ = editor.load_history(path);
+            }
+            loop {
+                let line = match editor.readline("(rip8) ") {
+                    Ok(line) => {
+                        if !line.trim().is_empty() {
+                            let _ = editor.add_history_entry(line.as_str());
+                            if let Some(path) = &history {
+                                let _ = editor.save_history(path);
+                            }
+                        }
+                        line
+                    }
+                    Err(ReadlineError::Interrupted) => "pause".to_string(),
+                    Err(_) => "quit".to_string(),
+                };
+                if line_sender.send(line).is_err() || ready.recv().is_err() {
+                    return;
+                }
+            }
+        });
+        Repl { lines, done, printer: printer.recv().ok().flatten(), history: Vec::new(), saved: None }
+    }
+
+    // For messages that arrive while the prompt is showing.
+    fn print(&mut self, message: String) {
+        match &mut self.printer {
+            Some(printer) => {
+                let _ = printer.print(message + "\n");
+            }
+            None => println!("{}", message),
+        }
+    }
+
+    fn execute(&mut self, debugger: &mut Debugger, line: &str) -> bool {
+        let line = match line.trim() {
+            "" => match self.history.last() {
+                Some(last) => last.clone(),
+                None => return true,
+            },
+            line => match line.strip_prefix('!').map(str::parse::<usize>) {
+                Some(Ok(n)) if (1..=self.history.len()).contains(&n) => self.history[n - 1].clone(),
+                Some(_) => {
+                    println!("No such command in the history.");
+                    return true;
+                }
+                None => line.to_string(),
+            },
+        };
+        self.history.push(line.clone());
+        let words: Vec<&str> = line.split_whitespace().collect();
+        match self.command(debugger, &words) {
+            Ok(keep_running) => keep_running,
+            Err(message) => {
+                println!("{}", message);
+                true
+            }
+        }
+    }
+
+    fn command(&mut self, debugger: &mut Debugger, words: &[&str]) -> Result<bool, String> {
+        let machine = debugger.machine();
+        let argument = |n: usize| words.get(n).map(|word| value(machine, word)).transpose();
+        match words[0] {
+            "help" | "h" => println!("{}", HELP),
+            "break" | "b" => match argument(1)? {
+                Some(address) => {
+                    debugger.set_breakpoint(address);
+                    println!("Breakpoint at 0x{:03X}.", address);
+                }
+                None => debugger.breakpoints().iter().for_each(|address| println!("0x{:03X}  {}", address, instruction(machine, *address))),
+            },
+            "delete" | "d" => {
+                let address = argument(1)?.ok_or("delete needs an address.")?;
+                if !debugger.clear_breakpoint(address) {
+                    return Err(format!("No breakpoint at 0x{:03X}.", address));
+                }
+            }
+            "watch" | "w" => match (argument(1)?, argument(2)?) {
+                (Some(address), length) => {
+                    debugger.set_watchpoint(address, length.unwrap_or(1).max(1));
+                    println!("Watching 0x{:03X}.", address);
+                }
+                (None, _) => debugger.watchpoints().iter().for_each(|(address, length)| println!("0x{:03X}  {} byte(s)", address, length)),
+            },
+            "unwatch" => {
+                let address = argument(1)?.ok_or("unwatch needs an address.")?;
+                if !debugger.clear_watchpoint(address, argument(2)?.unwrap_or(1).max(1)) {
+                    return Err(format!("No watchpoint at 0x{:03X}.", address));
+                }
+            }
+            "step" | "s" => {
+                let count = argument(1)?.unwrap_or(1);
+                debugger.set_paused(true);
+                for _ in 0..count {
+                    debugger.step();
+                    debugger.tick();
+                    if let Some(stop) = debugger.take_stop() {
+                        println!("{}", describe(debugger, stop));
+                        return Ok(true);
+                    }
+                }
+                println!("{}", instruction(debugger.machine(), debugger.machine().pc()));
+            }
+            "next" | "n" => {
+                debugger.set_paused(true);
+                debugger.step_over();
+                if debugger.is_paused() {
+                    debugger.tick();
+                    match debugger.take_stop() {
+                        Some(stop) => println!("{}", describe(debugger, stop)),
+                        None => println!("{}", instruction(debugger.machine(), debugger.machine().pc())),
+                    }
+                }
+            }
+            "finish" | "fin" => {
+                debugger.set_paused(true);
+                if !debugger.step_out() {
+                    return Err("Not inside a subroutine.".to_string());
+                }
+            }
+            "until" | "u" => debugger.run_to(argument(1)?.ok_or("until needs an address.")?),
+            "continue" | "c" => debugger.set_paused(false),
+            "pause" => {
+                debugger.set_paused(true);
+                println!("{}", instruction(debugger.machine(), debugger.machine().pc()));
+            }
+            "regs" | "r" => {
+                for row in machine.registers().chunks(8).enumerate() {
+                    let registers: Vec<String> = row.1.iter().enumerate().map(|(x, v)| format!("V{:X} {:02X}", row.0 * 8 + x, v)).collect();
+                    println!("{}", registers.join("  "));
+                }
+                println!("I {:03X}  PC {:03X}  SP {}  DT {:02X}  ST {:02X}",
+                         machine.index_register(), machine.pc(), machine.stack().len(), machine.delay_timer(), machine.sound_timer());
+            }
+            "set" => {
+                let (target, number) = match words {
+                    [_, target, number] => (target.to_ascii_uppercase(), value(machine, number)?),
+                    _ => return Err("set needs a register or address and a value.".to_string()),
+                };
+                let byte = u8::try_from(number).map_err(|_| format!("{} does not fit in a byte.", words[2]));
+                let machine = debugger.machine_mut();
+                match target.as_str() {
+                    "I" => machine.set_index_register(u16::try_from(number).map_err(|_| format!("{} does not fit in I.", words[2]))?),
+                    "PC" if number < 4096 => machine.set_pc(number),
+                    "PC" => return Err(format!("PC {} is outside memory.", words[2])),
+                    "DT" => machine.set_delay_timer(byte?),
+                    "ST" => machine.set_sound_timer(byte?),
+                    register if register.starts_with('V') => match usize::from_str_radix(&register[1..], 16) {
+                        Ok(x) if x < 16 => machine.set_register(x, byte?),
+                        _ => return Err(format!("No register {}.", words[1])),
+                    },
+                    _ => match value(machine, words[1]) {
+                        Ok(address) if address < 4096 => machine.write_memory(address, byte?),
+                        _ => return Err(format!("Cannot set {}.", words[1])),
+                    },
+                }
+            }
+            command if command.starts_with("x/") || command == "x" => {
+                let count = match command.strip_prefix("x/") {
+                    Some(count) => count.parse().map_err(|_| format!("Malformed count {}.", count))?,
+                    None => 16,
+                };
+                let start = argument(1)?.unwrap_or(machine.index_register() as usize);
+                let memory = machine.memory();
+                let end = memory.len().min(start.saturating_add(count));
+                for row in (start..end).step_by(16) {
+                    let bytes = &memory[row..end.min(row + 16)];
+                    let hex: Vec<String> = bytes.iter().map(|b| format!("{:02X}", b)).collect();
+                    let text: String = bytes.iter().map(|b| if b.is_ascii_graphic() { *b as char } else { '.' }).collect();
+                    println!("0x{:03X}  {:<47}  {}", row, hex.join(" "), text);
+                }
+            }
+            "disasm" | "dis" => {
+                let start = argument(1)?.unwrap_or(machine.pc());
+                for address in (start..machine.memory().len() - 1).step_by(2).take(argument(2)?.unwrap_or(10)) {
+                    let marker = match (address == machine.pc(), debugger.breakpoints().contains(&address)) {
+                        (true, _) => '>',
+                        (false, true) => '*',
+                        (false, false) => ' ',
+                    };
+                    println!("{} {}", marker, instruction(machine, address));
+                }
+            }
+            "bt" => {
+                for (n, (address, entry)) in debugger.backtrace().into_iter().enumerate() {
+                    let name = entry.map_or("main".to_string(), |entry| format!("sub_{:03X}", entry));
+                    println!("#{}  0x{:03X}  {}", n, address, name);
+                }
+            }
+            "sprite" => {
+                let start = argument(1)?.unwrap_or(machine.index_register() as usize);
+                for address in start..machine.memory().len().min(start.saturating_add(argument(2)?.unwrap_or(5))) {
+                    let byte = machine.memory()[address];
+                    let row: String = (0..8).rev().map(|bit| if byte >> bit & 1 == 1 { '#' } else { '.' }).collect();
+                    println!("0x{:03X}  {:02X}  {}", address, byte, row);
+                }
+            }
+            "save" => {
+                let state = machine.save_state();
+                match words.get(1) {
+                    Some(path) => std::fs::write(path, state).map_err(|e| format!("Could not write {}: {}.", path, e))?,
+                    None => self.saved = Some(state),
+                }
+            }
+            "load" => {
+                let state = match words.get(1) {
+                    Some(path) => std::fs::read(path).map_err(|e| format!("Could not read {}: {}.", path, e))?,
+                    None => self.saved.clone().ok_or("Nothing has been saved.")?,
+                };
+                if !debugger.machine_mut().load_state(&state) {
+                    return Err("Not a valid save state.".to_string());
+                }
+            }
+            "history" => {
+                for (n, line) in self.history.iter().enumerate() {
+                    println!("{:4}  {}", n + 1, line);
+                }
+            }
+            "quit" | "q" => return Ok(false),
+            command => return Err(format!("Unknown command {}; try help.", command)),
+        }
+        Ok(true)
+    }
+}
+
+impl Default for Repl {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Console for Repl {
+    fn update(&mut self, debugger: &mut Debugger) -> bool {
+        if let Some(stop) = debugger.take_stop() {
+            self.print(describe(debugger, stop));
+        }
+        match self.lines.try_recv() {
+            Ok(line) => {
+                let keep_running = self.execute(debugger, &line);
+                let _ = self.done.send(());
+                keep_running
+            }
+            Err(TryRecvError::Empty) => true,
+            Err(TryRecvError::Disconnected) => false,
+        }
+    }
+}
+
+fn value(machine: &Machine, text: &str) -> Result<usize, String> {
+    let number = match text.to_ascii_uppercase().as_str() {
+        "I" => Some(machine.index_register() as usize),
+        "PC" => Some(machine.pc()),
+        upper => match upper.strip_prefix("0X") {
+            Some(hex) => usize::from_str_radix(hex, 16).ok(),
+            None => text.parse().ok(),
+        },
+    };
+    number.ok_or_else(|| format!("Malformed number {}.", text))
+}
+
+fn instruction(machine: &Machine, address: usize) -> String {
+    let memory = machine.memory();
+    let address = address.min(memory.len() - 2);
+    let opcode = (memory[address] as u16) << 8 | memory[address + 1] as u16;
+    format!("0x{:03X}  {:04X}  {}", address, opcode, Decompiler::disassemble(opcode))
+}
+
+fn describe(debugger: &Debugger, stop: Stop) -> String {
+    let machine = debugger.machine();
+    let here = instruction(machine, machine.pc());
+    match stop {
+        Stop::Breakpoint(_) => format!("Breakpoint: {}", here),
+        Stop::Watchpoint(address) => format!("0x{:03X} written with {:02X}: {}", address, machine.memory()[address], here),
+        Stop::Halted => format!("Halted: {}", here),
+        Stop::Step => here,
+    }
+}
@@ -0,0 +1,140 @@
+use sdl2::pixels::{Color, PixelFormatEnum};
+use sdl2::rect::Rect;
+use sdl2::render::{Canvas, Texture, TextureCreator};
+use sdl2::video::{FullscreenType, Window, WindowContext};
+
+use crate::crt::{self, CrtSettings, CRT_SCALE};
+use crate::palette::Palette;
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum Scaling {
+    Integer,
+    Aspect,
+}
+
+impl Scaling {
+    pub fn parse(text: &str) -> Option<Self> {
+        match text {
+            "integer" => Some(Scaling::Integer),
+            "aspect" => Some(Scaling::Aspect),
+            _ => None,
+        }
+    }
+}
+
+// Owns the game window's canvas and a streaming texture at the emulated resolution, uploaded once per frame.
+pub struct Screen<'a> {
+    canvas: Canvas<Window>,
+    texture: Texture<'a>,
+    crt_texture: Texture<'a>,
+    width: u32,
+    height: u32,
+    scaling: Scaling,
+    crt: usize,
+}
+
+impl<'a> Screen<'a> {
+    pub fn new(canvas: Canvas<Window>, creator: &'a TextureCreator<WindowContext>, width: u32, height: u32) -> Self {
+        let texture = creator.create_texture_streaming(PixelFormatEnum::RGB24, width, height)
+            .expect("Failed to create texture");
+        let scale = CRT_SCALE as u32;
+        let crt_texture = creator.create_texture_streaming(PixelFormatEnum::RGB24, width * scale, height * scale)
+            .expect("Failed to create texture");
+        Screen {
+            canvas,
+            texture,
+            crt_texture,
+            width,
+            height,
+            scaling: Scaling::Aspect,
+            crt: 0,
+        }
+    }
+
+    pub fn set_crt(&mut self, preset: &str) -> bool {
+        match CrtSettings::PRESETS.iter().position(|p| *p == preset) {
+            Some(index) => {
+                self.crt = index;
+                true
+            }
+            None => false,
+        }
+    }
+
+    pub fn next_crt(&mut self) {
+        self.crt = (self.crt + 1) % CrtSettings::PRESETS.len();
+    }
+
+    pub fn crt(&self) -> &'static str {
+        CrtSettings::PRESETS[self.crt]
+    }
+
+    fn crt_settings(&self) -> CrtSettings {
+        CrtSettings::preset(self.crt()).expect("Unknown CRT preset")
+    }
+
+    pub fn set_scaling(&mut self, scaling: Scaling) {
+        self.scaling = scaling;
+    }
+
+    pub fn toggle_scaling(&mut self) {
+        self.scaling = match self.scaling {
+            Scaling::Integer => Scaling::Aspect,
+            Scaling::Aspect => Scaling::Integer,
+        };
+        self.present();
+    }
+
+    pub fn toggle_fullscreen(&mut self) {
+        let window = self.canvas.window_mut();
+        let mode = match window.fullscreen_state() {
+            FullscreenType::Off => FullscreenType::Desktop,
+            _ => FullscreenType::Off,
+        };
+        window.set_fullscreen(mode).expect("Failed to change fullscreen mode");
+        self.present();
+    }
+
+    pub fn draw(&mut self, intensity: &[f32], palette: &Palette) {
+        let frame: Vec<_> = intensity.iter().map(|level| palette.shade(*level)).collect();
+        let (width, height) = (self.width as usize, self.height as usize);
+        let settings = self.crt_settings();
+        if settings.enabled() {
+            let pixels = crt::process(&frame, width, height, &settings);
+            Self::upload(&mut self.crt_texture, &pixels, width * CRT_SCALE);
+        } else {
+            let pixels: Vec<u8> = frame.iter().flat_map(|(r, g, b)| [*r, *g, *b]).collect();
+            Self::upload(&mut self.texture, &pixels, width);
+        }
+        self.present();
+    }
+
+    fn upload(texture: &mut Texture, pixels: &[u8], width: usize) {
+        texture.with_lock(None, |buffer, pitch| {
+            for (y, row) in pixels.chunks_exact(width * 3).enumerate() {
+                buffer[y * pitch..y * pitch + row.len()].copy_from_slice(row);
+            }
+        }).expect("Failed to update texture");
+    }
+
+    // Re-scales the last uploaded frame, e.g. after the window was resized.
+    pub fn present(&mut self) {
+        let target = self.target();
+        self.canvas.set_draw_color(Color::RGB(0, 0, 0));
+        self.canvas.clear();
+        let texture = if self.crt_settings().enabled() { &self.crt_texture } else { &self.texture };
+        self.canvas.copy(texture, None, target).expect("Failed to draw");
+        self.canvas.present();
+    }
+
+    fn target(&self) -> Rect {
+        let (window_width, window_height) = self.canvas.output_size().expect("Failed to query window size");
+        let scale = (window_width as f32 / self.width as f32).min(window_height as f32 / self.height as f32);
+        let scale = match self.scaling {
+            Scaling::Integer => scale.floor().max(1.0),
+            Scaling::Aspect => scale,
+        };
+        let (width, height) = ((self.width as f32 * scale) as u32, (self.height as f32 * scale) as u32);
+        Rect::new((window_width as i32 - width as i32) / 2, (window_height as i32 - height as i32) / 2, width, height)
+    }
+}
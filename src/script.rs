@@ -0,0 +1,311 @@
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::Write;
+use std::rc::Rc;
+
+use rhai::{Blob, Dynamic, Engine, EvalAltResult, FnPtr, FuncArgs, ImmutableString, AST};
+
+use crate::Machine;
+
+type ScriptResult<T> = Result<T, Box<EvalAltResult>>;
+
+// Rhai scripts for automated tests and tool-assisted runs. The top level runs once at start-up and
+// registers hooks; they are called with the machine between instructions.
+//   on_frame(|frame| ..)             after each 60 Hz timer tick
+//   on_exec(addr, || ..)             before the instruction at addr runs
+//   on_write(addr, |addr, value| ..) after an instruction writes addr; on_write(start, end, ..) for a range
+//   on_key(|key, pressed| ..)        when the user presses or releases a key
+// and can call reg(x), set_reg(x, v), pc(), set_pc(a), index(), set_index(v), delay(), set_delay(v),
+// sound(), set_sound(v), peek(a), poke(a, v), frame(), pixel(x, y), press(k), release(k),
+// screenshot(path), save_state() / save_state(path), load_state(blob or path) and quit().
+pub struct Script {
+    engine: Engine,
+    ast: AST,
+    host: Rc<RefCell<Host>>,
+}
+
+struct Host {
+    // Swapped with the debugger's machine while the script runs.
+    machine: Machine,
+    frame_hooks: Vec<FnPtr>,
+    exec_hooks: HashMap<usize, Vec<FnPtr>>,
+    write_hooks: Vec<(usize, usize, FnPtr)>,
+    key_hooks: Vec<FnPtr>,
+    quit: bool,
+    // The first error a hook or the top level raised; the script stops running after it.
+    error: Option<String>,
+}
+
+impl Script {
+    pub fn load(path: &str) -> Result<Script, String> {
+        let host = Rc::new(RefCell::new(Host {
+            machine: Machine::new(),
+            frame_hooks: Vec::new(),
+            exec_hooks: HashMap::new(),
+            write_hooks: Vec::new(),
+            key_hooks: Vec::new(),
+            quit: false,
+            error: None,
+        }));
+        let mut engine = Engine::new();
+        Self::register_hooks(&mut engine, &host);
+        Self::register_machine(&mut engine, &host);
+        let ast = engine.compile_file(path.into()).map_err(|e| e.to_string())?;
+        Ok(Script { engine, ast, host })
+    }
+
+    fn register_hooks(engine: &mut Engine, host: &Rc<RefCell<Host>>) {
+        let h = host.clone();
+        engine.register_fn("on_frame", move |hook: FnPtr| h.borrow_mut().frame_hooks.push(hook));
+        let h = host.clone();
+        engine.register_fn("on_exec", move |address: i64, hook: FnPtr| -> ScriptResult<()> {
+            h.borrow_mut().exec_hooks.entry(address_of(address)?).or_default().push(hook);
+            Ok(())
+        });
+        let h = host.clone();
+        engine.register_fn("on_write", move |address: i64, hook: FnPtr| -> ScriptResult<()> {
+            let address = address_of(address)?;
+            h.borrow_mut().write_hooks.push((address, address, hook));
+            Ok(())
+        });
+        let h = host.clone();
+        engine.register_fn("on_write", move |start: i64, end: i64, hook: FnPtr| -> ScriptResult<()> {
+            h.borrow_mut().write_hooks.push((address_of(start)?, address_of(end)?, hook));
+            Ok(())
+        });
+        let h = host.clone();
+        engine.register_fn("on_key", move |hook: FnPtr| h.borrow_mut().key_hooks.push(hook));
+        let h = host.clone();
+        engine.register_fn("quit", move || h.borrow_mut().quit = true);
+    }
+
+    fn register_machine(engine: &mut Engine, host: &Rc<RefCell<Host>>) {
+        let h = host.clone();
+        engine.register_fn("reg", move |x: i64| -> ScriptResult<i64> { Ok(h.borrow().machine.registers()[register_of(x)?] as i64) });
+        let h = host.clone();
+        engine.register_fn("set_reg", move |x: i64, value: i64| -> ScriptResult<()> {
+            h.borrow_mut().machine.set_register(register_of(x)?, byte_of(value)?);
+            Ok(())
+        });
+        let h = host.clone();
+        engine.register_fn("pc", move || h.borrow().machine.pc() as i64);
+        let h = host.clone();
+        engine.register_fn("set_pc", move |address: i64| -> ScriptResult<()> {
+            h.borrow_mut().machine.set_pc(address_of(address)?);
+            Ok(())
+        });
+        let h = host.clone();
+        engine.register_fn("index", move || h.borrow().machine.index_register() as i64);
+        let h = host.clone();
+        engine.register_fn("set_index", move |value: i64| -> ScriptResult<()> {
+            let value = u16::try_from(value).map_err(|_| format!("index {} is out of range", value))?;
+            h.borrow_mut().machine.set_index_register(value);
+            Ok(())
+        });
+        let h = host.clone();
+        engine.register_fn("delay", move || h.borrow().machine.delay_timer() as i64);
+        let h = host.clone();
+        engine.register_fn("set_delay", move |value: i64| -> ScriptResult<()> {
+            h.borrow_mut().machine.set_delay_timer(byte_of(value)?);
+            Ok(())
+        });
+        let h = host.clone();
+        engine.register_fn("sound", move || h.borrow().machine.sound_timer() as i64);
+        let h = host.clone();
+        engine.register_fn("set_sound", move |value: i64| -> ScriptResult<()> {
+            h.borrow_mut().machine.set_sound_timer(byte_of(value)?);
+            Ok(())
+        });
+        let h = host.clone();
+        engine.register_fn("peek", move |address: i64| -> ScriptResult<i64> { Ok(h.borrow().machine.memory()[address_of(address)?] as i64) });
+        let h = host.clone();
+        engine.register_fn("poke", move |address: i64, value: i64| -> ScriptResult<()> {
+            h.borrow_mut().machine.write_memory(address_of(address)?, byte_of(value)?);
+            Ok(())
+        });
+        let h = host.clone();
+        engine.register_fn("frame", move || h.borrow().machine.frames() as i64);
+        let h = host.clone();
+        engine.register_fn("pixel", move |x: i64, y: i64| h.borrow().machine.screen()[(y.rem_euclid(32) * 64 + x.rem_euclid(64)) as usize]);
+        let h = host.clone();
+        engine.register_fn("press", move |key: i64| -> ScriptResult<()> {
+            h.borrow_mut().machine.key_pressed(key_of(key)?);
+            Ok(())
+        });
+        let h = host.clone();
+        engine.register_fn("release", move |key: i64| -> ScriptResult<()> {
+            h.borrow_mut().machine.key_released(key_of(key)?);
+            Ok(())
+        });
+        let h = host.clone();
+        engine.register_fn("screenshot", move |path: ImmutableString| -> ScriptResult<()> {
+            write_screenshot(&h.borrow().machine, &path).map_err(|e| format!("could not write {}: {}", path, e).into())
+        });
+        let h = host.clone();
+        engine.register_fn("save_state", move || -> Blob { h.borrow().machine.save_state() });
+        let h = host.clone();
+        engine.register_fn("save_state", move |path: ImmutableString| -> ScriptResult<()> {
+            std::fs::write(path.as_str(), h.borrow().machine.save_state()).map_err(|e| format!("could not write {}: {}", path, e).into())
+        });
+        let h = host.clone();
+        engine.register_fn("load_state", move |state: Blob| h.borrow_mut().machine.load_state(&state));
+        let h = host.clone();
+        engine.register_fn("load_state", move |path: ImmutableString| -> ScriptResult<bool> {
+            let state = std::fs::read(path.as_str()).map_err(|e| format!("could not read {}: {}", path, e))?;
+            Ok(h.borrow_mut().machine.load_state(&state))
+        });
+    }
+
+    // Runs the top level of the script against the machine.
+    pub fn start(&mut self, machine: &mut Machine) {
+        self.with_machine(machine, |script| script.engine.run_ast(&script.ast));
+    }
+
+    pub fn quit_requested(&self) -> bool {
+        self.host.borrow().quit
+    }
+
+    pub fn error(&self) -> Option<String> {
+        self.host.borrow().error.clone()
+    }
+
+    pub fn before_cycle(&mut self, machine: &mut Machine) {
+        if machine.halted() || machine.waiting_for_key() {
+            return;
+        }
+        let hooks = self.host.borrow().exec_hooks.get(&machine.pc()).cloned();
+        if let Some(hooks) = hooks {
+            self.call(machine, hooks, ());
+        }
+    }
+
+    pub fn after_cycle(&mut self, machine: &mut Machine, frames_before: u64) {
+        if let Some((start, length)) = machine.last_write() {
+            for address in (start..start + length).map(|a| a % 4096) {
+                let hooks: Vec<FnPtr> = self.host.borrow().write_hooks.iter()
+                    .filter(|(first, last, _)| (*first..=*last).contains(&address))
+                    .map(|(_, _, hook)| hook.clone())
+                    .collect();
+                let value = machine.memory()[address] as i64;
+                self.call(machine, hooks, (address as i64, value));
+            }
+        }
+        if machine.frames() != frames_before {
+            let (hooks, frame) = (self.host.borrow().frame_hooks.clone(), machine.frames() as i64);
+            self.call(machine, hooks, (frame,));
+        }
+    }
+
+    pub fn key_event(&mut self, machine: &mut Machine, key: usize, pressed: bool) {
+        let hooks = self.host.borrow().key_hooks.clone();
+        self.call(machine, hooks, (key as i64, pressed));
+    }
+
+    fn call<A: FuncArgs + Clone>(&mut self, machine: &mut Machine, hooks: Vec<FnPtr>, args: A) {
+        if hooks.is_empty() {
+            return;
+        }
+        self.with_machine(machine, |script| {
+            for hook in &hooks {
+                let _ = hook.call::<Dynamic>(&script.engine, &script.ast, args.clone())?;
+            }
+            Ok(())
+        });
+    }
+
+    // Errors end the run like quit() does, so the caller can still write its reports.
+    fn with_machine<R>(&mut self, machine: &mut Machine, run: impl FnOnce(&Script) -> ScriptResult<R>) {
+        if self.host.borrow().error.is_some() {
+            return;
+        }
+        std::mem::swap(machine, &mut self.host.borrow_mut().machine);
+        let result = run(self);
+        let mut host = self.host.borrow_mut();
+        std::mem::swap(machine, &mut host.machine);
+        if let Err(e) = result {
+            host.error = Some(e.to_string());
+            host.quit = true;
+        }
+    }
+}
+
+fn address_of(address: i64) -> ScriptResult<usize> {
+    usize::try_from(address).ok().filter(|a| *a < 4096).ok_or_else(|| format!("address {} is outside memory", address).into())
+}
+
+fn register_of(x: i64) -> ScriptResult<usize> {
+    usize::try_from(x).ok().filter(|x| *x < 16).ok_or_else(|| format!("register {} does not exist", x).into())
+}
+
+fn key_of(key: i64) -> ScriptResult<usize> {
+    usize::try_from(key).ok().filter(|k| *k < 16).ok_or_else(|| format!("key {} is not between 0 and 15", key).into())
+}
+
+fn byte_of(value: i64) -> ScriptResult<u8> {
+    u8::try_from(value).map_err(|_| format!("{} does not fit in a byte", value).into())
+}
+
+// Binary PGM, lit pixels white.
+fn write_screenshot(machine: &Machine, path: &str) -> std::io::Result<()> {
+    let mut file = File::create(path)?;
+    write!(file, "P5\n64 32\n255\n")?;
+    let pixels: Vec<u8> = machine.screen().iter().map(|pixel| if *pixel { 0xFF } else { 0 }).collect();
+    file.write_all(&pixels)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::debugger::Debugger;
+    use crate::frontend::NoDisplay;
+
+    // Runs the script against a counting loop (v0 += 1; jump 0x200) until it quits.
+    fn run(name: &str, source: &str) -> Debugger {
+        let path = std::env::temp_dir().join(format!("rip8-script-test-{}-{}.rhai", std::process::id(), name));
+        std::fs::write(&path, source).unwrap();
+        let script = Script::load(&path.to_string_lossy());
+        std::fs::remove_file(&path).unwrap();
+        let mut machine = Machine::new();
+        machine.load_program(&[0x70, 0x01, 0x12, 0x00][..]);
+        let mut debugger = Debugger::new(machine);
+        debugger.set_paused(false);
+        debugger.set_script(script.unwrap());
+        for _ in 0..10_000 {
+            if debugger.quit_requested() {
+                break;
+            }
+            debugger.cycle(&mut NoDisplay);
+        }
+        debugger
+    }
+
+    #[test]
+    fn hooks_see_and_change_the_machine() {
+        let debugger = run("hooks", r#"
+            set_reg(2, 40);
+            on_exec(0x200, || set_reg(1, reg(1) + 1));
+            on_frame(|frame| {
+                if frame == 5 {
+                    poke(0x300, reg(0));
+                    quit();
+                }
+            });
+        "#);
+        let machine = debugger.machine();
+        assert!(debugger.quit_requested());
+        assert_eq!(debugger.script_error(), None);
+        assert_eq!(machine.frames(), 5);
+        assert_eq!(machine.registers()[1], machine.registers()[0]);
+        assert_eq!(machine.registers()[2], 40);
+        assert_eq!(machine.memory()[0x300], machine.registers()[0]);
+    }
+
+    #[test]
+    fn errors_stop_the_run_instead_of_panicking() {
+        let debugger = run("error", "on_frame(|frame| if frame == 3 { set_reg(16, 0) });");
+        assert!(debugger.quit_requested());
+        assert_eq!(debugger.machine().frames(), 3);
+        assert!(debugger.script_error().is_some_and(|e| e.contains("register 16 does not exist")));
+    }
+}
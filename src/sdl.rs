@@ -0,0 +1,252 @@
+use lazy_static::lazy_static;
+use sdl2::event::{Event, WindowEvent};
+use sdl2::gfx::primitives::DrawRenderer;
+use sdl2::keyboard::{Keycode, Mod};
+use sdl2::pixels::Color;
+use sdl2::render::Canvas;
+use sdl2::video::Window;
+use sdl2::EventPump;
+use std::collections::HashMap;
+
+use crate::debugger::Debugger;
+use crate::decompiler::Decompiler;
+use crate::frontend::{Display, DisplayAction, Input, InputSource, MemoryAction};
+use crate::memory_view;
+use crate::palette::Palette;
+use crate::screen::Screen;
+use crate::Machine;
+
+lazy_static! {
+    static ref DEFAULT_MAPPINGS: HashMap<Keycode, usize> = [
+
+    (Keycode::Num1, 0x1), (Keycode::Num2, 0x2), (Keycode::Num3, 0x3), (Keycode::Num4, 0xC),
+    (Keycode::Q   , 0x4), (Keycode::W   , 0x5), (Keycode::E   , 0x6), (Keycode::R   , 0xD),
+    (Keycode::A   , 0x7), (Keycode::S   , 0x8), (Keycode::D   , 0x9), (Keycode::F   , 0xE),
+    (Keycode::Z   , 0xA), (Keycode::X   , 0x0), (Keycode::C   , 0xB), (Keycode::V   , 0xF),
+    ].iter().copied().collect();
+}
+
+// Rows of disassembly in the debug window, half of them before the cursor.
+const CODE_ROWS: usize = 16;
+
+// Rows of the memory editor, sixteen bytes each, with the selection on the middle one.
+const MEMORY_ROWS: usize = 24;
+
+// The game window plus the debug window's canvas for the register panel.
+pub struct SdlDisplay<'a> {
+    screen: Screen<'a>,
+    dbg_canvas: Canvas<Window>,
+}
+
+impl<'a> SdlDisplay<'a> {
+    pub fn new(screen: Screen<'a>, mut dbg_canvas: Canvas<Window>) -> Self {
+        dbg_canvas.set_draw_color(Color::RGB(0, 0, 0));
+        dbg_canvas.clear();
+        dbg_canvas.present();
+        SdlDisplay { screen, dbg_canvas }
+    }
+}
+
+impl Display for SdlDisplay<'_> {
+    fn draw(&mut self, intensity: &[f32; 64 * 32], palette: &Palette) {
+        self.screen.draw(intensity, palette);
+    }
+
+    fn draw_status(&mut self, debugger: &Debugger) {
+        let machine = debugger.machine();
+        let pc = machine.pc.min(machine.memory.len() - 2);
+        let opcode = (machine.memory[pc] as u16) << 8 | machine.memory[pc + 1] as u16;
+        self.dbg_canvas.set_draw_color(Color::RGB(0, 0, 0));
+        self.dbg_canvas.clear();
+        let data_x = "REGISTERS".len() as i16 * 8 + 16;
+        let crt = self.screen.crt();
+
+        let draw_colored = |x: i16, y: i16, text: String, color: Color| {
+            for (i, c) in text.chars().enumerate() {
+                self.dbg_canvas.character(x + i as i16 * 8, y, c, color).expect("Failed to draw");
+            }
+        };
+        let draw_string = |x: i16, y: i16, text: String| draw_colored(x, y, text, Color::RGB(255, 255, 255));
+
+        draw_string(0, 10, "REGISTERS".to_string());
+
+        for (i, x) in machine.registers.iter().enumerate() {
+            draw_string(data_x+i as i16*32, 10, format!("{:02X}", x));
+        }
+
+        draw_string(0, 20, "PC/OPCODE".to_string());
+        draw_string(data_x, 20, format!("0x{:04X}  0x{:04X}", machine.pc, opcode));
+
+        draw_string(0, 30, "STACK".to_string());
+        for (i, x) in machine.stack.iter().enumerate() {
+            draw_string(data_x+i as i16*64, 30, format!("0x{:04X}, ", x));
+        }
+
+        draw_string(0, 40, "INDEX REG".to_string());
+        draw_string(data_x, 40, format!("0x{:04X}", machine.index_register));
+
+        draw_string(20*8, 40, "DELAY".to_string());
+        draw_string(data_x+20*8, 40, format!("0x{:02X}", machine.delay_timer));
+
+        draw_string(40*8, 40, "SOUND".to_string());
+        draw_string(data_x+40*8, 40, format!("0x{:02X}", machine.sound_timer));
+
+        draw_string(60*8, 40, "FILTER".to_string());
+        draw_string(data_x+60*8, 40, debugger.filter().name().to_uppercase());
+
+        draw_string(80*8, 40, "PALETTE".to_string());
+        draw_string(data_x+80*8, 40, debugger.palette().name.to_uppercase());
+
+        draw_string(0, 60, "CRT".to_string());
+        draw_string(data_x, 60, format!("{}  (F4)", crt.to_uppercase()));
+
+        if let Some(profiler) = &machine.profiler {
+            draw_string(0, 50, "HOT SPOTS".to_string());
+            for (i, (addr, count)) in profiler.hot_spots(6).iter().enumerate() {
+                draw_string(data_x+i as i16*128, 50, format!("0x{:03X}:{}", addr, count));
+            }
+        }
+
+        // The code around the run to cursor target: > marks pc, * a breakpoint, and the cursor is yellow.
+        let cursor = debugger.cursor();
+        draw_string(0, 70, "CODE".to_string());
+        let first = cursor - cursor.min(CODE_ROWS);
+        for (row, address) in (first..machine.memory.len() - 1).step_by(2).take(CODE_ROWS).enumerate() {
+            let opcode = (machine.memory[address] as u16) << 8 | machine.memory[address + 1] as u16;
+            let marker = match (address == machine.pc, debugger.breakpoints().contains(&address)) {
+                (true, _) => '>',
+                (false, true) => '*',
+                (false, false) => ' ',
+            };
+            let color = if address == cursor { Color::RGB(255, 255, 0) } else { Color::RGB(255, 255, 255) };
+            draw_colored(data_x - 16, 70 + row as i16 * 10, format!("{} 0x{:03X}  {:04X}  {}", marker, address, opcode, Decompiler::disassemble(opcode)), color);
+        }
+
+        // Memory: the selection has a yellow box, pc is green, I cyan, recent writes red and the font grey.
+        let view = debugger.memory_view();
+        let selected = view.selected();
+        draw_string(0, 250, "MEMORY".to_string());
+        match view.jump_entry() {
+            Some(entry) => draw_colored(data_x, 250, format!("GO TO 0x{}_", entry), Color::RGB(255, 255, 0)),
+            None => draw_string(data_x, 250, format!("0x{:03X}  {}", selected, if debugger.is_paused() { "" } else { "PAUSE TO EDIT" })),
+        }
+        let rows = machine.memory.len() / 16;
+        let first_row = (selected / 16).saturating_sub(MEMORY_ROWS / 2).min(rows - MEMORY_ROWS);
+        for row in 0..MEMORY_ROWS {
+            let y = 260 + row as i16 * 10;
+            let start = (first_row + row) * 16;
+            draw_string(0, y, format!("0x{:03X}", start));
+            for (column, address) in (start..start + 16).enumerate() {
+                let x = data_x - 16 + column as i16 * 24;
+                let ascii_x = data_x - 16 + 16 * 24 + 16 + column as i16 * 8;
+                let byte = machine.memory[address];
+                let color = if address == machine.pc || address == machine.pc + 1 {
+                    Color::RGB(0, 255, 0)
+                } else if address == machine.index_register as usize {
+                    Color::RGB(0, 255, 255)
+                } else if let Some(age) = view.write_age(address, machine.frames()) {
+                    Color::RGB(255, (age * 160 / memory_view::RECENT_FRAMES) as u8, (age * 160 / memory_view::RECENT_FRAMES) as u8)
+                } else if memory_view::FONT.contains(&address) {
+                    Color::RGB(128, 128, 128)
+                } else {
+                    Color::RGB(255, 255, 255)
+                };
+                let text = match view.pending_nibble() {
+                    Some(high) if address == selected => format!("{:X}_", high),
+                    _ => format!("{:02X}", byte),
+                };
+                if address == selected {
+                    self.dbg_canvas.rectangle(x - 2, y - 1, x + 17, y + 8, Color::RGB(255, 255, 0)).expect("Failed to draw");
+                }
+                draw_colored(x, y, text, color);
+                draw_colored(ascii_x, y, if byte.is_ascii_graphic() { byte as char } else { '.' }.to_string(), color);
+            }
+        }
+        self.dbg_canvas.present();
+    }
+
+    fn handle(&mut self, action: DisplayAction) -> bool {
+        match action {
+            DisplayAction::ToggleScaling => self.screen.toggle_scaling(),
+            DisplayAction::NextCrt => {
+                self.screen.next_crt();
+                return true;
+            }
+            DisplayAction::ToggleFullscreen => self.screen.toggle_fullscreen(),
+            DisplayAction::Refresh => self.screen.present(),
+        }
+        false
+    }
+}
+
+pub struct SdlInput {
+    event_pump: EventPump,
+    dbg_id: u32,
+}
+
+impl SdlInput {
+    pub fn new(event_pump: EventPump, dbg_id: u32) -> Self {
+        SdlInput { event_pump, dbg_id }
+    }
+}
+
+impl InputSource for SdlInput {
+    fn poll(&mut self, _machine: &Machine) -> Vec<Input> {
+        let mut inputs = Vec::new();
+        for event in self.event_pump.poll_iter() {
+            let input = if event.get_window_id() == Some(self.dbg_id) {
+                match event {
+                    Event::Quit {..} |
+                    Event::KeyDown { keycode: Some(Keycode::Escape), .. } => Some(Input::Quit),
+                    Event::KeyDown { keycode: Some(Keycode::Space), .. } => Some(Input::TogglePause),
+                    Event::KeyDown { keycode: Some(Keycode::Tab), .. } => Some(Input::Step),
+                    // F4 picks a CRT preset from here too, so it can be tried out while paused.
+                    Event::KeyDown { keycode: Some(Keycode::F4), .. } => Some(Input::Display(DisplayAction::NextCrt)),
+                    // Stepping uses F8-F10; the game window keeps F1-F4 and F11 for display settings.
+                    Event::KeyDown { keycode: Some(Keycode::F10), .. } => Some(Input::StepOver),
+                    Event::KeyDown { keycode: Some(Keycode::F9), .. } => Some(Input::StepOut),
+                    Event::KeyDown { keycode: Some(Keycode::F8), .. } => Some(Input::RunToCursor),
+                    Event::KeyDown { keycode: Some(Keycode::Up), keymod, .. } if keymod.intersects(Mod::LSHIFTMOD | Mod::RSHIFTMOD) => Some(Input::Memory(MemoryAction::Move(-16))),
+                    Event::KeyDown { keycode: Some(Keycode::Down), keymod, .. } if keymod.intersects(Mod::LSHIFTMOD | Mod::RSHIFTMOD) => Some(Input::Memory(MemoryAction::Move(16))),
+                    Event::KeyDown { keycode: Some(Keycode::PageUp), keymod, .. } if keymod.intersects(Mod::LSHIFTMOD | Mod::RSHIFTMOD) => Some(Input::Memory(MemoryAction::Move(-16 * MEMORY_ROWS as isize))),
+                    Event::KeyDown { keycode: Some(Keycode::PageDown), keymod, .. } if keymod.intersects(Mod::LSHIFTMOD | Mod::RSHIFTMOD) => Some(Input::Memory(MemoryAction::Move(16 * MEMORY_ROWS as isize))),
+                    Event::KeyDown { keycode: Some(Keycode::Up), .. } => Some(Input::MoveCursor(-1)),
+                    Event::KeyDown { keycode: Some(Keycode::Down), .. } => Some(Input::MoveCursor(1)),
+                    Event::KeyDown { keycode: Some(Keycode::PageUp), .. } => Some(Input::MoveCursor(-(CODE_ROWS as isize))),
+                    Event::KeyDown { keycode: Some(Keycode::PageDown), .. } => Some(Input::MoveCursor(CODE_ROWS as isize)),
+                    Event::KeyDown { keycode: Some(Keycode::Home), .. } => Some(Input::ResetCursor),
+                    Event::KeyDown { keycode: Some(Keycode::Left), .. } => Some(Input::Memory(MemoryAction::Move(-1))),
+                    Event::KeyDown { keycode: Some(Keycode::Right), .. } => Some(Input::Memory(MemoryAction::Move(1))),
+                    Event::MouseWheel { y, .. } => Some(Input::Memory(MemoryAction::Move(-y as isize * 64))),
+                    Event::KeyDown { keycode: Some(Keycode::P), .. } => Some(Input::Memory(MemoryAction::GoToPc)),
+                    Event::KeyDown { keycode: Some(Keycode::I), .. } => Some(Input::Memory(MemoryAction::GoToIndex)),
+                    Event::KeyDown { keycode: Some(Keycode::G), .. } => Some(Input::Memory(MemoryAction::StartJump)),
+                    Event::KeyDown { keycode: Some(Keycode::Return), .. } => Some(Input::Memory(MemoryAction::Enter)),
+                    Event::KeyDown { keycode: Some(Keycode::Backspace), .. } => Some(Input::Memory(MemoryAction::Erase)),
+                    // 0-9 and A-F type into the memory editor.
+                    Event::KeyDown { keycode: Some(x), .. } => u8::from_str_radix(&x.name(), 16).ok()
+                        .filter(|_| x.name().len() == 1)
+                        .map(|digit| Input::Memory(MemoryAction::Digit(digit))),
+                    _ => None,
+                }
+            } else {
+                match event {
+                    Event::Quit { .. } |
+                    Event::KeyDown { keycode: Some(Keycode::Escape), .. } => Some(Input::Quit),
+                    Event::KeyDown { keycode: Some(Keycode::F1), .. } => Some(Input::NextFilter),
+                    Event::KeyDown { keycode: Some(Keycode::F2), .. } => Some(Input::NextPalette),
+                    Event::KeyDown { keycode: Some(Keycode::F3), .. } => Some(Input::Display(DisplayAction::ToggleScaling)),
+                    Event::KeyDown { keycode: Some(Keycode::F4), .. } => Some(Input::Display(DisplayAction::NextCrt)),
+                    Event::KeyDown { keycode: Some(Keycode::F11), .. } => Some(Input::Display(DisplayAction::ToggleFullscreen)),
+                    Event::Window { win_event: WindowEvent::Resized(..), .. } |
+                    Event::Window { win_event: WindowEvent::Exposed, .. } => Some(Input::Display(DisplayAction::Refresh)),
+                    Event::KeyDown { keycode: Some(x), .. } => DEFAULT_MAPPINGS.get(&x).map(|key| Input::Press(*key)),
+                    Event::KeyUp { keycode: Some(x), .. } => DEFAULT_MAPPINGS.get(&x).map(|key| Input::Release(*key)),
+                    _ => None,
+                }
+            };
+            inputs.extend(input);
+        }
+        inputs
+    }
+}
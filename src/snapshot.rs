@@ -0,0 +1,201 @@
+use crate::machine::STACK_SIZE;
+use crate::{Machine, State};
+
+// Bumped whenever the layout changes, so older states are refused rather than misread.
+const MAGIC: &[u8; 8] = b"RIP8SAV2";
+
+// Save states hold the emulated hardware only; keys, quirks, speed and attached tools are left alone.
+impl Machine {
+    pub fn save_state(&self) -> Vec<u8> {
+        let mut data = Vec::with_capacity(MAGIC.len() + self.memory.len() + self.screen.len() + 64);
+        data.extend_from_slice(MAGIC);
+        data.extend_from_slice(&self.memory);
+        data.extend_from_slice(&self.registers);
+        data.extend_from_slice(&(self.pc as u16).to_le_bytes());
+        data.extend_from_slice(&self.index_register.to_le_bytes());
+        data.extend_from_slice(&[self.delay_timer, self.sound_timer, self.frame_timer]);
+        data.extend_from_slice(&match self.state {
+            State::Running => [0, 0],
+            State::Halted => [1, 0],
+            State::WaitingForKey(x) => [2, x as u8],
+        });
+        // The stack is padded to its full size so every state is the same length, which libretro needs.
+        data.push(self.stack.len() as u8);
+        for n in 0..STACK_SIZE {
+            data.extend_from_slice(&self.stack.get(n).copied().unwrap_or(0).to_le_bytes());
+        }
+        data.extend(self.screen.iter().map(|pixel| *pixel as u8));
+        data.extend_from_slice(&self.frames.to_le_bytes());
+        data.extend_from_slice(&self.rng.to_le_bytes());
+        data
+    }
+
+    // Leaves the machine untouched and returns false if the data is not a valid save state.
+    pub fn load_state(&mut self, data: &[u8]) -> bool {
+        let mut reader = Reader { data };
+        match Self::read_state(&mut reader) {
+            Some(machine) if reader.data.is_empty() => {
+                machine.apply(self);
+                true
+            }
+            _ => false,
+        }
+    }
+
+    fn read_state(reader: &mut Reader) -> Option<Snapshot> {
+        if reader.take(MAGIC.len())? != MAGIC {
+            return None;
+        }
+        let memory: [u8; 4096] = reader.take(4096)?.try_into().ok()?;
+        let registers = reader.take(16)?.try_into().ok()?;
+        let pc = reader.u16()? as usize;
+        let index_register = reader.u16()?;
+        if pc >= memory.len() || index_register as usize >= memory.len() {
+            return None;
+        }
+        let timers = reader.take(3)?;
+        let (delay_timer, sound_timer, frame_timer) = (timers[0], timers[1], timers[2]);
+        let state = match reader.take(2)? {
+            [0, _] => State::Running,
+            [1, _] => State::Halted,
+            [2, x] if *x < 16 => State::WaitingForKey(*x as usize),
+            _ => return None,
+        };
+        let depth = reader.take(1)?[0] as usize;
+        if depth > STACK_SIZE {
+            return None;
+        }
+        let mut stack = (0..STACK_SIZE).map(|_| reader.u16()).collect::<Option<Vec<_>>>()?;
+        stack.truncate(depth);
+        // Return addresses point just past a call, which the debugger reads back.
+        if stack.iter().any(|address| *address as usize > memory.len() - 2) {
+            return None;
+        }
+        let mut screen = [false; 64 * 32];
+        for (pixel, value) in screen.iter_mut().zip(reader.take(64 * 32)?) {
+            *pixel = *value != 0;
+        }
+        let frames = reader.u64()?;
+        let rng = reader.u64()?;
+        if rng == 0 {
+            return None;
+        }
+        Some(Snapshot { memory, registers, pc, index_register, delay_timer, sound_timer, frame_timer, state, stack, screen, frames, rng })
+    }
+}
+
+struct Snapshot {
+    memory: [u8; 4096],
+    registers: [u8; 16],
+    pc: usize,
+    index_register: u16,
+    delay_timer: u8,
+    sound_timer: u8,
+    frame_timer: u8,
+    state: State,
+    stack: Vec<u16>,
+    screen: [bool; 64 * 32],
+    frames: u64,
+    rng: u64,
+}
+
+impl Snapshot {
+    fn apply(self, machine: &mut Machine) {
+        machine.memory = self.memory;
+        machine.registers = self.registers;
+        machine.pc = self.pc;
+        machine.index_register = self.index_register;
+        machine.delay_timer = self.delay_timer;
+        machine.sound_timer = self.sound_timer;
+        machine.frame_timer = self.frame_timer;
+        machine.state = self.state;
+        machine.stack = self.stack;
+        machine.screen = self.screen;
+        machine.frames = self.frames;
+        machine.rng = self.rng;
+        machine.draw_flag = true;
+    }
+}
+
+struct Reader<'a> {
+    data: &'a [u8],
+}
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, n: usize) -> Option<&'a [u8]> {
+        if self.data.len() < n {
+            return None;
+        }
+        let (head, tail) = self.data.split_at(n);
+        self.data = tail;
+        Some(head)
+    }
+
+    fn u16(&mut self) -> Option<u16> {
+        Some(u16::from_le_bytes(self.take(2)?.try_into().ok()?))
+    }
+
+    fn u64(&mut self) -> Option<u64> {
+        Some(u64::from_le_bytes(self.take(8)?.try_into().ok()?))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Offsets into a state, after the magic and memory.
+    const PC: usize = 8 + 4096 + 16;
+    const INDEX: usize = PC + 2;
+    const DEPTH: usize = INDEX + 2 + 3 + 2;
+
+    fn machine() -> Machine {
+        let mut machine = Machine::new();
+        // call 0x206; jump 0x202; ...; v1 := 7; i := 0x30A; ...
+        machine.load_program(&[0x22, 0x06, 0x12, 0x02, 0x00, 0x00, 0x61, 0x07, 0xA3, 0x0A, 0x12, 0x0A][..]);
+        for _ in 0..3 {
+            machine.cycle();
+        }
+        machine
+    }
+
+    #[test]
+    fn round_trip() {
+        let machine = machine();
+        let state = machine.save_state();
+        let mut loaded = Machine::new();
+        assert!(loaded.load_state(&state));
+        assert_eq!(loaded.save_state(), state);
+        assert_eq!(loaded.pc(), 0x20A);
+        assert_eq!(loaded.index_register(), 0x30A);
+        assert_eq!(loaded.registers()[1], 7);
+        assert_eq!(loaded.stack(), &[0x202]);
+    }
+
+    #[test]
+    fn length_does_not_depend_on_stack_depth() {
+        assert_eq!(machine().save_state().len(), Machine::new().save_state().len());
+    }
+
+    #[test]
+    fn rejects_out_of_range_addresses() {
+        let state = machine().save_state();
+        let corrupt = |offset: usize, bytes: &[u8]| {
+            let mut state = state.clone();
+            state[offset..offset + bytes.len()].copy_from_slice(bytes);
+            let mut machine = Machine::new();
+            let before = machine.save_state();
+            let loaded = machine.load_state(&state);
+            assert!(loaded || machine.save_state() == before);
+            loaded
+        };
+        assert!(corrupt(PC, &[0x0A, 0x02]));
+        assert!(!corrupt(PC, &[0x00, 0x10]));
+        assert!(!corrupt(INDEX, &[0x00, 0x10]));
+        assert!(!corrupt(DEPTH, &[STACK_SIZE as u8 + 1]));
+        assert!(!corrupt(DEPTH + 1, &[0xFF, 0x0F]));
+        assert!(corrupt(DEPTH + 1, &[0xFE, 0x0F]));
+        assert!(!corrupt(0, b"RIP8SAV1"));
+        assert!(!Machine::new().load_state(&state[..state.len() - 1]));
+    }
+}
@@ -0,0 +1,309 @@
+use std::io::{self, Stdout, Write};
+use std::time::{Duration, Instant};
+
+use crossterm::event::{self, Event, KeyCode, KeyEvent, KeyEventKind, KeyModifiers, KeyboardEnhancementFlags, PushKeyboardEnhancementFlags, PopKeyboardEnhancementFlags};
+use crossterm::style::{Color, Print, ResetColor, SetBackgroundColor, SetForegroundColor};
+use crossterm::{cursor, queue, terminal};
+
+use crate::debugger::Debugger;
+use crate::frontend::{Beeper, Display, Input, InputSource};
+use crate::palette::{Palette, Rgb};
+use crate::Machine;
+
+const WIDTH: usize = 64;
+const HEIGHT: usize = 32;
+// Terminals only report presses, so a key counts as held until no press or auto-repeat has arrived for
+// this long. That is a few frames for a tap; a held key is released during the keyboard's first-repeat
+// delay and pressed again once repeats start, as repeats then come well inside this.
+const RELEASE_AFTER: Duration = Duration::from_millis(100);
+
+const KEYS: [(char, usize); 16] = [
+    ('1', 0x1), ('2', 0x2), ('3', 0x3), ('4', 0xC),
+    ('q', 0x4), ('w', 0x5), ('e', 0x6), ('r', 0xD),
+    ('a', 0x7), ('s', 0x8), ('d', 0x9), ('f', 0xE),
+    ('z', 0xA), ('x', 0x0), ('c', 0xB), ('v', 0xF),
+];
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum Glyphs {
+    HalfBlock,
+    Braille,
+}
+
+impl Glyphs {
+    pub fn parse(text: &str) -> Option<Self> {
+        match text {
+            "half" => Some(Glyphs::HalfBlock),
+            "braille" => Some(Glyphs::Braille),
+            _ => None,
+        }
+    }
+
+    fn rows(self) -> usize {
+        match self {
+            Glyphs::HalfBlock => HEIGHT / 2,
+            Glyphs::Braille => HEIGHT / 4,
+        }
+    }
+}
+
+// Puts the terminal in raw mode on the alternate screen until dropped.
+pub struct TerminalDisplay {
+    out: Stdout,
+    glyphs: Glyphs,
+    enhanced: bool,
+}
+
+impl TerminalDisplay {
+    pub fn new(glyphs: Glyphs) -> io::Result<Self> {
+        let mut out = io::stdout();
+        terminal::enable_raw_mode()?;
+        queue!(out, terminal::EnterAlternateScreen, cursor::Hide, terminal::Clear(terminal::ClearType::All))?;
+        let enhanced = terminal::supports_keyboard_enhancement().unwrap_or(false);
+        if enhanced {
+            queue!(out, PushKeyboardEnhancementFlags(KeyboardEnhancementFlags::REPORT_EVENT_TYPES))?;
+        }
+        out.flush()?;
+        Ok(TerminalDisplay { out, glyphs, enhanced })
+    }
+
+    // Whether the terminal reports key releases, which makes release emulation unnecessary.
+    pub fn reports_releases(&self) -> bool {
+        self.enhanced
+    }
+
+    fn render(&mut self, intensity: &[f32; WIDTH * HEIGHT], palette: &Palette) -> io::Result<()> {
+        let color = |(r, g, b): Rgb| Color::Rgb { r, g, b };
+        for row in 0..self.glyphs.rows() {
+            queue!(self.out, cursor::MoveTo(0, row as u16))?;
+            let mut colors = None;
+            for x in 0..WIDTH / self.cell_width() {
+                let (foreground, background, glyph) = match self.glyphs {
+                    Glyphs::HalfBlock => {
+                        let top = palette.shade(intensity[row * 2 * WIDTH + x]);
+                        let bottom = palette.shade(intensity[(row * 2 + 1) * WIDTH + x]);
+                        (top, bottom, '▀')
+                    }
+                    Glyphs::Braille => (palette.colors[1], palette.background(), Self::braille(intensity, x * 2, row * 4)),
+                };
+                if colors != Some((foreground, background)) {
+                    queue!(self.out, SetForegroundColor(color(foreground)), SetBackgroundColor(color(background)))?;
+                    colors = Some((foreground, background));
+                }
+                queue!(self.out, Print(glyph))?;
+            }
+        }
+        queue!(self.out, ResetColor)?;
+        self.out.flush()
+    }
+
+    fn cell_width(&self) -> usize {
+        match self.glyphs {
+            Glyphs::HalfBlock => 1,
+            Glyphs::Braille => 2,
+        }
+    }
+
+    fn braille(intensity: &[f32; WIDTH * HEIGHT], left: usize, top: usize) -> char {
+        const DOTS: [[u32; 4]; 2] = [[0x01, 0x02, 0x04, 0x40], [0x08, 0x10, 0x20, 0x80]];
+        let mut bits = 0;
+        for (dx, column) in DOTS.iter().enumerate() {
+            for (dy, bit) in column.iter().enumerate() {
+                if intensity[(top + dy) * WIDTH + left + dx] >= 0.5 {
+                    bits |= bit;
+                }
+            }
+        }
+        char::from_u32(0x2800 + bits).expect("Invalid braille pattern")
+    }
+
+    // Prints the debugger panel below the screen.
+    fn render_status(&mut self, lines: &[String]) -> io::Result<()> {
+        let top = self.glyphs.rows() as u16 + 1;
+        for (i, line) in lines.iter().enumerate() {
+            queue!(self.out, cursor::MoveTo(0, top + i as u16), terminal::Clear(terminal::ClearType::UntilNewLine), Print(line))?;
+        }
+        queue!(self.out, cursor::MoveTo(0, top + lines.len() as u16), terminal::Clear(terminal::ClearType::FromCursorDown))?;
+        self.out.flush()
+    }
+}
+
+impl Display for TerminalDisplay {
+    fn draw(&mut self, intensity: &[f32; WIDTH * HEIGHT], palette: &Palette) {
+        self.render(intensity, palette).expect("Failed to draw");
+    }
+
+    fn draw_status(&mut self, debugger: &Debugger) {
+        self.render_status(&debugger.status()).expect("Failed to draw");
+    }
+}
+
+pub struct TerminalInput {
+    emulate_releases: bool,
+    // When each key was last pressed or repeated.
+    held: [Option<Instant>; 16],
+}
+
+impl TerminalInput {
+    pub fn new(emulate_releases: bool) -> Self {
+        TerminalInput {
+            emulate_releases,
+            held: [None; 16],
+        }
+    }
+
+    fn read(&mut self) -> io::Result<Vec<Input>> {
+        let mut inputs = Vec::new();
+        while event::poll(Duration::ZERO)? {
+            if let Event::Key(key) = event::read()? {
+                self.key(key, Instant::now(), &mut inputs);
+            }
+        }
+        if self.emulate_releases {
+            self.release_stale(Instant::now(), &mut inputs);
+        }
+        Ok(inputs)
+    }
+
+    fn key(&mut self, key: KeyEvent, now: Instant, inputs: &mut Vec<Input>) {
+        let pressed = key.kind != KeyEventKind::Release;
+        match key.code {
+            // Raw mode delivers Ctrl-C as a key rather than a signal.
+            KeyCode::Char('c') if pressed && key.modifiers.contains(KeyModifiers::CONTROL) => inputs.push(Input::Quit),
+            KeyCode::Esc if pressed => inputs.push(Input::Quit),
+            KeyCode::Char(' ') if pressed => inputs.push(Input::TogglePause),
+            KeyCode::Tab if pressed => inputs.push(Input::Step),
+            KeyCode::F(10) if pressed => inputs.push(Input::StepOver),
+            KeyCode::F(9) if pressed => inputs.push(Input::StepOut),
+            KeyCode::F(8) if pressed => inputs.push(Input::RunToCursor),
+            KeyCode::Up if pressed => inputs.push(Input::MoveCursor(-1)),
+            KeyCode::Down if pressed => inputs.push(Input::MoveCursor(1)),
+            KeyCode::Home if pressed => inputs.push(Input::ResetCursor),
+            KeyCode::F(1) if pressed => inputs.push(Input::NextFilter),
+            KeyCode::F(2) if pressed => inputs.push(Input::NextPalette),
+            KeyCode::Char(c) => {
+                if let Some((_, key)) = KEYS.iter().find(|(k, _)| *k == c.to_ascii_lowercase()) {
+                    if !pressed {
+                        self.held[*key] = None;
+                        inputs.push(Input::Release(*key));
+                    } else {
+                        if self.held[*key].is_none() {
+                            inputs.push(Input::Press(*key));
+                        }
+                        self.held[*key] = Some(now);
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+
+    fn release_stale(&mut self, now: Instant, inputs: &mut Vec<Input>) {
+        for (key, held) in self.held.iter_mut().enumerate() {
+            if held.is_some_and(|since| now.duration_since(since) > RELEASE_AFTER) {
+                *held = None;
+                inputs.push(Input::Release(key));
+            }
+        }
+    }
+}
+
+impl InputSource for TerminalInput {
+    fn poll(&mut self, _machine: &Machine) -> Vec<Input> {
+        self.read().expect("Failed to read terminal events")
+    }
+}
+
+// Rings the bell when a tone starts; terminals cannot hold a note.
+#[derive(Default)]
+pub struct TerminalBell {
+    sounding: bool,
+}
+
+impl Beeper for TerminalBell {
+    fn set_playing(&mut self, playing: bool) {
+        if playing && !self.sounding {
+            let mut out = io::stdout();
+            let _ = queue!(out, Print('\x07'));
+            let _ = out.flush();
+        }
+        self.sounding = playing;
+    }
+}
+
+impl Drop for TerminalDisplay {
+    fn drop(&mut self) {
+        if self.enhanced {
+            let _ = queue!(self.out, PopKeyboardEnhancementFlags);
+        }
+        let _ = queue!(self.out, ResetColor, cursor::Show, terminal::LeaveAlternateScreen);
+        let _ = self.out.flush();
+        let _ = terminal::disable_raw_mode();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn press(input: &mut TerminalInput, code: KeyCode, modifiers: KeyModifiers, now: Instant) -> Vec<Input> {
+        let mut inputs = Vec::new();
+        input.key(KeyEvent::new(code, modifiers), now, &mut inputs);
+        inputs
+    }
+
+    fn stale(input: &mut TerminalInput, now: Instant) -> Vec<Input> {
+        let mut inputs = Vec::new();
+        input.release_stale(now, &mut inputs);
+        inputs
+    }
+
+    #[test]
+    fn braille_dots_follow_the_unicode_layout() {
+        let dots = |pixels: &[(usize, usize)]| {
+            let mut intensity = [0.0; WIDTH * HEIGHT];
+            for (x, y) in pixels {
+                intensity[(4 + y) * WIDTH + 2 + x] = 1.0;
+            }
+            TerminalDisplay::braille(&intensity, 2, 4)
+        };
+        assert_eq!(dots(&[]), '\u{2800}');
+        assert_eq!(dots(&[(0, 0)]), '\u{2801}');
+        assert_eq!(dots(&[(0, 2)]), '\u{2804}');
+        assert_eq!(dots(&[(1, 0)]), '\u{2808}');
+        assert_eq!(dots(&[(0, 3)]), '\u{2840}');
+        assert_eq!(dots(&[(1, 3)]), '\u{2880}');
+        let all: Vec<(usize, usize)> = (0..2).flat_map(|x| (0..4).map(move |y| (x, y))).collect();
+        assert_eq!(dots(&all), '\u{28FF}');
+    }
+
+    #[test]
+    fn taps_are_released_after_a_few_frames() {
+        let mut input = TerminalInput::new(true);
+        let start = Instant::now();
+        assert_eq!(press(&mut input, KeyCode::Char('w'), KeyModifiers::NONE, start), [Input::Press(5)]);
+        assert!(stale(&mut input, start + Duration::from_millis(50)).is_empty());
+        assert_eq!(stale(&mut input, start + Duration::from_millis(150)), [Input::Release(5)]);
+    }
+
+    #[test]
+    fn repeats_keep_a_key_held() {
+        let mut input = TerminalInput::new(true);
+        let start = Instant::now();
+        press(&mut input, KeyCode::Char('W'), KeyModifiers::SHIFT, start);
+        for repeat in 1..30 {
+            let now = start + Duration::from_millis(33 * repeat);
+            assert!(press(&mut input, KeyCode::Char('w'), KeyModifiers::NONE, now).is_empty());
+            assert!(stale(&mut input, now).is_empty());
+        }
+        assert_eq!(stale(&mut input, start + Duration::from_millis(33 * 29 + 150)), [Input::Release(5)]);
+    }
+
+    #[test]
+    fn ctrl_c_quits() {
+        let mut input = TerminalInput::new(true);
+        let now = Instant::now();
+        assert_eq!(press(&mut input, KeyCode::Char('c'), KeyModifiers::CONTROL, now), [Input::Quit]);
+        assert_eq!(press(&mut input, KeyCode::Char('c'), KeyModifiers::NONE, now), [Input::Press(0xB)]);
+    }
+}
@@ -0,0 +1,297 @@
+use std::fs::File;
+use std::io::{self, BufReader, BufWriter, Read, Write};
+use std::ops::RangeInclusive;
+
+use crate::decompiler::Decompiler;
+use crate::OpCode;
+
+const MAGIC: &[u8; 8] = b"RIP8TRC1";
+const RECORD_SIZE: usize = 28;
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum TraceFormat {
+    Text,
+    Binary,
+}
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub(crate) struct TraceRecord {
+    pub frame: u32,
+    pub pc: u16,
+    pub opcode: OpCode,
+    pub registers: [u8; 16],
+    pub index_register: u16,
+    pub delay_timer: u8,
+    pub sound_timer: u8,
+}
+
+impl TraceRecord {
+    fn to_bytes(self) -> [u8; RECORD_SIZE] {
+        let mut bytes = [0; RECORD_SIZE];
+        bytes[0..4].copy_from_slice(&self.frame.to_le_bytes());
+        bytes[4..6].copy_from_slice(&self.pc.to_le_bytes());
+        bytes[6..8].copy_from_slice(&self.opcode.to_le_bytes());
+        bytes[8..24].copy_from_slice(&self.registers);
+        bytes[24..26].copy_from_slice(&self.index_register.to_le_bytes());
+        bytes[26] = self.delay_timer;
+        bytes[27] = self.sound_timer;
+        bytes
+    }
+
+    fn from_bytes(bytes: &[u8; RECORD_SIZE]) -> Self {
+        let mut registers = [0; 16];
+        registers.copy_from_slice(&bytes[8..24]);
+        TraceRecord {
+            frame: u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]),
+            pc: u16::from_le_bytes([bytes[4], bytes[5]]),
+            opcode: u16::from_le_bytes([bytes[6], bytes[7]]),
+            registers,
+            index_register: u16::from_le_bytes([bytes[24], bytes[25]]),
+            delay_timer: bytes[26],
+            sound_timer: bytes[27],
+        }
+    }
+
+    fn describe(&self) -> String {
+        let registers = self.registers.iter().map(|r| format!("{:02X}", r)).collect::<Vec<_>>().join(" ");
+        format!("frame={} pc=0x{:03X} op=0x{:04X} {} | V=[{}] I=0x{:03X} DT={:02X} ST={:02X}",
+                self.frame, self.pc, self.opcode, Decompiler::disassemble(self.opcode),
+                registers, self.index_register, self.delay_timer, self.sound_timer)
+    }
+}
+
+#[derive(Default)]
+pub struct TraceFilter {
+    pub addresses: Option<RangeInclusive<u16>>,
+    pub classes: Option<[bool; 16]>,
+    pub frames: Option<RangeInclusive<u32>>,
+}
+
+impl TraceFilter {
+    pub fn parse_addresses(&mut self, text: &str) -> Result<(), String> {
+        let (start, end) = text.split_once('-').ok_or("Address range must be START-END.")?;
+        let parse = |s: &str| u16::from_str_radix(s.trim_start_matches("0x"), 16).map_err(|_| format!("Malformed address {}.", s));
+        self.addresses = Some(parse(start)?..=parse(end)?);
+        Ok(())
+    }
+
+    pub fn parse_classes(&mut self, text: &str) -> Result<(), String> {
+        let mut classes = [false; 16];
+        for class in text.split(',') {
+            let class = usize::from_str_radix(class.trim(), 16).ok().filter(|c| *c < 16)
+                .ok_or_else(|| format!("Malformed opcode class {}.", class))?;
+            classes[class] = true;
+        }
+        self.classes = Some(classes);
+        Ok(())
+    }
+
+    pub fn parse_frames(&mut self, text: &str) -> Result<(), String> {
+        let (start, end) = text.split_once('-').ok_or("Frame range must be START-END.")?;
+        let parse = |s: &str| s.parse().map_err(|_| format!("Malformed frame {}.", s));
+        self.frames = Some(parse(start)?..=parse(end)?);
+        Ok(())
+    }
+
+    fn matches(&self, record: &TraceRecord) -> bool {
+        self.addresses.as_ref().is_none_or(|range| range.contains(&record.pc))
+            && self.classes.is_none_or(|classes| classes[(record.opcode >> 12) as usize])
+            && self.frames.as_ref().is_none_or(|range| range.contains(&record.frame))
+    }
+}
+
+pub struct Tracer {
+    out: BufWriter<File>,
+    format: TraceFormat,
+    filter: TraceFilter,
+    frame: u32,
+    // The first write that failed; tracing stops there and the error waits for finish().
+    error: Option<io::Error>,
+}
+
+impl Tracer {
+    pub fn new(path: &str, format: TraceFormat, filter: TraceFilter) -> io::Result<Self> {
+        let mut out = BufWriter::new(File::create(path)?);
+        if format == TraceFormat::Binary {
+            out.write_all(MAGIC)?;
+        }
+        Ok(Tracer { out, format, filter, frame: 0, error: None })
+    }
+
+    pub fn frame(&self) -> u32 {
+        self.frame
+    }
+
+    pub fn next_frame(&mut self) {
+        self.frame += 1;
+    }
+
+    pub(crate) fn record(&mut self, before: &[u8; 16], record: TraceRecord) {
+        if self.error.is_some() || !self.filter.matches(&record) {
+            return;
+        }
+        let result = match self.format {
+            TraceFormat::Binary => self.out.write_all(&record.to_bytes()),
+            TraceFormat::Text => {
+                let deltas = before.iter().zip(record.registers.iter()).enumerate()
+                    .filter(|(_, (a, b))| a != b)
+                    .map(|(i, (a, b))| format!("V{:X}:{:02X}->{:02X}", i, a, b))
+                    .collect::<Vec<_>>()
+                    .join(" ");
+                writeln!(self.out, "{:>6} 0x{:03X} 0x{:04X} {:<24} | {:<32} | I=0x{:03X} DT={:02X} ST={:02X}",
+                         record.frame, record.pc, record.opcode, Decompiler::disassemble(record.opcode),
+                         deltas, record.index_register, record.delay_timer, record.sound_timer)
+            }
+        };
+        if let Err(e) = result {
+            self.error = Some(e);
+        }
+    }
+
+    // Flushes the trace and reports the first error from the whole run.
+    pub fn finish(&mut self) -> io::Result<()> {
+        match self.error.take() {
+            Some(e) => Err(e),
+            None => self.out.flush(),
+        }
+    }
+}
+
+enum Trace {
+    Text(Vec<String>),
+    Binary(Vec<TraceRecord>),
+}
+
+fn load(path: &str) -> io::Result<Trace> {
+    let mut data = Vec::new();
+    BufReader::new(File::open(path)?).read_to_end(&mut data)?;
+    if data.starts_with(MAGIC) {
+        let records = data[MAGIC.len()..].chunks_exact(RECORD_SIZE)
+            .map(|chunk| TraceRecord::from_bytes(chunk.try_into().unwrap()))
+            .collect();
+        Ok(Trace::Binary(records))
+    } else {
+        Ok(Trace::Text(String::from_utf8_lossy(&data).lines().map(str::to_string).collect()))
+    }
+}
+
+pub fn diff(left: &str, right: &str) -> io::Result<bool> {
+    match (load(left)?, load(right)?) {
+        (Trace::Binary(a), Trace::Binary(b)) => {
+            for (i, (x, y)) in a.iter().zip(b.iter()).enumerate() {
+                if x != y {
+                    println!("First divergence at record {}:", i);
+                    println!("  < {}", x.describe());
+                    println!("  > {}", y.describe());
+                    if i > 0 {
+                        println!("  previous: {}", a[i - 1].describe());
+                    }
+                    return Ok(false);
+                }
+            }
+            report_length(a.len(), b.len())
+        }
+        (Trace::Text(a), Trace::Text(b)) => {
+            for (i, (x, y)) in a.iter().zip(b.iter()).enumerate() {
+                if x != y {
+                    println!("First divergence at line {}:", i + 1);
+                    println!("  < {}", x);
+                    println!("  > {}", y);
+                    return Ok(false);
+                }
+            }
+            report_length(a.len(), b.len())
+        }
+        _ => Err(io::Error::new(io::ErrorKind::InvalidData, "Cannot compare text and binary traces.")),
+    }
+}
+
+fn report_length(left: usize, right: usize) -> io::Result<bool> {
+    if left == right {
+        println!("Traces are identical ({} entries).", left);
+        Ok(true)
+    } else {
+        println!("Traces match for {} entries, then one ends (left {}, right {}).", left.min(right), left, right);
+        Ok(false)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::env;
+    use std::fs;
+    use crate::Machine;
+
+    fn temp(name: &str) -> String {
+        env::temp_dir().join(format!("rip8-trace-test-{}-{}", std::process::id(), name)).to_string_lossy().into_owned()
+    }
+
+    fn record(frame: u32, pc: u16, opcode: OpCode) -> TraceRecord {
+        TraceRecord { frame, pc, opcode, registers: [0; 16], index_register: 0, delay_timer: 0, sound_timer: 0 }
+    }
+
+    // v0 += 1; jump 0x200, traced for 20 cycles.
+    fn trace(name: &str, format: TraceFormat, filter: TraceFilter, step: u8) -> String {
+        let path = temp(name);
+        let mut machine = Machine::new();
+        machine.load_program(&[0x70, step, 0x12, 0x00][..]);
+        machine.set_tracer(Tracer::new(&path, format, filter).unwrap());
+        for _ in 0..20 {
+            machine.cycle();
+        }
+        machine.tracer_mut().unwrap().finish().unwrap();
+        path
+    }
+
+    #[test]
+    fn filters_by_address_class_and_frame() {
+        let mut filter = TraceFilter::default();
+        filter.parse_addresses("0x200-0x20F").unwrap();
+        filter.parse_classes("1, 7").unwrap();
+        filter.parse_frames("2-3").unwrap();
+        assert!(filter.matches(&record(2, 0x200, 0x7001)));
+        assert!(filter.matches(&record(3, 0x20F, 0x1200)));
+        assert!(!filter.matches(&record(1, 0x200, 0x7001)));
+        assert!(!filter.matches(&record(2, 0x210, 0x7001)));
+        assert!(!filter.matches(&record(2, 0x200, 0x6001)));
+        let mut filter = TraceFilter::default();
+        assert!(filter.parse_addresses("0x200").is_err());
+        assert!(filter.parse_addresses("0x200-0xZZ").is_err());
+        assert!(filter.parse_classes("10").is_err());
+        assert!(filter.parse_frames("1-x").is_err());
+    }
+
+    #[test]
+    fn diffs_traces() {
+        for (format, name) in [(TraceFormat::Text, "text"), (TraceFormat::Binary, "binary")] {
+            let left = trace(&format!("{}-left", name), format, TraceFilter::default(), 1);
+            let same = trace(&format!("{}-same", name), format, TraceFilter::default(), 1);
+            let other = trace(&format!("{}-other", name), format, TraceFilter::default(), 2);
+            let mut filter = TraceFilter::default();
+            filter.parse_classes("7").unwrap();
+            let shorter = trace(&format!("{}-shorter", name), format, filter, 1);
+            assert!(diff(&left, &same).unwrap());
+            assert!(!diff(&left, &other).unwrap());
+            assert!(!diff(&left, &shorter).unwrap());
+            for path in [left, same, other, shorter] {
+                fs::remove_file(path).unwrap();
+            }
+        }
+        let text = trace("mixed-text", TraceFormat::Text, TraceFilter::default(), 1);
+        let binary = trace("mixed-binary", TraceFormat::Binary, TraceFilter::default(), 1);
+        assert!(diff(&text, &binary).is_err());
+        fs::remove_file(text).unwrap();
+        fs::remove_file(binary).unwrap();
+    }
+
+    #[test]
+    #[cfg(target_os = "linux")]
+    fn write_errors_wait_for_finish() {
+        let mut tracer = Tracer::new("/dev/full", TraceFormat::Text, TraceFilter::default()).unwrap();
+        for pc in 0..1000 {
+            tracer.record(&[0; 16], record(0, pc, 0x7001));
+        }
+        assert!(tracer.finish().is_err());
+    }
+}
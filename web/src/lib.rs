@@ -0,0 +1,127 @@
+use wasm_bindgen::prelude::*;
+
+use rip_8::palette::Palette;
+use rip_8::quirks::Quirks;
+use rip_8::Machine;
+
+pub const WIDTH: usize = 64;
+pub const HEIGHT: usize = 32;
+
+// Thin wrapper for JavaScript: the page owns timing, input events and drawing.
+#[wasm_bindgen]
+pub struct Emulator {
+    machine: Machine,
+    rom: Vec<u8>,
+    seed: u64,
+    palette: Palette,
+}
+
+#[wasm_bindgen]
+impl Emulator {
+    #[wasm_bindgen(constructor)]
+    pub fn new(seed: u64) -> Emulator {
+        Emulator {
+            machine: Machine::new(),
+            rom: Vec::new(),
+            seed,
+            palette: Palette::builtin().remove(0),
+        }
+    }
+
+    pub fn load(&mut self, rom: &[u8]) {
+        self.rom = rom.to_vec();
+        self.reset();
+    }
+
+    pub fn reset(&mut self) {
+        let (quirks, speed) = (self.machine.quirks(), self.machine.speed());
+        self.machine = Machine::new();
+        self.machine.seed(self.seed);
+        self.machine.set_quirks(quirks);
+        self.machine.set_speed(speed);
+        self.machine.load_program(&self.rom[..]);
+    }
+
+    pub fn set_quirks(&mut self, profile: &str) -> bool {
+        match Quirks::profile(profile) {
+            Some(quirks) => {
+                self.machine.set_quirks(quirks);
+                true
+            }
+            None => false,
+        }
+    }
+
+    pub fn set_speed(&mut self, cycles_per_frame: u8) {
+        self.machine.set_speed(cycles_per_frame.max(1));
+    }
+
+    pub fn set_palette(&mut self, name: &str) -> bool {
+        match Palette::builtin().into_iter().find(|p| p.name == name) {
+            Some(palette) => {
+                self.palette = palette;
+                true
+            }
+            None => false,
+        }
+    }
+
+    // Runs one 60 Hz frame's worth of instructions.
+    pub fn step_frame(&mut self) {
+        for _ in 0..self.machine.speed() {
+            self.machine.cycle();
+        }
+    }
+
+    pub fn key_down(&mut self, key: usize) {
+        if key < 16 {
+            self.machine.key_pressed(key);
+        }
+    }
+
+    pub fn key_up(&mut self, key: usize) {
+        if key < 16 {
+            self.machine.key_released(key);
+        }
+    }
+
+    pub fn sound_playing(&self) -> bool {
+        self.machine.sound_playing()
+    }
+
+    pub fn halted(&self) -> bool {
+        self.machine.halted()
+    }
+
+    pub fn width(&self) -> usize {
+        WIDTH
+    }
+
+    pub fn height(&self) -> usize {
+        HEIGHT
+    }
+
+    // One byte per pixel, 1 for lit.
+    pub fn framebuffer(&self) -> Vec<u8> {
+        self.machine.screen().iter().map(|pixel| *pixel as u8).collect()
+    }
+
+    // RGBA bytes in the current palette, ready for an ImageData.
+    pub fn framebuffer_rgba(&self) -> Vec<u8> {
+        let (off, on) = (self.palette.colors[0], self.palette.colors[1]);
+        self.machine.screen().iter()
+            .flat_map(|pixel| {
+                let (r, g, b) = if *pixel { on } else { off };
+                [r, g, b, 0xFF]
+            })
+            .collect()
+    }
+
+    pub fn save_state(&self) -> Vec<u8> {
+        self.machine.save_state()
+    }
+
+    pub fn load_state(&mut self, data: &[u8]) -> bool {
+        self.machine.load_state(data)
+    }
+}
@@ -0,0 +1,72 @@
+#![cfg(target_arch = "wasm32")]
+
+use rip_8_web::{Emulator, HEIGHT, WIDTH};
+use wasm_bindgen_test::*;
+
+// 00E0, A20C, 6000, 6100, D015, 120A, then the sprite for 0.
+const ROM: [u8; 17] = [0x00, 0xE0, 0xA2, 0x0C, 0x60, 0x00, 0x61, 0x00, 0xD0, 0x15, 0x12, 0x0A, 0xF0, 0x90, 0x90, 0x90, 0xF0];
+
+#[wasm_bindgen_test]
+fn draws_sprite() {
+    let mut emulator = Emulator::new(1);
+    emulator.load(&ROM);
+    emulator.step_frame();
+    emulator.step_frame();
+    let screen = emulator.framebuffer();
+    assert_eq!(screen.len(), WIDTH * HEIGHT);
+    assert_eq!(&screen[..4], &[1, 1, 1, 1]);
+    assert_eq!(&screen[WIDTH..WIDTH + 4], &[1, 0, 0, 1]);
+    assert_eq!(emulator.framebuffer_rgba().len(), WIDTH * HEIGHT * 4);
+}
+
+#[wasm_bindgen_test]
+fn save_state_round_trips() {
+    let mut emulator = Emulator::new(1);
+    emulator.load(&ROM);
+    emulator.step_frame();
+    let state = emulator.save_state();
+    emulator.step_frame();
+    assert!(emulator.load_state(&state));
+    assert_eq!(emulator.save_state(), state);
+    assert!(!emulator.load_state(&state[1..]));
+}
+
+#[wasm_bindgen_test]
+fn ignores_out_of_range_keys() {
+    // F00A, F029, D115, 1206: wait for a key, then draw its hex digit.
+    let mut emulator = Emulator::new(1);
+    emulator.load(&[0xF0, 0x0A, 0xF0, 0x29, 0xD1, 0x15, 0x12, 0x06]);
+    emulator.key_down(16);
+    emulator.key_down(usize::MAX);
+    emulator.step_frame();
+    emulator.step_frame();
+    assert!(emulator.framebuffer().iter().all(|pixel| *pixel == 0));
+
+    emulator.key_down(5);
+    emulator.step_frame();
+    emulator.step_frame();
+    let screen = emulator.framebuffer();
+    assert_eq!(&screen[WIDTH..WIDTH + 4], &[1, 0, 0, 0]);
+}
+
+#[wasm_bindgen_test]
+fn set_quirks_picks_a_profile() {
+    // 6005, 6108, 8016, F029, 6200, D225, 120C: v0 >>= 1 (or v0 := v1 >> 1), then draw its digit.
+    let rom = [0x60, 0x05, 0x61, 0x08, 0x80, 0x16, 0xF0, 0x29, 0x62, 0x00, 0xD2, 0x25, 0x12, 0x0C];
+    let mut emulator = Emulator::new(1);
+    assert!(emulator.set_quirks("cosmac"));
+    emulator.load(&rom);
+    emulator.step_frame();
+    emulator.step_frame();
+    // 4 = 8 >> 1, shifted from VY.
+    assert_eq!(&emulator.framebuffer()[..4], &[1, 0, 0, 1]);
+
+    assert!(emulator.set_quirks("schip"));
+    emulator.reset();
+    emulator.step_frame();
+    emulator.step_frame();
+    // 2 = 5 >> 1, shifted in place.
+    assert_eq!(&emulator.framebuffer()[..4], &[1, 1, 1, 1]);
+
+    assert!(!emulator.set_quirks("unknown"));
+}